@@ -0,0 +1,994 @@
+use super::opcode::Instruction;
+use super::variant::Platform;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// How deep includes can nest before the assembler assumes a file includes itself
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// How deep constants can refer to other constants before the assembler assumes one
+/// refers to itself
+const MAX_CONSTANT_DEPTH: usize = 64;
+
+/// An error in the source, `line` and `column` count from 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    /// The file the error is in, `None` for source that didn't come from a file
+    pub file: Option<PathBuf>,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file.display())?;
+        }
+
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// Assembles source written in the mnemonics of Cowgod's CHIP-8 technical reference into
+/// a ROM that loads at the platform's load address. Source that isn't in a file can't use
+/// `include`, see [`assemble_file`].
+///
+/// ```text
+/// SPEED equ 2
+///
+/// main:
+///     LD I, sprite
+///     DRW V0, V1, sprite_end - sprite
+///     ADD V0, SPEED          ; move right
+///     JP main
+///
+/// sprite:
+///     db 0b11110000, 0x90, 0x90, 0xF0
+/// sprite_end:
+/// ```
+///
+/// Besides instructions every line can hold a `label:`, a `NAME equ <expr>` constant, `db`
+/// with bytes or strings, `dw` with big-endian words or `include "file"`. Expressions are
+/// numbers (decimal, `0x` hex or `0b` binary), labels and constants combined with
+/// `+ - * / % & | ^ << >> ~` and parentheses. Everything after `;` is a comment. The
+/// register names and `I`, `DT`, `ST`, `K`, `F`, `HF`, `B`, `R` and `LONG` are reserved.
+pub fn assemble(source: &str, platform: Platform) -> Result<Vec<u8>, AsmError> {
+    let mut assembler = Assembler::new(platform);
+    assembler.parse(source, None, 0)?;
+    assembler.emit()
+}
+
+/// Assembles the file at `path` like [`assemble`], resolving includes relative to the
+/// file that includes them
+pub fn assemble_file(path: &Path, platform: Platform) -> Result<Vec<u8>, AsmError> {
+    let source = std::fs::read_to_string(path).map_err(|err| AsmError {
+        file: Some(path.to_path_buf()),
+        line: 0,
+        column: 0,
+        message: err.to_string(),
+    })?;
+
+    let mut assembler = Assembler::new(platform);
+    assembler.parse(&source, Some(path), 0)?;
+    assembler.emit()
+}
+
+/// An error on the line being parsed, the column and the message
+type ParseError = (usize, String);
+
+/// Where a token or statement is, `file` indexes [`Assembler::files`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Location {
+    file: usize,
+    line: usize,
+    column: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(i64),
+    Str(Vec<u8>),
+    Comma,
+    Colon,
+    LeftBracket,
+    RightBracket,
+    LeftParen,
+    RightParen,
+    Op(&'static str),
+}
+
+/// Every instruction mnemonic
+const MNEMONICS: [&str; 36] = [
+    "CLS", "RET", "SYS", "SCD", "SCU", "SCR", "SCL", "EXIT", "LOW", "HIGH", "BGC", "JP", "CALL",
+    "SE", "SNE", "SAVE", "LOAD", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SUBN", "SHR", "SHL",
+    "COL", "RND", "DRW", "SKP", "SKNP", "SKP2", "SKNP2", "PLANE", "AUDIO", "PITCH",
+];
+
+/// Names that can't be used for labels and constants, besides the registers
+const RESERVED: [&str; 9] = ["i", "dt", "st", "k", "f", "hf", "b", "r", "long"];
+
+const OPS: [&str; 12] = ["<<", ">>", "+", "-", "*", "/", "%", "&", "|", "^", "~", "="];
+
+/// Splits a line into tokens and the columns they start at
+fn tokenize(line: &str) -> Result<Vec<(Token, usize)>, ParseError> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let column = i + 1;
+
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        if c == ';' {
+            break;
+        }
+
+        let token = if c.is_ascii_alphabetic() || c == '_' || c == '.' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || "_.".contains(chars[i])) {
+                i += 1;
+            }
+            tokens.push((Token::Ident(chars[start..i].iter().collect()), column));
+            continue;
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && chars[i].is_ascii_alphanumeric() {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            let lower = text.to_ascii_lowercase();
+
+            let value = if let Some(hex) = lower.strip_prefix("0x") {
+                i64::from_str_radix(hex, 16)
+            } else if let Some(binary) = lower.strip_prefix("0b") {
+                i64::from_str_radix(binary, 2)
+            } else {
+                lower.parse()
+            };
+            let value = value.map_err(|_| (column, format!("invalid number '{text}'")))?;
+
+            tokens.push((Token::Number(value), column));
+            continue;
+        } else if c == '"' {
+            let mut bytes = Vec::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => return Err((column, "unterminated string".to_string())),
+                    Some('"') => break,
+                    Some('\\') => {
+                        bytes.push(match chars.get(i + 1) {
+                            Some('n') => b'\n',
+                            Some('0') => 0,
+                            Some('\\') => b'\\',
+                            Some('"') => b'"',
+                            _ => return Err((i + 1, "unknown escape".to_string())),
+                        });
+                        i += 1;
+                    }
+                    Some(&c) if c.is_ascii() => bytes.push(c as u8),
+                    Some(_) => return Err((i + 1, "strings can only hold ASCII".to_string())),
+                }
+                i += 1;
+            }
+            Token::Str(bytes)
+        } else {
+            match c {
+                ',' => Token::Comma,
+                ':' => Token::Colon,
+                '[' => Token::LeftBracket,
+                ']' => Token::RightBracket,
+                '(' => Token::LeftParen,
+                ')' => Token::RightParen,
+                _ => {
+                    let rest: String = chars[i..].iter().take(2).collect();
+                    let op = OPS
+                        .iter()
+                        .find(|op| rest.starts_with(**op))
+                        .ok_or((column, format!("unexpected '{c}'")))?;
+                    i += op.len() - 1;
+                    Token::Op(op)
+                }
+            }
+        };
+
+        tokens.push((token, column));
+        i += 1;
+    }
+
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Number(i64),
+    Symbol(String),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+}
+
+/// An expression and the column it starts at
+#[derive(Debug, Clone, PartialEq)]
+struct Expr {
+    node: Node,
+    column: usize,
+}
+
+/// How tightly binary operators bind, higher binds tighter
+fn precedence(op: &str) -> Option<u8> {
+    match op {
+        "|" => Some(1),
+        "^" => Some(2),
+        "&" => Some(3),
+        "<<" | ">>" => Some(4),
+        "+" | "-" => Some(5),
+        "*" | "/" | "%" => Some(6),
+        _ => None,
+    }
+}
+
+/// A statement operand
+#[derive(Debug, Clone, PartialEq)]
+enum Operand {
+    V(u8),
+    I,
+    /// `[I]`, the memory I points to
+    IndirectI,
+    Dt,
+    St,
+    K,
+    F,
+    Hf,
+    B,
+    R,
+    Long(Expr),
+    Value(Expr),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Statement {
+    Instruction {
+        mnemonic: String,
+        operands: Vec<Operand>,
+    },
+    Bytes(Vec<DataItem>),
+    Words(Vec<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum DataItem {
+    Str(Vec<u8>),
+    Expr(Expr),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Symbol {
+    Label(u16),
+    Constant(Expr, Location),
+}
+
+struct Assembler {
+    platform: Platform,
+
+    /// Every file read so far, `None` for source that didn't come from a file
+    files: Vec<Option<PathBuf>>,
+
+    symbols: HashMap<String, Symbol>,
+
+    /// Every statement in the order they're assembled
+    statements: Vec<(Location, Statement)>,
+
+    /// The address of the next statement
+    addr: usize,
+}
+
+/// Reads tokens of a single line
+struct Parser<'a> {
+    tokens: &'a [(Token, usize)],
+    pos: usize,
+
+    /// The column just past the line, for errors about missing tokens
+    end: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn column(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .map_or(self.end, |&(_, column)| column)
+    }
+
+    fn next(&mut self) -> Option<&Token> {
+        self.pos += 1;
+        self.tokens.get(self.pos - 1).map(|(token, _)| token)
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.tokens.len()
+    }
+
+    fn expect_end(&self) -> Result<(), ParseError> {
+        if self.at_end() {
+            Ok(())
+        } else {
+            Err((self.column(), "expected the end of the line".to_string()))
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, ParseError> {
+        self.binary(1)
+    }
+
+    /// Parses binary operators that bind at least as tightly as `min`
+    fn binary(&mut self, min: u8) -> Result<Expr, ParseError> {
+        let mut left = self.unary()?;
+
+        while let Some(&Token::Op(op)) = self.peek() {
+            let Some(prec) = precedence(op).filter(|&prec| prec >= min) else {
+                break;
+            };
+            self.next();
+
+            let right = self.binary(prec + 1)?;
+            let column = left.column;
+            left = Expr {
+                node: Node::Binary(op, Box::new(left), Box::new(right)),
+                column,
+            };
+        }
+
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        let column = self.column();
+
+        let node = match self.next().cloned() {
+            Some(Token::Number(value)) => Node::Number(value),
+            Some(Token::Ident(name)) => Node::Symbol(name),
+            Some(Token::Op(op @ ("-" | "~" | "+"))) => Node::Unary(op, Box::new(self.unary()?)),
+            Some(Token::LeftParen) => {
+                let expr = self.expr()?;
+                if self.next() != Some(&Token::RightParen) {
+                    return Err((self.column(), "expected ')'".to_string()));
+                }
+                return Ok(Expr { column, ..expr });
+            }
+            _ => return Err((column, "expected an expression".to_string())),
+        };
+
+        Ok(Expr { node, column })
+    }
+
+    fn operand(&mut self) -> Result<Operand, ParseError> {
+        if let Some(Token::Ident(name)) = self.peek() {
+            let name = name.to_ascii_lowercase();
+
+            let keyword = match name.as_str() {
+                "i" => Some(Operand::I),
+                "dt" => Some(Operand::Dt),
+                "st" => Some(Operand::St),
+                "k" => Some(Operand::K),
+                "f" => Some(Operand::F),
+                "hf" => Some(Operand::Hf),
+                "b" => Some(Operand::B),
+                "r" => Some(Operand::R),
+                _ => register(&name).map(Operand::V),
+            };
+            if let Some(keyword) = keyword {
+                self.next();
+                return Ok(keyword);
+            }
+
+            if name == "long" {
+                self.next();
+                return Ok(Operand::Long(self.expr()?));
+            }
+        }
+
+        if self.peek() == Some(&Token::LeftBracket) {
+            let column = self.column();
+            self.next();
+            if !matches!(self.next(), Some(Token::Ident(name)) if name.eq_ignore_ascii_case("i"))
+                || self.next() != Some(&Token::RightBracket)
+            {
+                return Err((column, "expected [I]".to_string()));
+            }
+            return Ok(Operand::IndirectI);
+        }
+
+        Ok(Operand::Value(self.expr()?))
+    }
+
+    /// Parses a comma separated list of things
+    fn list<T>(
+        &mut self,
+        mut item: impl FnMut(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<Vec<T>, ParseError> {
+        let mut items = Vec::new();
+        if self.at_end() {
+            return Ok(items);
+        }
+
+        loop {
+            items.push(item(self)?);
+            match self.next() {
+                None => return Ok(items),
+                Some(Token::Comma) => {}
+                Some(_) => {
+                    self.pos -= 1;
+                    return Err((self.column(), "expected ','".to_string()));
+                }
+            }
+        }
+    }
+}
+
+/// The register index of `v0` to `vf`
+fn register(name: &str) -> Option<u8> {
+    let digit = name.strip_prefix(['v', 'V'])?;
+
+    match digit.len() {
+        1 => u8::from_str_radix(digit, 16).ok(),
+        _ => None,
+    }
+}
+
+impl Assembler {
+    fn new(platform: Platform) -> Self {
+        Self {
+            platform,
+            files: Vec::new(),
+            symbols: HashMap::new(),
+            statements: Vec::new(),
+            addr: platform.load_addr() as usize,
+        }
+    }
+
+    fn error(&self, location: Location, message: impl Into<String>) -> AsmError {
+        AsmError {
+            file: self.files[location.file].clone(),
+            line: location.line,
+            column: location.column,
+            message: message.into(),
+        }
+    }
+
+    /// The first pass, which reads the source, defines every label and constant and lays
+    /// out the statements
+    fn parse(&mut self, source: &str, path: Option<&Path>, depth: usize) -> Result<(), AsmError> {
+        let file = self.files.len();
+        self.files.push(path.map(Path::to_path_buf));
+
+        for (i, line) in source.lines().enumerate() {
+            let location = |column| Location {
+                file,
+                line: i + 1,
+                column,
+            };
+
+            let tokens = tokenize(line)
+                .map_err(|(column, message)| self.error(location(column), message))?;
+            let mut parser = Parser {
+                tokens: &tokens,
+                pos: 0,
+                end: line.chars().count() + 1,
+            };
+
+            let include = self
+                .parse_line(&mut parser, &location)
+                .map_err(|(column, message)| self.error(location(column), message))?;
+
+            if let Some((file, column)) = include {
+                self.include(&file, path, depth, location(column))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Parses one line, returning the file and column of an `include` for the caller to
+    /// read
+    fn parse_line(
+        &mut self,
+        parser: &mut Parser,
+        location: &dyn Fn(usize) -> Location,
+    ) -> Result<Option<(Vec<u8>, usize)>, ParseError> {
+        if parser.at_end() {
+            return Ok(None);
+        }
+
+        let column = parser.column();
+        let Some(Token::Ident(name)) = parser.next().cloned() else {
+            return Err((column, "expected a label or instruction".to_string()));
+        };
+
+        // `name:` defines a label, which can be followed by a statement
+        if parser.peek() == Some(&Token::Colon) {
+            parser.next();
+            self.define(&name, Symbol::Label(self.label_addr(column)?), column)?;
+
+            return self.parse_line(parser, location);
+        }
+
+        // `NAME equ <expr>` or `NAME = <expr>` defines a constant
+        let is_constant = match parser.peek() {
+            Some(Token::Ident(word)) => word.eq_ignore_ascii_case("equ"),
+            Some(Token::Op("=")) => true,
+            _ => false,
+        };
+        if is_constant {
+            parser.next();
+            let expr = parser.expr()?;
+            parser.expect_end()?;
+            self.define(&name, Symbol::Constant(expr, location(column)), column)?;
+            return Ok(None);
+        }
+
+        let statement = match name.to_ascii_lowercase().as_str() {
+            "db" => Statement::Bytes(parser.list(|parser| match parser.peek() {
+                Some(Token::Str(bytes)) => {
+                    let bytes = bytes.clone();
+                    parser.next();
+                    Ok(DataItem::Str(bytes))
+                }
+                _ => Ok(DataItem::Expr(parser.expr()?)),
+            })?),
+            "dw" => Statement::Words(parser.list(Parser::expr)?),
+            "include" => {
+                let include_column = parser.column();
+                let Some(Token::Str(file)) = parser.next().cloned() else {
+                    return Err((include_column, "expected a file name in quotes".to_string()));
+                };
+                parser.expect_end()?;
+
+                return Ok(Some((file, include_column)));
+            }
+            _ => {
+                let operands = parser.list(Parser::operand)?;
+                Statement::Instruction {
+                    mnemonic: name.to_ascii_uppercase(),
+                    operands,
+                }
+            }
+        };
+
+        let size = match &statement {
+            Statement::Instruction { operands, .. } => {
+                if operands
+                    .iter()
+                    .any(|operand| matches!(operand, Operand::Long(_)))
+                {
+                    4
+                } else {
+                    2
+                }
+            }
+            Statement::Bytes(items) => items
+                .iter()
+                .map(|item| match item {
+                    DataItem::Str(bytes) => bytes.len(),
+                    DataItem::Expr(_) => 1,
+                })
+                .sum(),
+            Statement::Words(words) => 2 * words.len(),
+        };
+
+        self.statements.push((location(column), statement));
+        self.addr += size;
+
+        Ok(None)
+    }
+
+    fn label_addr(&self, column: usize) -> Result<u16, ParseError> {
+        u16::try_from(self.addr).map_err(|_| (column, "the program is too large".to_string()))
+    }
+
+    fn define(&mut self, name: &str, symbol: Symbol, column: usize) -> Result<(), ParseError> {
+        if register(name).is_some() || RESERVED.contains(&name.to_ascii_lowercase().as_str()) {
+            return Err((column, format!("'{name}' is reserved")));
+        }
+        if self.symbols.contains_key(name) {
+            return Err((column, format!("'{name}' is already defined")));
+        }
+
+        self.symbols.insert(name.to_string(), symbol);
+        Ok(())
+    }
+
+    /// Reads `file` relative to `path`, the file that includes it
+    fn include(
+        &mut self,
+        file: &[u8],
+        path: Option<&Path>,
+        depth: usize,
+        location: Location,
+    ) -> Result<(), AsmError> {
+        let Some(path) = path else {
+            return Err(self.error(location, "include only works in files"));
+        };
+        if depth >= MAX_INCLUDE_DEPTH {
+            return Err(self.error(location, "includes nest too deep"));
+        }
+
+        let included = path
+            .parent()
+            .unwrap_or(Path::new(""))
+            .join(String::from_utf8_lossy(file).as_ref());
+        let source = std::fs::read_to_string(&included).map_err(|err| {
+            self.error(
+                location,
+                format!("could not read {}: {err}", included.display()),
+            )
+        })?;
+
+        self.parse(&source, Some(&included), depth + 1)
+    }
+
+    /// Evaluates `expr`, looking up labels and constants
+    fn eval(&self, expr: &Expr, location: Location, depth: usize) -> Result<i64, AsmError> {
+        let at = |column| Location { column, ..location };
+
+        Ok(match &expr.node {
+            Node::Number(value) => *value,
+            Node::Symbol(name) => match self.symbols.get(name) {
+                Some(Symbol::Label(addr)) => *addr as i64,
+                Some(Symbol::Constant(expr, location)) => {
+                    if depth >= MAX_CONSTANT_DEPTH {
+                        return Err(self.error(*location, format!("'{name}' refers to itself")));
+                    }
+                    self.eval(expr, *location, depth + 1)?
+                }
+                None => return Err(self.error(at(expr.column), format!("unknown name '{name}'"))),
+            },
+            Node::Unary(op, operand) => {
+                let operand = self.eval(operand, location, depth)?;
+                match *op {
+                    "-" => operand.wrapping_neg(),
+                    "~" => !operand,
+                    _ => operand,
+                }
+            }
+            Node::Binary(op, left, right) => {
+                let (left, right_value) = (
+                    self.eval(left, location, depth)?,
+                    self.eval(right, location, depth)?,
+                );
+                let divisor = || match right_value {
+                    0 => Err(self.error(at(right.column), "division by zero")),
+                    divisor => Ok(divisor),
+                };
+
+                match *op {
+                    "+" => left.wrapping_add(right_value),
+                    "-" => left.wrapping_sub(right_value),
+                    "*" => left.wrapping_mul(right_value),
+                    "/" => left.wrapping_div(divisor()?),
+                    "%" => left.wrapping_rem(divisor()?),
+                    "&" => left & right_value,
+                    "|" => left | right_value,
+                    "^" => left ^ right_value,
+                    "<<" => left.wrapping_shl(right_value as u32),
+                    _ => left.wrapping_shr(right_value as u32),
+                }
+            }
+        })
+    }
+
+    /// Evaluates `expr` and checks it's between `min` and `max`
+    fn value(
+        &self,
+        expr: &Expr,
+        location: Location,
+        min: i64,
+        max: i64,
+        what: &str,
+    ) -> Result<i64, AsmError> {
+        let at = Location {
+            column: expr.column,
+            ..location
+        };
+        let value = self.eval(expr, at, 0)?;
+
+        if !(min..=max).contains(&value) {
+            return Err(self.error(at, format!("{what} {value} is out of range")));
+        }
+
+        Ok(value)
+    }
+
+    /// The second pass, which evaluates every expression and writes the ROM
+    fn emit(&self) -> Result<Vec<u8>, AsmError> {
+        let mut rom = Vec::with_capacity(self.addr - self.platform.load_addr() as usize);
+
+        for (location, statement) in &self.statements {
+            match statement {
+                Statement::Instruction { mnemonic, operands } => {
+                    let instruction = self.instruction(mnemonic, operands, *location)?;
+
+                    if !self.platform.supports(&instruction) {
+                        let extension = if instruction.is_chip8x() {
+                            "CHIP-8X"
+                        } else if instruction.is_xochip() {
+                            "XO-CHIP"
+                        } else {
+                            "SUPER-CHIP"
+                        };
+                        return Err(self.error(*location, format!("{mnemonic} needs {extension}")));
+                    }
+
+                    rom.extend_from_slice(&instruction.to_bytes());
+                }
+                Statement::Bytes(items) => {
+                    for item in items {
+                        match item {
+                            DataItem::Str(bytes) => rom.extend_from_slice(bytes),
+                            DataItem::Expr(expr) => {
+                                let byte = self.value(expr, *location, -0x80, 0xFF, "byte")?;
+                                rom.push(byte as u8);
+                            }
+                        }
+                    }
+                }
+                Statement::Words(words) => {
+                    for expr in words {
+                        let word = self.value(expr, *location, -0x8000, 0xFFFF, "word")?;
+                        rom.extend_from_slice(&(word as u16).to_be_bytes());
+                    }
+                }
+            }
+        }
+
+        Ok(rom)
+    }
+
+    fn instruction(
+        &self,
+        mnemonic: &str,
+        operands: &[Operand],
+        location: Location,
+    ) -> Result<Instruction, AsmError> {
+        let addr = |expr| {
+            self.value(expr, location, 0, 0xFFF, "address")
+                .map(|addr| addr as u16)
+        };
+        let long = |expr| {
+            self.value(expr, location, 0, 0xFFFF, "address")
+                .map(|addr| addr as u16)
+        };
+        let byte = |expr| {
+            self.value(expr, location, -0x80, 0xFF, "byte")
+                .map(|byte| byte as u8)
+        };
+        let nibble = |expr| {
+            self.value(expr, location, 0, 0xF, "nibble")
+                .map(|n| n as u8)
+        };
+
+        use Operand::{Dt, Hf, IndirectI, Long, St, Value, B, F, I, K, R, V};
+
+        Ok(match (mnemonic, operands) {
+            ("CLS", []) => Instruction::Cls,
+            ("RET", []) => Instruction::Ret,
+            ("SYS", [Value(e)]) => Instruction::Sys(addr(e)?),
+            ("SCD", [Value(e)]) => Instruction::ScrollDown(nibble(e)?),
+            ("SCU", [Value(e)]) => Instruction::ScrollUp(nibble(e)?),
+            ("SCR", []) => Instruction::ScrollRight,
+            ("SCL", []) => Instruction::ScrollLeft,
+            ("EXIT", []) => Instruction::Exit,
+            ("LOW", []) => Instruction::Lores,
+            ("HIGH", []) => Instruction::Hires,
+            ("BGC", []) => Instruction::StepBackground,
+            ("JP", [Value(e)]) => Instruction::Jp(addr(e)?),
+            ("JP", [V(0), Value(e)]) => Instruction::JpV0(addr(e)?),
+            ("CALL", [Value(e)]) => Instruction::Call(addr(e)?),
+            ("SE", [V(x), V(y)]) => Instruction::SeReg(*x, *y),
+            ("SE", [V(x), Value(e)]) => Instruction::Se(*x, byte(e)?),
+            ("SNE", [V(x), V(y)]) => Instruction::SneReg(*x, *y),
+            ("SNE", [V(x), Value(e)]) => Instruction::Sne(*x, byte(e)?),
+            ("SAVE", [V(x), V(y)]) => Instruction::SaveRange(*x, *y),
+            ("LOAD", [V(x), V(y)]) => Instruction::LoadRange(*x, *y),
+            ("LD", [V(x), V(y)]) => Instruction::LdReg(*x, *y),
+            ("LD", [V(x), Value(e)]) => Instruction::Ld(*x, byte(e)?),
+            ("LD", [V(x), Dt]) => Instruction::LdVxDt(*x),
+            ("LD", [V(x), K]) => Instruction::LdVxK(*x),
+            ("LD", [V(x), IndirectI]) => Instruction::LdVxI(*x),
+            ("LD", [V(x), R]) => Instruction::LdVxR(*x),
+            ("LD", [I, Value(e)]) => Instruction::LdI(addr(e)?),
+            ("LD", [I, Long(e)]) => Instruction::LdILong(long(e)?),
+            ("LD", [Dt, V(x)]) => Instruction::LdDtVx(*x),
+            ("LD", [St, V(x)]) => Instruction::LdStVx(*x),
+            ("LD", [F, V(x)]) => Instruction::LdF(*x),
+            ("LD", [Hf, V(x)]) => Instruction::LdHf(*x),
+            ("LD", [B, V(x)]) => Instruction::LdB(*x),
+            ("LD", [IndirectI, V(x)]) => Instruction::LdIVx(*x),
+            ("LD", [R, V(x)]) => Instruction::LdRVx(*x),
+            ("ADD", [V(x), V(y)]) => Instruction::AddReg(*x, *y),
+            ("ADD", [V(x), Value(e)]) => Instruction::Add(*x, byte(e)?),
+            ("ADD", [I, V(x)]) => Instruction::AddI(*x),
+            ("OR", [V(x), V(y)]) => Instruction::Or(*x, *y),
+            ("AND", [V(x), V(y)]) => Instruction::And(*x, *y),
+            ("XOR", [V(x), V(y)]) => Instruction::Xor(*x, *y),
+            ("SUB", [V(x), V(y)]) => Instruction::Sub(*x, *y),
+            ("SUBN", [V(x), V(y)]) => Instruction::Subn(*x, *y),
+            // Without Vy the register shifts itself, whichever shift quirk is on
+            ("SHR", [V(x)]) => Instruction::Shr(*x, *x),
+            ("SHR", [V(x), V(y)]) => Instruction::Shr(*x, *y),
+            ("SHL", [V(x)]) => Instruction::Shl(*x, *x),
+            ("SHL", [V(x), V(y)]) => Instruction::Shl(*x, *y),
+            ("COL", [V(x), V(y), Value(e)]) => Instruction::Colour(*x, *y, nibble(e)?),
+            ("RND", [V(x), Value(e)]) => Instruction::Rnd(*x, byte(e)?),
+            ("DRW", [V(x), V(y), Value(e)]) => Instruction::Drw(*x, *y, nibble(e)?),
+            ("SKP", [V(x)]) => Instruction::Skp(*x),
+            ("SKNP", [V(x)]) => Instruction::Sknp(*x),
+            ("SKP2", [V(x)]) => Instruction::SkpKeypad2(*x),
+            ("SKNP2", [V(x)]) => Instruction::SknpKeypad2(*x),
+            ("PLANE", [Value(e)]) => Instruction::Plane(nibble(e)?),
+            ("AUDIO", []) => Instruction::LdAudio,
+            ("PITCH", [V(x)]) => Instruction::Pitch(*x),
+            _ if MNEMONICS.contains(&mnemonic) => {
+                return Err(self.error(location, format!("invalid operands for {mnemonic}")))
+            }
+            _ => return Err(self.error(location, format!("unknown instruction '{mnemonic}'"))),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::CPU;
+    use crate::disasm::{disassemble, Syntax};
+    use crate::octo::compile;
+
+    #[test]
+    fn instructions() {
+        let source = "
+            CLS
+            LD V1, 0x2A
+            LD I, 0x300
+            DRW V0, V1, 5
+            LD [I], V3
+            LD V3, [I]
+            SHR V4
+            SE VA, VB
+        ";
+
+        assert_eq!(
+            assemble(source, Platform::Chip8).unwrap(),
+            [
+                0x00, 0xE0, 0x61, 0x2A, 0xA3, 0x00, 0xD0, 0x15, 0xF3, 0x55, 0xF3, 0x65, 0x84, 0x46,
+                0x5A, 0xB0
+            ]
+        );
+    }
+
+    #[test]
+    fn labels_constants_and_data() {
+        let source = "
+            HEIGHT equ end - sprite
+            start: LD I, sprite     ; labels can come before they're defined
+            DRW V0, V0, HEIGHT
+            JP start
+            sprite:
+            db 0b10000001, -1, \"A\"
+            dw start + 2 * 3, (1 << 8) | 0x34
+            end:
+        ";
+
+        assert_eq!(
+            assemble(source, Platform::Chip8).unwrap(),
+            [0xA2, 0x06, 0xD0, 0x07, 0x12, 0x00, 0x81, 0xFF, b'A', 0x02, 0x06, 0x01, 0x34]
+        );
+    }
+
+    #[test]
+    fn errors_have_locations() {
+        let error = |source| assemble(source, Platform::Chip8).unwrap_err();
+
+        assert_eq!(
+            error("CLS\n  LD V0, missing"),
+            AsmError {
+                file: None,
+                line: 2,
+                column: 10,
+                message: "unknown name 'missing'".to_string()
+            }
+        );
+        assert_eq!(error("  FOO V0").message, "unknown instruction 'FOO'");
+        assert_eq!(error("LD V0").message, "invalid operands for LD");
+        assert_eq!(error("DRW V0, V0, 16").message, "nibble 16 is out of range");
+        assert_eq!(error("x: x:").message, "'x' is already defined");
+        assert_eq!(
+            error("x equ y\ny equ x\nLD V0, x").message,
+            "'x' refers to itself"
+        );
+        assert_eq!(error("HIGH").message, "HIGH needs SUPER-CHIP");
+        assert_eq!(error("include \"x\"").column, 9);
+        assert!(assemble("HIGH", Platform::SuperChip).is_ok());
+    }
+
+    #[test]
+    fn includes() {
+        let dir = std::env::temp_dir().join(format!("sschip8-asm-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("main.asm"), "JP sub\ninclude \"sub.asm\"\n").unwrap();
+        std::fs::write(dir.join("sub.asm"), "sub:\n  RET\n  BAD\n").unwrap();
+
+        let error = assemble_file(&dir.join("main.asm"), Platform::Chip8).unwrap_err();
+        assert_eq!(error.file, Some(dir.join("sub.asm")));
+        assert_eq!((error.line, error.column), (3, 3));
+
+        std::fs::write(dir.join("sub.asm"), "sub:\n  RET\n").unwrap();
+        let rom = assemble_file(&dir.join("main.asm"), Platform::Chip8).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(rom, [0x12, 0x02, 0x00, 0xEE]);
+    }
+
+    #[test]
+    fn runs_on_the_cpu() {
+        let source = "
+            LD V0, 20
+            LD V1, 22
+            ADD V0, V1
+            EXIT
+        ";
+        let rom = assemble(source, Platform::SuperChip).unwrap();
+        let mut cpu = CPU::with_platform(Platform::SuperChip, &rom).unwrap();
+
+        cpu.run_frame().unwrap();
+
+        assert!(cpu.exited);
+        assert_eq!(cpu.registers[0], 42);
+    }
+
+    #[test]
+    fn round_trips_with_the_disassembler() {
+        let rom = [
+            0x22, 0x08, // CALL sub
+            0xF0, 0x00, 0x02, 0x11, // LD I, LONG data + 1
+            0x30, 0x00, // SE V0, 0
+            0x12, 0x06, // JP 0x206
+            0x6F, 0xFF, // LD VF, 0xFF
+            0xA2, 0x03, // LD I, 0x203, inside the long load
+            0xDF, 0x00, // DRW VF, V0, 0
+            0x00, 0xEE, // RET
+            0x01, 0x02, 0x03, // data
+        ];
+
+        let listing = disassemble(&rom, Platform::XoChip).render(Syntax::Cowgod, true);
+
+        assert_eq!(
+            assemble(&listing, Platform::XoChip).unwrap(),
+            rom,
+            "{listing}"
+        );
+    }
+
+    #[test]
+    fn round_trips_octo_with_the_disassembler() {
+        let rom = [
+            0x23, 0x00, // call 0x300, past the end of the ROM
+            0x20, 0xFF, // call 0x0FF, which fits in a byte
+            0x12, 0x04, // jump 0x204
+        ];
+
+        let listing = disassemble(&rom, Platform::Chip8).render(Syntax::Octo, true);
+
+        assert_eq!(compile(&listing).unwrap().rom, rom, "{listing}");
+    }
+}
@@ -1,6 +1,13 @@
-use std::time::UNIX_EPOCH;
-const USE_NEW_SHIFTING_CONVENTIONS: bool = true;
-const USE_NEW_MEMOPS_CONVENTIONS: bool = false;
+use super::display::Colours;
+use super::error::Chip8Error;
+use super::opcode::Instruction;
+use super::platform::{AudioSink, DisplaySink, Keypad, NullAudio, NullDisplay, NullKeypad};
+use super::quirks::Quirks;
+use super::random::Random;
+use super::scheduler::{FrameBudget, Scheduler};
+use super::state::rom_hash;
+use super::timers::Timers;
+use super::variant::{Platform, HIRES_HEADER};
 
 pub const FONT: [u8; 80] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
@@ -21,11 +28,70 @@ pub const FONT: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+/// The 8x10 SUPER-CHIP font used by `Fx30`. SUPER-CHIP 1.1 only has the digits 0-9,
+/// A-F are the letters Octo added.
+pub const BIG_FONT: [u8; 160] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+/// Where `FONT` is stored in memory
+pub const FONT_ADDR: u16 = 0x050;
+
+/// Where `BIG_FONT` is stored in memory, right after `FONT`
+pub const BIG_FONT_ADDR: u16 = FONT_ADDR + FONT.len() as u16;
+
+/// The largest ROM that fits between 0x200 and the end of a 4K memory
+pub const MAX_ROM_SIZE: usize = 4096 - 0x200;
+
+/// What happened during a call to [`CPU::step`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome {
+    /// The instruction was executed normally
+    Executed(Instruction),
+
+    /// The program ran `00FD` and has ended, nothing more is executed
+    Exited,
+}
+
+/// Whether an instruction read or wrote a byte of memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+/// A byte of memory an instruction touched, logged in [`CPU::accesses`]. Instruction
+/// fetches aren't logged, only the data instructions read and write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub addr: u16,
+
+    /// The byte that was read, or the byte that was written
+    pub value: u8,
+
+    pub kind: AccessKind,
+}
+
 /// A struct representing the CHIP-8 CPU and RAM
 #[allow(clippy::upper_case_acronyms)]
 pub struct CPU {
-    /// 4K of memory for the CHIP-8
-    pub mem: [u8; 4096],
+    /// The memory for the CHIP-8, 4K or 64K on XO-CHIP
+    pub mem: Vec<u8>,
 
     /// The program counter
     pub pc: u16,
@@ -33,517 +99,841 @@ pub struct CPU {
     /// The 'I' register to store memory addresses
     pub i_reg: u16,
 
-    /// The stack for the CHIP-8
+    /// The stack for the CHIP-8. On platforms that keep the stack in memory this mirrors
+    /// the entries in use.
     pub stack: [u16; 16],
 
-    /// The registers for the CPU
+    /// The registers for the CPU, V0 through VF. VF doubles as the flag register.
     pub registers: [u8; 16],
 
     /// The stack pointer
     pub sp: u8,
 
-    /// The delay timer
-    pub delay_timer: u8,
+    /// The delay and sound timers
+    pub timers: Timers,
+
+    /// The display buffer, `width * height` pixels in row-major order
+    pub buf: Vec<u8>,
+
+    /// The current width of the screen in pixels
+    pub width: usize,
+
+    /// The current height of the screen in pixels
+    pub height: usize,
 
-    /// The sound timer
-    pub sound_timer: u8,
+    /// Whether `buf` changed since it was last shown on the display
+    pub dirty: bool,
 
-    /// The VF register
-    pub vf: u8,
+    /// The bitplanes selected by XO-CHIP's `Fn01`, bit n stands for plane n
+    pub planes: u8,
 
-    /// The display buffer
-    pub buf: [u8; 2048],
+    /// The colours set by CHIP-8X's `02A0` and `Bxyn`, `None` on every other platform
+    pub colours: Option<Colours>,
 
-    // Variables for helping with internals, not meant for instruction use.
-    pub last_st_write: u128,
-    pub last_dt_write: u128,
-    pub last_cpu_cycle: u128,
+    /// The machine being emulated, which decides the available instructions and screen sizes
+    pub platform: Platform,
 
-    pub is_key_pressed: bool,
-    pub key_code: i16,
+    /// The interpreter conventions used for ambiguous instructions
+    pub quirks: Quirks,
+
+    /// The SUPER-CHIP RPL user flags written by `Fx75` and read by `Fx85`. They live
+    /// outside of memory so the frontend can keep them between runs.
+    pub rpl: [u8; 16],
+
+    /// Whether the program ran `00FD` to end itself
+    pub exited: bool,
+
+    /// The XO-CHIP audio pattern loaded by `F002`, 128 1-bit samples
+    pub audio_pattern: [u8; 16],
+
+    /// The XO-CHIP audio playback rate set by `Fx3A`
+    pub pitch: u8,
+
+    /// How much work `run_frame` does before ticking the timers
+    pub frame_budget: FrameBudget,
+
+    /// How much of the frame budget has been used up in the current frame
+    pub frame_cycles: u32,
+
+    /// The emulated clock, the total number of instructions executed
+    pub cycles: u64,
+
+    /// The number of 60 Hz frames that have been completed
+    pub frames: u64,
+
+    /// The key `Fx0A` saw go down and is waiting to be released
+    pub pressed_key: Option<u8>,
+
+    /// Where `Cxnn` gets its random numbers
+    pub random: Random,
+
+    /// The hash of the loaded ROM, save states remember it to warn about loading a state
+    /// into another game
+    pub rom_hash: u64,
+
+    /// Where finished frames are shown
+    pub display: Box<dyn DisplaySink>,
+
+    /// Where key presses come from
+    pub keypad: Box<dyn Keypad>,
+
+    /// The buzzer for the sound timer
+    pub audio: Box<dyn AudioSink>,
+
+    /// Every memory access made by instructions since the log was last cleared. It's
+    /// `None` unless something like the debugger's watchpoints needs it, so normal runs
+    /// don't pay for the logging.
+    pub accesses: Option<Vec<MemoryAccess>>,
 }
 
-impl CPU {
-    /// Initiate a new instance of the CPU struct
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-    #[allow(dead_code)]
+impl CPU {
+    /// Initiate a new instance of the CPU struct. It isn't connected to a display, keypad
+    /// or speaker until they're set.
     pub fn new() -> Self {
-        let mut mem: [u8; 4096] = [0; 4096];
-
-        // Write the font to mem
-        mem[0x050..(0x09F + 1)].copy_from_slice(&FONT[..]);
+        let platform = Platform::default();
+        let mem = vec![0; platform.memory_size()];
+        let (width, height) = platform.lores_size();
 
-        CPU {
+        let mut cpu = CPU {
             registers: [0; 16],
             pc: 0x200,
             sp: 0,
             mem,
             stack: [0; 16],
             i_reg: 0x200,
-            delay_timer: 255,
-            sound_timer: 255,
-            vf: 0,
-            buf: [0; 2048],
-            last_st_write: 0,
-            last_dt_write: 0,
-            last_cpu_cycle: 0,
-            is_key_pressed: false,
-            key_code: 0,
-        }
+            timers: Timers::default(),
+            buf: vec![0; width * height],
+            width,
+            height,
+            dirty: true,
+            planes: 1,
+            colours: None,
+            platform,
+            quirks: Quirks::default(),
+            rpl: [0; 16],
+            exited: false,
+            audio_pattern: [0; 16],
+            pitch: 64,
+            frame_budget: FrameBudget::default(),
+            frame_cycles: 0,
+            cycles: 0,
+            frames: 0,
+            pressed_key: None,
+            random: Random::default(),
+            rom_hash: rom_hash(&[]),
+            display: Box::new(NullDisplay),
+            keypad: Box::new(NullKeypad),
+            audio: Box::new(NullAudio),
+            accesses: None,
+        };
+        cpu.load_fonts();
+
+        cpu
+    }
+
+    /// Initiate a new instance of the CPU struct with a program loaded at 0x200. Hires
+    /// CHIP-8 programs are recognized by their header and get a 64x64 screen.
+    pub fn new_with_memory(program_memory: &[u8]) -> Result<Self, Chip8Error> {
+        Self::with_platform(Platform::detect(program_memory), program_memory)
     }
 
-    pub fn new_with_memory(program_memory: &[u8]) -> Self {
-        let mut mem: [u8; 4096] = [0; 4096];
-        // Write the font to mem
-        mem[0x050..(0x09F + 1)].copy_from_slice(&FONT[..]);
+    /// Initiate a new instance of the CPU struct for `platform` with a program loaded at
+    /// the platform's load address
+    pub fn with_platform(platform: Platform, program_memory: &[u8]) -> Result<Self, Chip8Error> {
+        let load_addr = platform.load_addr() as usize;
+
+        if program_memory.len() > platform.program_end() - load_addr {
+            return Err(Chip8Error::RomTooLarge {
+                len: program_memory.len(),
+            });
+        }
+
+        let mut cpu = CPU::new();
+        cpu.set_platform(platform);
+        cpu.pc = platform.load_addr();
+        cpu.rom_hash = rom_hash(program_memory);
 
         // Load the program in memory
-        mem[0x200..(program_memory.len() + 0x200)].copy_from_slice(program_memory);
+        cpu.mem[load_addr..(program_memory.len() + load_addr)].copy_from_slice(program_memory);
 
-        CPU {
-            registers: [0; 16],
-            pc: 0x200,
-            sp: 0,
-            mem,
-            stack: [0; 16],
-            i_reg: 0x200,
-            delay_timer: 255,
-            sound_timer: 255,
-            vf: 0,
-            buf: [0; 2048],
-            last_st_write: 0,
-            last_dt_write: 0,
-            last_cpu_cycle: 0,
-            is_key_pressed: false,
-            key_code: 0,
+        // The hires header jumps into machine code that patches the interpreter, which is
+        // emulated by the platform instead. Jump straight to the program after it.
+        if platform == Platform::HiresChip8 && program_memory.starts_with(&HIRES_HEADER) {
+            cpu.mem[load_addr..(load_addr + 2)].copy_from_slice(&[0x12, 0xC0]);
         }
+
+        Ok(cpu)
     }
 
-    /// Decodes two bytes into 4 seperate nibbles
-    pub fn decode(&self, upper_byte: u8, lower_byte: u8) -> (u8, u8, u8, u8) {
-        let upper_high = ((upper_byte & 0xF0) >> 4) as u8;
-        let upper_low = (upper_byte & 0x0F) as u8;
+    /// Switches to another platform, which starts over in its low resolution mode with
+    /// only the first bitplane selected. Memory grows or shrinks to the platform's size and
+    /// the fonts move to where the platform keeps them.
+    pub fn set_platform(&mut self, platform: Platform) {
+        // Take the fonts out of where the old platform kept them
+        self.mem[..0x200].fill(0);
+        let old_font = self.platform.font_addr() as usize;
+        self.mem[old_font..(old_font + FONT.len())].fill(0);
+
+        self.platform = platform;
+        self.mem.resize(platform.memory_size(), 0);
+        self.load_fonts();
+        self.planes = 1;
+        self.set_hires(false);
+        self.colours = platform
+            .has_chip8x()
+            .then(|| Colours::new(self.width, self.height));
+    }
 
-        let lower_high = ((lower_byte & 0xF0) >> 4) as u8;
-        let lower_low = (lower_byte & 0x0F) as u8;
+    /// Writes the fonts to where the platform keeps them
+    fn load_fonts(&mut self) {
+        let font = self.platform.font_addr() as usize;
+        self.mem[font..(font + FONT.len())].copy_from_slice(&FONT[..]);
 
-        (upper_high, upper_low, lower_high, lower_low)
+        if let Some(big_font) = self.platform.big_font_addr() {
+            let big_font = big_font as usize;
+            self.mem[big_font..(big_font + BIG_FONT.len())].copy_from_slice(&BIG_FONT[..]);
+        }
     }
 
-    /// Runs the CHIP-8
-    pub fn run(&mut self) {
-        loop {
-            let instruction =
-                self.decode(self.mem[self.pc as usize], self.mem[self.pc as usize + 1]);
+    /// The addresses of `len` bytes starting at `addr`, which wrap around from 0xFFFF to 0
+    /// like I does. Fails unless all of them lie inside memory.
+    pub fn mem_addrs(
+        &self,
+        addr: u16,
+        len: usize,
+    ) -> Result<impl Iterator<Item = usize> + Clone, Chip8Error> {
+        let addrs = (0..len).map(move |i| (addr as usize + i) & 0xFFFF);
+
+        if addrs.clone().any(|addr| addr >= self.mem.len()) {
+            return Err(Chip8Error::MemoryOutOfBounds { addr });
+        }
 
-            let now = std::time::SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_millis();
+        Ok(addrs)
+    }
 
-            if now - self.last_cpu_cycle >= 17 && self.sound_timer != 0 {
-                // Play audio or something
-                unsafe {
-                    winapi::um::utilapiset::Beep(750, 100);
-                }
+    /// Reads `len` bytes starting at `addr` for an instruction, logging the reads if
+    /// accesses are being logged
+    pub fn read_mem(&mut self, addr: u16, len: usize) -> Result<Vec<u8>, Chip8Error> {
+        let addrs = self.mem_addrs(addr, len)?;
+        let bytes: Vec<u8> = addrs.clone().map(|addr| self.mem[addr]).collect();
+
+        if let Some(accesses) = &mut self.accesses {
+            accesses.extend(addrs.zip(&bytes).map(|(addr, &value)| MemoryAccess {
+                addr: addr as u16,
+                value,
+                kind: AccessKind::Read,
+            }));
+        }
+
+        Ok(bytes)
+    }
+
+    /// Writes `bytes` to memory starting at `addr` for an instruction, logging the writes
+    /// if accesses are being logged
+    pub fn write_mem(&mut self, addr: u16, bytes: &[u8]) -> Result<(), Chip8Error> {
+        let addrs = self.mem_addrs(addr, bytes.len())?;
+        for (addr, &value) in addrs.clone().zip(bytes) {
+            self.mem[addr] = value;
+        }
+
+        if let Some(accesses) = &mut self.accesses {
+            accesses.extend(addrs.zip(bytes).map(|(addr, &value)| MemoryAccess {
+                addr: addr as u16,
+                value,
+                kind: AccessKind::Write,
+            }));
+        }
 
-                self.sound_timer -= 1;
+        Ok(())
+    }
+
+    /// Reads the big-endian word at `addr`, wrapping around like [`CPU::mem_addrs`]
+    pub fn read_word(&self, addr: u16) -> Result<u16, Chip8Error> {
+        let byte = |addr: u16| self.mem.get(addr as usize).copied();
+
+        match (byte(addr), byte(addr.wrapping_add(1))) {
+            (Some(high), Some(low)) => Ok(u16::from_be_bytes([high, low])),
+            _ => Err(Chip8Error::MemoryOutOfBounds { addr }),
+        }
+    }
+
+    /// Fetches the big-endian opcode at the program counter
+    pub fn fetch(&self) -> Result<u16, Chip8Error> {
+        self.read_word(self.pc)
+    }
+
+    /// Fetches and decodes the instruction at the program counter, including the address
+    /// word of XO-CHIP's `F000 nnnn`
+    pub fn fetch_instruction(&self) -> Result<Instruction, Chip8Error> {
+        let pc = self.pc;
+        let opcode = self.fetch()?;
+
+        let next = if opcode == 0xF000 && self.platform.has_xochip() {
+            self.read_word(pc.wrapping_add(2))?
+        } else {
+            0
+        };
+
+        self.platform
+            .decode(opcode, next)
+            .map_err(|_| Chip8Error::InvalidOpcode { pc, opcode })
+    }
+
+    /// Fetches, decodes and executes exactly one instruction
+    pub fn step(&mut self) -> Result<StepOutcome, Chip8Error> {
+        if self.exited {
+            return Ok(StepOutcome::Exited);
+        }
+
+        let pc = self.pc;
+        let instruction = self.fetch_instruction()?;
+        let registers = self.registers;
+
+        // Increment the program counter
+        let next = self.pc.wrapping_add(instruction.size());
+        self.pc = next;
+
+        // Point the program counter back at the failing instruction so it can be reported
+        if let Err(err) = self.execute(instruction) {
+            self.pc = pc;
+            return Err(err);
+        }
+        // Only skips care, for them moving anywhere else means they skipped
+        let skipped = self.pc != next;
+        self.frame_cycles += self.frame_budget.cost(&instruction, &registers, skipped);
+        self.cycles += 1;
+        self.random.tick();
+
+        if self.exited {
+            return Ok(StepOutcome::Exited);
+        }
+
+        Ok(StepOutcome::Executed(instruction))
+    }
+
+    /// Executes up to `n` instructions, stopping early if an error occurs
+    pub fn run_cycles(&mut self, n: usize) -> Result<(), Chip8Error> {
+        for _ in 0..n {
+            self.step()?;
+        }
+
+        Ok(())
+    }
+
+    /// Executes instructions until the next 60 Hz timer tick and then ticks the timers
+    pub fn run_frame(&mut self) -> Result<(), Chip8Error> {
+        self.run_frame_until(|_| false)?;
+
+        Ok(())
+    }
+
+    /// Like `run_frame`, but checks `stop` before every instruction and returns `true`
+    /// without executing it once `stop` does. The frame isn't over then, the next call
+    /// carries on where this one left off.
+    pub fn run_frame_until(
+        &mut self,
+        mut stop: impl FnMut(&CPU) -> bool,
+    ) -> Result<bool, Chip8Error> {
+        self.keypad.start_frame(self.frames);
+
+        while self.frame_cycles < self.frame_budget.size() {
+            if stop(self) {
+                return Ok(true);
             }
-            // Increment the program counter
-            self.pc += 2;
 
-            match instruction {
-                // 0x00E0 - clr
-                (0x0, 0x0, 0xE, 0x0) => {
-                    #[cfg(feature = "show_commands")]
-                    println!("0x00E0: clr");
+            let outcome = self.step()?;
 
-                    self.cls00e0();
-                }
-                // 0x1nnn - jp
-                (0x1, nnn_a, nnn_b, nnn_c) => {
-                    let addr = self.to_nnn(nnn_a, nnn_b, nnn_c);
+            if outcome == StepOutcome::Exited {
+                break;
+            }
 
-                    #[cfg(feature = "show_commands")]
-                    println!("0x1nnn: jp to {addr}");
+            // The VIP waits for the vertical blank after drawing, which ends the frame early
+            if self.quirks.display_wait
+                && matches!(outcome, StepOutcome::Executed(Instruction::Drw(..)))
+            {
+                break;
+            }
+        }
+        self.frame_cycles = 0;
+        self.frames += 1;
 
-                    self.jp1nnn(addr);
-                }
+        self.audio.set_playing(self.timers.is_sound_playing());
+        self.timers.tick();
 
-                // 0x6xnn - set
-                (0x6, x, upper_nibble, lower_nibble) => {
-                    let nn = (upper_nibble << 4) | lower_nibble;
+        Ok(false)
+    }
 
-                    #[cfg(feature = "show_commands")]
-                    println!("0x6xnn: set V{x} to {nn}");
+    /// Runs the CHIP-8 at 60 frames per second
+    pub fn run(&mut self) -> Result<(), Chip8Error> {
+        self.run_with(&mut Scheduler::default())
+    }
 
-                    self.set6xnn(x, nn);
-                }
+    /// Runs the CHIP-8, letting `scheduler` decide when each frame runs, until the program
+    /// exits with `00FD`
+    pub fn run_with(&mut self, scheduler: &mut Scheduler) -> Result<(), Chip8Error> {
+        while !self.exited {
+            if !scheduler.wait_for_frame() {
+                continue;
+            }
 
-                // 0x7Xnn - add
-                (0x7, x, upper_nibble, lower_nibble) => {
-                    let nn = (upper_nibble << 4) | lower_nibble;
+            self.run_frame()?;
+            self.update();
+        }
 
-                    #[cfg(feature = "show_commands")]
-                    println!("0x7xnn: add {nn} to V{x}");
+        Ok(())
+    }
 
-                    self.add7xnn(x, nn);
-                }
+    /// Executes a single decoded instruction. The program counter should already point
+    /// to the next instruction.
+    pub fn execute(&mut self, instruction: Instruction) -> Result<(), Chip8Error> {
+        if !self.platform.supports(&instruction) {
+            return Err(Chip8Error::InvalidOpcode {
+                pc: self.pc.wrapping_sub(instruction.size()),
+                opcode: instruction.encode(),
+            });
+        }
 
-                // 0xAnnn - set
-                (0xA, nnn_a, nnn_b, nnn_c) => {
-                    let nn = self.to_nnn(nnn_a, nnn_b, nnn_c);
+        match instruction {
+            Instruction::Cls => {
+                #[cfg(feature = "show_commands")]
+                println!("0x00E0: clr");
 
-                    #[cfg(feature = "show_commands")]
-                    println!("0xAnnn: set I to {nn}");
+                self.cls00e0();
+            }
 
-                    self.setannn(nn);
-                }
+            Instruction::Jp(addr) => {
+                #[cfg(feature = "show_commands")]
+                println!("0x1nnn: jp to {addr}");
 
-                // 0xDxyn - draw
-                (0xD, x, y, n) => {
-                    #[cfg(feature = "show_commands")]
-                    println!("0xDxyn: draw sprite at address {n} at ({x}, {y})");
+                self.jp1nnn(addr);
+            }
 
-                    self.drwdxyn(x, y, n);
-                }
+            Instruction::Ld(x, nn) => {
+                #[cfg(feature = "show_commands")]
+                println!("0x6xnn: set V{x} to {nn}");
 
-                // 0x2nnn - call
-                (0x2, nnn_a, nnn_b, nnn_c) => {
-                    let addr = self.to_nnn(nnn_a, nnn_b, nnn_c);
+                self.set6xnn(x, nn);
+            }
 
-                    #[cfg(feature = "show_commands")]
-                    println!("0x2nnn: call {addr}");
+            Instruction::Add(x, nn) => {
+                #[cfg(feature = "show_commands")]
+                println!("0x7xnn: add {nn} to V{x}");
 
-                    self.call2nnn(addr);
-                }
+                self.add7xnn(x, nn);
+            }
 
-                // 0x00EE - return
-                (0x0, 0x0, 0xE, 0xE) => {
-                    #[cfg(feature = "show_commands")]
-                    println!("return from subroutine");
+            Instruction::LdI(nnn) => {
+                #[cfg(feature = "show_commands")]
+                println!("0xAnnn: set I to {nnn}");
 
-                    self.ret00ee();
-                }
+                self.setannn(nnn);
+            }
 
-                // 0x3xnn - se
-                (0x3, x, upper_nibble, lower_nibble) => {
-                    let nn = (upper_nibble << 4) | lower_nibble;
+            Instruction::Drw(x, y, n) => {
+                #[cfg(feature = "show_commands")]
+                println!("0xDxyn: draw sprite at address {n} at ({x}, {y})");
 
-                    self.se3xnn(x, nn);
-                }
+                self.drwdxyn(x, y, n)?;
+            }
 
-                // 0x4xnn - sne
-                (0x4, x, upper_nibble, lower_nibble) => {
-                    let nn = (upper_nibble << 4) | lower_nibble;
-                    self.sne4xnn(x, nn);
-                }
+            Instruction::Call(addr) => {
+                #[cfg(feature = "show_commands")]
+                println!("0x2nnn: call {addr}");
 
-                // 0x5xy0 - se
-                (0x5, x, y, 0x0) => {
-                    self.se5xy0(x, y);
-                }
+                self.call2nnn(addr)?;
+            }
 
-                // 0x9xy0 - sne
-                (0x9, x, y, 0x0) => {
-                    self.sne9xy0(x, y);
-                }
+            Instruction::Ret => {
+                #[cfg(feature = "show_commands")]
+                println!("return from subroutine");
 
-                // 0x8xy0 - ld
-                (0x8, x, y, 0x0) => {
-                    #[cfg(feature = "show_commands")]
-                    println!("setting V{x} to value of V{y}");
+                self.ret00ee()?;
+            }
 
-                    self.ld8xy0(x, y);
-                }
+            Instruction::Se(x, nn) => self.se3xnn(x, nn),
 
-                // 0x8xy1 - bitwise OR
-                (0x8, x, y, 0x1) => {
-                    #[cfg(feature = "show_commands")]
-                    println!("V{x} = V{x} OR V{y}");
+            Instruction::Sne(x, nn) => self.sne4xnn(x, nn),
 
-                    self.or8xy1(x, y);
-                }
+            Instruction::SeReg(x, y) => self.se5xy0(x, y),
 
-                // 0x8xy2 - bitwise AND
-                (0x8, x, y, 0x2) => {
-                    #[cfg(feature = "show_commands")]
-                    println!("V{x} = V{x} AND V{y}");
-                    self.and8xy2(x, y);
-                }
+            Instruction::SneReg(x, y) => self.sne9xy0(x, y),
 
-                // 0x8xy3 - bitwise XOR
-                (0x8, x, y, 0x3) => {
-                    #[cfg(feature = "show_commands")]
-                    println!("V{x} = V{x} XOR V{y}");
+            Instruction::LdReg(x, y) => {
+                #[cfg(feature = "show_commands")]
+                println!("setting V{x} to value of V{y}");
 
-                    self.xor8xy3(x, y);
-                }
+                self.ld8xy0(x, y);
+            }
 
-                // 0x8xy4 - ADD
-                (0x8, x, y, 0x4) => {
-                    #[cfg(feature = "show_commands")]
-                    println!("Set V{x} = V{x} + V{y}, set VF = carry");
+            Instruction::Or(x, y) => {
+                #[cfg(feature = "show_commands")]
+                println!("V{x} = V{x} OR V{y}");
 
-                    self.add8xy4(x, y);
-                }
+                self.or8xy1(x, y);
+            }
 
-                // 0x8xy5 - SUB
-                (0x8, x, y, 0x5) => {
-                    #[cfg(feature = "show_commands")]
-                    println!("Set V{x} = V{x} - V{y}, set VF = NOT borrow.");
+            Instruction::And(x, y) => {
+                #[cfg(feature = "show_commands")]
+                println!("V{x} = V{x} AND V{y}");
 
-                    self.sub8xy5(x, y);
-                }
+                self.and8xy2(x, y);
+            }
 
-                // 0x8xy7 - SUB
-                (0x8, x, y, 0x7) => {
-                    #[cfg(feature = "show_commands")]
-                    println!("Set V{x} = V{y} - V{x}, set VF = NOT borrow.");
+            Instruction::Xor(x, y) => {
+                #[cfg(feature = "show_commands")]
+                println!("V{x} = V{x} XOR V{y}");
+
+                self.xor8xy3(x, y);
+            }
+
+            Instruction::AddReg(x, y) => {
+                #[cfg(feature = "show_commands")]
+                println!("Set V{x} = V{x} + V{y}, set VF = carry");
+
+                self.add8xy4(x, y);
+            }
+
+            Instruction::Sub(x, y) => {
+                #[cfg(feature = "show_commands")]
+                println!("Set V{x} = V{x} - V{y}, set VF = NOT borrow.");
 
-                    self.sub8xy7(x, y);
+                self.sub8xy5(x, y);
+            }
+
+            Instruction::Subn(x, y) => {
+                #[cfg(feature = "show_commands")]
+                println!("Set V{x} = V{y} - V{x}, set VF = NOT borrow.");
+
+                self.sub8xy7(x, y);
+            }
+
+            Instruction::Shr(x, y) => {
+                #[cfg(feature = "show_commands")]
+                println!("Set V{x} = V{x} SHR 1.");
+
+                if self.quirks.shift_uses_vy {
+                    self.shr8xy6_usey(x, y);
+                } else {
+                    self.shr8xy6_usex(x, y);
                 }
+            }
+
+            Instruction::Shl(x, y) => {
+                #[cfg(feature = "show_commands")]
+                println!("Set V{x} = V{x} SHL 1.");
 
-                // 0x8xy6 - shr
-                (0x8, x, y, 0x6) => match USE_NEW_SHIFTING_CONVENTIONS {
-                    true => {
-                        #[cfg(feature = "show_commands")]
-                        println!("Set V{x} = V{x} SHR 1.");
-                        self.shr8xy6_usex(x, y);
-                    }
-                    false => {
-                        #[cfg(feature = "show_commands")]
-                        println!("Set V{x} = V{x} SHR 1.");
-                        self.shr8xy6_usey(x, y);
-                    }
-                },
-
-                // 0x8xy6 - shl
-                (0x8, x, y, 0xE) => {
-                    if USE_NEW_SHIFTING_CONVENTIONS {
-                        #[cfg(feature = "show_commands")]
-                        println!("Set V{x} = V{x} SHL 1.");
-                        self.shl8xye_usex(x, y);
-                    } else {
-                        #[cfg(feature = "show_commands")]
-                        println!("Set V{x} = V{x} SHL 1.");
-                        self.shl8xye_usey(x, y);
-                    }
+                if self.quirks.shift_uses_vy {
+                    self.shl8xye_usey(x, y);
+                } else {
+                    self.shl8xye_usex(x, y);
                 }
+            }
+
+            Instruction::JpV0(nnn) => {
+                // With the jump quirk the highest nibble of nnn doubles as the register index
+                let x = (nnn >> 8) as u8;
+
+                if self.quirks.jump_uses_vx {
+                    #[cfg(feature = "show_commands")]
+                    println!("jp to {nnn} + V{x}");
 
-                // 0xBnnn - jp
-                (0xB, nnn_a, nnn_b, nnn_c) => {
-                    let nnn = self.to_nnn(nnn_a, nnn_b, nnn_c);
+                    self.jpbxnn(x, nnn);
+                } else {
                     #[cfg(feature = "show_commands")]
                     println!("jp to {nnn} + V0");
 
                     self.jpbnnn(nnn);
                 }
+            }
 
-                // 0xCxnn - rnd
-                (0xC, x, upper_nibble, lower_nibble) => {
-                    let nn = (upper_nibble << 4) | lower_nibble;
+            Instruction::Rnd(x, nn) => {
+                #[cfg(feature = "show_commands")]
+                println!("get rnd | {nn}");
 
-                    #[cfg(feature = "show_commands")]
-                    println!("get rnd | {nn}");
+                self.rndcxnn(x, nn);
+            }
 
-                    self.rndcxnn(x, nn);
-                }
+            Instruction::LdVxDt(x) => {
+                #[cfg(feature = "show_commands")]
+                println!("Set V{x} = delay timer val");
 
-                // 0xFx07 - ldf
-                (0xF, x, 0x0, 0x7) => {
-                    #[cfg(feature = "show_commands")]
-                    println!("Set V{x} = delay timer val");
+                self.ldfx07(x);
+            }
 
-                    self.ldfx07(x);
-                }
+            Instruction::LdDtVx(x) => {
+                #[cfg(feature = "show_commands")]
+                println!("Set delay timer to V{x}");
 
-                // 0xFx15 - ld
-                (0xF, x, 0x1, 0x5) => {
-                    #[cfg(feature = "show_commands")]
-                    println!("Set delay timer to V{x}");
+                self.ldfx15(x);
+            }
 
-                    self.ldfx15(x);
-                }
+            Instruction::LdStVx(x) => {
+                #[cfg(feature = "show_commands")]
+                println!("Set sound timer to V{x}");
 
-                // 0xFx18 - ld
-                (0xF, x, 0x1, 0x8) => {
-                    #[cfg(feature = "show_commands")]
-                    println!("Set sound timer to V{x}");
+                self.ldfx18(x);
+            }
 
-                    self.ldfx18(x);
-                }
+            Instruction::AddI(x) => {
+                #[cfg(feature = "show_commands")]
+                println!("I = I + V{x}");
 
-                // 0xFx1E - add
-                (0xF, x, 0x1, 0xE) => {
-                    #[cfg(feature = "show_commands")]
-                    println!("I = I + V{x}");
+                self.addfx1e(x);
+            }
 
-                    self.addfx1e(x);
-                }
+            Instruction::LdF(x) => {
+                #[cfg(feature = "show_commands")]
+                println!("Set I = location of sprite for digit V{x}.");
 
-                // 0xFx29 - ld
-                (0xF, x, 0x2, 0x9) => {
-                    #[cfg(feature = "show_commands")]
-                    println!("Set I = location of sprite for digit V{x}.");
+                self.ldfx29(x);
+            }
 
-                    self.ldfx29(x);
-                }
+            Instruction::LdB(x) => {
+                #[cfg(feature = "show_commands")]
+                println!("Store BCD representation of Vx in memory locations I, I+1, and I+2.");
 
-                // 0xFx33 - ld
-                (0xF, x, 0x3, 0x3) => {
-                    #[cfg(feature = "show_commands")]
-                    println!("Store BCD representation of Vx in memory locations I, I+1, and I+2.");
+                self.ldfx33(x)?;
+            }
+
+            Instruction::LdIVx(x) => {
+                #[cfg(feature = "show_commands")]
+                println!("Store registers V0 through Vx in memory starting at location I.");
 
-                    self.ldfx33(x);
+                if self.quirks.memory_increments_i {
+                    self.ldfx55_old(x)?;
+                } else {
+                    self.ldfx55(x)?;
                 }
+            }
 
-                // 0xFx55 - ld
-                (0xF, x, 0x5, 0x5) => {
-                    #[cfg(feature = "show_commands")]
-                    println!("Store registers V0 through Vx in memory starting at location I.");
+            Instruction::LdVxI(x) => {
+                #[cfg(feature = "show_commands")]
+                println!("Read registers V0 through Vx from memory starting at location I.");
 
-                    if USE_NEW_MEMOPS_CONVENTIONS {
-                        self.ldfx55(x);
-                    } else {
-                        self.ldfx55_old(x);
-                    }
+                if self.quirks.memory_increments_i {
+                    self.ldfx65_old(x)?;
+                } else {
+                    self.ldfx65(x)?;
                 }
+            }
 
-                (0xF, x, 0x6, 0x5) => {
-                    #[cfg(feature = "show_commands")]
-                    println!("Read registers V0 through Vx from memory starting at location I.");
+            Instruction::LdVxK(x) => {
+                #[cfg(feature = "show_commands")]
+                println!("Waiting for keypress and writing result to V{x}");
 
-                    if USE_NEW_MEMOPS_CONVENTIONS {
-                        self.ldfx65(x);
-                    } else {
-                        self.ldfx65_old(x);
-                    }
-                }
+                self.ldfx0a(x);
+            }
 
-                (0xF, x, 0x0, 0xA) => {
-                    #[cfg(feature = "show_commands")]
-                    println!("Waiting for keypress and writing result to V{x}");
+            Instruction::Sknp(x) => {
+                #[cfg(feature = "show_commands")]
+                println!("Skip next instruction if key with the value of V{x} not is pressed. V{x} btw: 0x{:x}", 
+                        self.registers[x as usize]);
 
-                    self.ldfx0a(x);
-                }
+                self.skpexa1(x);
+            }
 
-                (0xE, x, 0xA, 0x1) => {
-                    #[cfg(feature = "show_commands")]
-                    println!("Skip next instruction if key with the value of V{x} not is pressed. V{x} btw: 0x{:x}", 
-                            self.registers[x as usize]);
+            Instruction::Skp(x) => {
+                #[cfg(feature = "show_commands")]
+                println!("Skip next instruction if key with the value of V{x} is pressed. V{x} btw: 0x{:x}", 
+                        self.registers[x as usize]);
 
-                    self.skpexa1(x);
-                }
+                self.skpex9e(x);
+            }
 
-                (0xE, x, 0x9, 0xE) => {
-                    #[cfg(feature = "show_commands")]
-                    println!("Skip next instruction if key with the value of V{x} is pressed. V{x} btw: 0x{:x}", 
-                            self.registers[x as usize]);
+            Instruction::ScrollDown(n) => {
+                #[cfg(feature = "show_commands")]
+                println!("0x00Cn: scroll down {n}");
 
-                    self.skpex9e(x);
-                }
+                self.scd00cn(n);
+            }
+
+            Instruction::StepBackground => {
+                #[cfg(feature = "show_commands")]
+                println!("0x02A0: step background colour");
+
+                self.bgc02a0();
+            }
+
+            Instruction::Colour(x, y, n) => {
+                #[cfg(feature = "show_commands")]
+                println!("0xBxyn: set colour of area at V{x} to V{y}");
+
+                self.colbxyn(x, y, n);
+            }
+
+            Instruction::SkpKeypad2(x) => {
+                #[cfg(feature = "show_commands")]
+                println!("Skip next instruction if key V{x} is pressed on keypad 2.");
+
+                self.skpexf2(x);
+            }
+
+            Instruction::SknpKeypad2(x) => {
+                #[cfg(feature = "show_commands")]
+                println!("Skip next instruction if key V{x} is not pressed on keypad 2.");
+
+                self.sknpexf5(x);
+            }
+
+            Instruction::ScrollUp(n) => {
+                #[cfg(feature = "show_commands")]
+                println!("0x00Dn: scroll up {n}");
+
+                self.scu00dn(n);
+            }
+
+            Instruction::SaveRange(x, y) => {
+                #[cfg(feature = "show_commands")]
+                println!("Store registers V{x} through V{y} in memory starting at location I.");
+
+                self.save5xy2(x, y)?;
+            }
+
+            Instruction::LoadRange(x, y) => {
+                #[cfg(feature = "show_commands")]
+                println!("Read registers V{x} through V{y} from memory starting at location I.");
+
+                self.load5xy3(x, y)?;
+            }
+
+            Instruction::LdILong(nnnn) => {
+                #[cfg(feature = "show_commands")]
+                println!("0xF000: set I to {nnnn}");
+
+                self.ldf000(nnnn);
+            }
+
+            Instruction::Plane(n) => {
+                #[cfg(feature = "show_commands")]
+                println!("0xFn01: select planes {n}");
+
+                self.planefn01(n);
+            }
+
+            Instruction::LdAudio => {
+                #[cfg(feature = "show_commands")]
+                println!("0xF002: load audio pattern from I");
+
+                self.audiof002()?;
+            }
+
+            Instruction::Pitch(x) => {
+                #[cfg(feature = "show_commands")]
+                println!("Set pitch to V{x}");
+
+                self.pitchfx3a(x);
+            }
+
+            Instruction::ScrollRight => {
+                #[cfg(feature = "show_commands")]
+                println!("0x00FB: scroll right");
+
+                self.scr00fb();
+            }
+
+            Instruction::ScrollLeft => {
+                #[cfg(feature = "show_commands")]
+                println!("0x00FC: scroll left");
+
+                self.scl00fc();
+            }
+
+            Instruction::Exit => {
+                #[cfg(feature = "show_commands")]
+                println!("0x00FD: exit");
+
+                self.exit00fd();
+            }
+
+            Instruction::Lores => {
+                #[cfg(feature = "show_commands")]
+                println!("0x00FE: low resolution");
+
+                self.low00fe();
+            }
+
+            Instruction::Hires => {
+                #[cfg(feature = "show_commands")]
+                println!("0x00FF: high resolution");
+
+                self.high00ff();
+            }
+
+            Instruction::LdHf(x) => {
+                #[cfg(feature = "show_commands")]
+                println!("Set I = location of large sprite for digit V{x}.");
+
+                self.ldfx30(x);
+            }
+
+            Instruction::LdRVx(x) => {
+                #[cfg(feature = "show_commands")]
+                println!("Store registers V0 through V{x} in the RPL flags.");
+
+                self.ldfx75(x);
+            }
+
+            Instruction::LdVxR(x) => {
+                #[cfg(feature = "show_commands")]
+                println!("Read registers V0 through V{x} from the RPL flags.");
 
-                (a, b, c, d) => {
-                    unimplemented!("0x{:x}{:x}{:x}{:x}", a, b, c, d);
-                }
+                self.ldfx85(x);
             }
-            self.update();
 
-            // Update the status of the last cpu cycle
-            self.last_cpu_cycle = std::time::SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_millis();
+            Instruction::Sys(addr) => {
+                // Machine code routines can't be emulated
+                return Err(Chip8Error::InvalidOpcode {
+                    pc: self.pc.wrapping_sub(2),
+                    opcode: Instruction::Sys(addr).encode(),
+                });
+            }
         }
+
+        Ok(())
     }
 
-    /// A helper function to convert 8 nibbles into one u16 value
-    fn to_nnn(&self, a: u8, b: u8, c: u8) -> u16 {
-        let mut byte = ((a << 4) | b) as u16;
-        byte = (byte << 4) | c as u16;
-
-        byte
-    }
-
-    /// A function for checking if a key is currently pressed using `winapi`
-    pub fn is_key_pressed(&self, key: u8) -> bool {
-        let key_code = match key {
-            0x0 => 0x30,
-            0x1 => 0x31,
-            0x2 => 0x32,
-            0x3 => 0x33,
-            0x4 => 0x34,
-            0x5 => 0x35,
-            0x6 => 0x36,
-            0x7 => 0x37,
-            0x8 => 0x38,
-            0x9 => 0x39,
-            0xA => 0x41,
-            0xB => 0x42,
-            0xC => 0x43,
-            0xD => 0x44,
-            0xE => 0x45,
-            0xF => 0x46,
-            _ => 0x30,
-        };
+    /// A function for checking if a key is currently pressed on the keypad
+    pub fn is_key_pressed(&mut self, key: u8) -> bool {
+        self.keypad.is_key_pressed(key)
+    }
 
-        unsafe {
-            let is_key_pressed = winapi::um::winuser::GetAsyncKeyState(key_code);
-            
-            // Checking if the MSB of `is_key_pressed`, which indicates if the key was pressed during the 
-            // function call
-            ((is_key_pressed >> 15) & 1) == 1
-        }
+    /// Checks if a key is currently pressed on CHIP-8X's second keypad
+    pub fn is_second_key_pressed(&mut self, key: u8) -> bool {
+        self.keypad.is_second_key_pressed(key)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::thread;
-    use std::time::{Duration, SystemTime};
 
     fn new_cpu() -> CPU {
         CPU::new()
     }
 
     #[test]
-    fn decode_instruction_success() {
-        let upper_byte = 0xAB;
-        let lower_byte = 0xCD;
-        let cpu = new_cpu();
-        let decoded_instruction = cpu.decode(upper_byte, lower_byte);
-
-        assert_eq!(decoded_instruction.0, 0xA);
-        assert_eq!(decoded_instruction.1, 0xB);
-        assert_eq!(decoded_instruction.2, 0xC);
-        assert_eq!(decoded_instruction.3, 0xD);
-    }
-
-    #[test]
-    fn decode_instruction_fail() {
-        let upper_byte = 0xAB;
-        let lower_byte = 0xCD;
-        let cpu = new_cpu();
-        let decoded_instruction = cpu.decode(upper_byte, lower_byte);
+    fn test_fetch() {
+        let cpu = CPU::new_with_memory(&[0xAB, 0xCD]).unwrap();
 
-        assert_ne!(decoded_instruction.0, 0x6);
-        assert_ne!(decoded_instruction.1, 0x9);
-        assert_ne!(decoded_instruction.2, 0x4);
-        assert_ne!(decoded_instruction.3, 0x2);
+        assert_eq!(cpu.fetch().unwrap(), 0xABCD);
     }
 
     #[test]
-    fn test_to_nnn() {
-        let a = 0xA;
-        let b = 0xB;
-        let c = 0xC;
-        let cpu = new_cpu();
+    fn test_execute() {
+        let mut cpu = new_cpu();
 
-        let new_val = cpu.to_nnn(a, b, c);
+        cpu.execute(Instruction::decode(0x6A2A).unwrap()).unwrap();
+        cpu.execute(Instruction::decode(0x7A01).unwrap()).unwrap();
+        cpu.execute(Instruction::decode(0xA123).unwrap()).unwrap();
 
-        assert_eq!(new_val, 0xABC);
+        assert_eq!(cpu.registers[0xA], 43);
+        assert_eq!(cpu.i_reg, 0x123);
     }
 
     #[test]
@@ -595,17 +985,44 @@ mod tests {
         assert_eq!(cpu.i_reg, arbitrary_value);
     }
 
+    #[test]
+    fn test_rndcxnn() {
+        let mut cpu = new_cpu();
+        cpu.random = Random::sequence(vec![0xAB, 0xFF]);
+
+        cpu.rndcxnn(0, 0x0F);
+        cpu.rndcxnn(1, 0xF0);
+
+        assert_eq!(cpu.registers[0], 0x0B);
+        assert_eq!(cpu.registers[1], 0xF0);
+    }
+
+    #[test]
+    fn same_seed_same_run() {
+        // RND V0, 0xFF; RND V1, 0xFF; RND V2, 0xFF
+        let program = [0xC0, 0xFF, 0xC1, 0xFF, 0xC2, 0xFF];
+        let run = |seed| {
+            let mut cpu = CPU::new_with_memory(&program).unwrap();
+            cpu.random = Random::seeded(seed);
+            cpu.run_cycles(3).unwrap();
+            cpu.registers
+        };
+
+        assert_eq!(run(5), run(5));
+        assert_ne!(run(5), run(6));
+    }
+
     #[test]
     fn test_call2nnn() {
         let mut cpu = new_cpu();
 
         let arbitrary_address = 500;
 
-        cpu.call2nnn(arbitrary_address);
+        cpu.call2nnn(arbitrary_address).unwrap();
 
         assert_eq!(cpu.sp, 1);
         assert_eq!(cpu.pc, arbitrary_address);
-        assert_eq!(cpu.stack[cpu.sp as usize], arbitrary_address);
+        assert_eq!(cpu.stack[0], 0x200);
     }
 
     #[test]
@@ -631,8 +1048,8 @@ mod tests {
 
         let arbitrary_subroutine_address = 500;
 
-        cpu.call2nnn(arbitrary_subroutine_address);
-        cpu.ret00ee();
+        cpu.call2nnn(arbitrary_subroutine_address).unwrap();
+        cpu.ret00ee().unwrap();
 
         assert_eq!(cpu.sp, 0);
     }
@@ -760,7 +1177,7 @@ mod tests {
 
         cpu.add8xy4(0, 1);
 
-        assert_eq!(cpu.vf, 0);
+        assert_eq!(cpu.registers[0xF], 0);
         assert_eq!(cpu.registers[0], 20);
     }
 
@@ -776,7 +1193,7 @@ mod tests {
 
         cpu.add8xy4(0, 1);
 
-        assert_eq!(cpu.vf, 1);
+        assert_eq!(cpu.registers[0xF], 1);
     }
 
     #[test]
@@ -791,7 +1208,7 @@ mod tests {
 
         cpu.sub8xy5(0, 1);
 
-        assert_eq!(cpu.vf, 1);
+        assert_eq!(cpu.registers[0xF], 1);
         assert_eq!(cpu.registers[0], 25);
     }
 
@@ -807,7 +1224,7 @@ mod tests {
 
         cpu.sub8xy5(0, 1);
 
-        assert_eq!(cpu.vf, 0);
+        assert_eq!(cpu.registers[0xF], 0);
         assert_eq!(cpu.registers[0], 231);
     }
 
@@ -823,7 +1240,7 @@ mod tests {
 
         cpu.sub8xy7(0, 1);
 
-        assert_eq!(cpu.vf, 1);
+        assert_eq!(cpu.registers[0xF], 1);
         assert_eq!(cpu.registers[0], 25);
     }
 
@@ -839,7 +1256,7 @@ mod tests {
 
         cpu.sub8xy7(0, 1);
 
-        assert_eq!(cpu.vf, 0);
+        assert_eq!(cpu.registers[0xF], 0);
         assert_eq!(cpu.registers[0], 231);
     }
 
@@ -854,7 +1271,7 @@ mod tests {
         cpu.shr8xy6_usey(0, 1);
 
         assert_eq!(cpu.registers[0], byte >> 1);
-        assert_eq!(cpu.vf, 1);
+        assert_eq!(cpu.registers[0xF], 1);
     }
 
     #[test]
@@ -868,7 +1285,7 @@ mod tests {
         cpu.shr8xy6_usey(0, 0);
 
         assert_eq!(cpu.registers[0], byte >> 1);
-        assert_eq!(cpu.vf, 1);
+        assert_eq!(cpu.registers[0xF], 1);
     }
 
     #[test]
@@ -882,7 +1299,7 @@ mod tests {
         cpu.shl8xye_usey(0, 1);
 
         assert_eq!(cpu.registers[0], byte << 1);
-        assert_eq!(cpu.vf, 1);
+        assert_eq!(cpu.registers[0xF], 1);
     }
 
     #[test]
@@ -896,7 +1313,57 @@ mod tests {
         cpu.shl8xye_usex(0, 0);
 
         assert_eq!(cpu.registers[0], byte << 1);
-        assert_eq!(cpu.vf, 1);
+        assert_eq!(cpu.registers[0xF], 1);
+    }
+
+    #[test]
+    fn test_add8xy4_flag_overwrites_result() {
+        let mut cpu = new_cpu();
+
+        cpu.set6xnn(0xF, 200);
+
+        // 8FF4 - the carry flag is written after the sum
+        cpu.add8xy4(0xF, 0xF);
+
+        assert_eq!(cpu.registers[0xF], 1);
+    }
+
+    #[test]
+    fn test_sub8xy5_equal_values() {
+        let mut cpu = new_cpu();
+
+        cpu.set6xnn(0, 25);
+        cpu.set6xnn(1, 25);
+
+        cpu.sub8xy5(0, 1);
+
+        assert_eq!(cpu.registers[0], 0);
+        assert_eq!(cpu.registers[0xF], 1);
+    }
+
+    #[test]
+    fn test_shr8xy6_flag_overwrites_result() {
+        let mut cpu = new_cpu();
+
+        cpu.set6xnn(0xF, 0b10);
+
+        cpu.shr8xy6_usex(0xF, 0xF);
+
+        assert_eq!(cpu.registers[0xF], 0);
+    }
+
+    #[test]
+    fn test_ldfx55_stores_flag() {
+        let mut cpu = new_cpu();
+
+        cpu.set6xnn(0, 255);
+        cpu.set6xnn(1, 1);
+        cpu.add8xy4(0, 1);
+
+        cpu.i_reg = 1024;
+        cpu.ldfx55(0xF).unwrap();
+
+        assert_eq!(cpu.mem[1024 + 0xF], 1);
     }
 
     #[test]
@@ -911,16 +1378,54 @@ mod tests {
         assert_eq!(cpu.pc, 1279)
     }
 
+    #[test]
+    fn test_jpbxnn() {
+        let mut cpu = new_cpu();
+
+        cpu.set6xnn(2, 10);
+
+        cpu.jpbxnn(2, 0x234);
+
+        assert_eq!(cpu.pc, 0x234 + 10)
+    }
+
+    #[test]
+    fn test_logic_resets_vf() {
+        let mut cpu = new_cpu();
+        cpu.quirks = Quirks::vip();
+
+        cpu.registers[0xF] = 1;
+        cpu.or8xy1(0, 1);
+
+        assert_eq!(cpu.registers[0xF], 0);
+    }
+
     #[test]
     fn test_delay_instructions() {
         let mut cpu = new_cpu();
 
         cpu.set6xnn(0, 69);
         cpu.ldfx15(0);
-        let time = SystemTime::now();
-        thread::sleep(Duration::from_millis(20));
-        cpu.ldfx07(0);
-        assert_eq!(time.elapsed().unwrap().as_millis() as u8, cpu.registers[0]);
+        for _ in 0..20 {
+            cpu.timers.tick();
+        }
+        cpu.ldfx07(1);
+        assert_eq!(cpu.registers[1], 69 - 20);
+    }
+
+    #[test]
+    fn test_sound_timer() {
+        let mut cpu = new_cpu();
+
+        cpu.set6xnn(0, 2);
+        cpu.ldfx18(0);
+        assert!(cpu.timers.is_sound_playing());
+
+        cpu.timers.tick();
+        cpu.timers.tick();
+        cpu.timers.tick();
+        assert_eq!(cpu.timers.sound, 0);
+        assert!(!cpu.timers.is_sound_playing());
     }
 
     #[test]
@@ -983,13 +1488,48 @@ mod tests {
         cpu.set6xnn(0, 123);
 
         cpu.i_reg = 1024;
-        cpu.ldfx33(0);
+        cpu.ldfx33(0).unwrap();
 
         assert_eq!(cpu.mem[1024], 1);
         assert_eq!(cpu.mem[1025], 2);
         assert_eq!(cpu.mem[1026], 3);
     }
 
+    #[test]
+    fn test_memory_accesses() {
+        let mut cpu = new_cpu();
+
+        cpu.set6xnn(0, 42);
+        cpu.i_reg = 1024;
+        cpu.ldfx33(0).unwrap();
+        assert_eq!(cpu.accesses, None);
+
+        cpu.accesses = Some(Vec::new());
+        cpu.ldfx33(0).unwrap();
+        cpu.ldfx65(1).unwrap();
+
+        let write = |addr, value| MemoryAccess {
+            addr,
+            value,
+            kind: AccessKind::Write,
+        };
+        let read = |addr, value| MemoryAccess {
+            addr,
+            value,
+            kind: AccessKind::Read,
+        };
+        assert_eq!(
+            cpu.accesses.unwrap(),
+            [
+                write(1024, 0),
+                write(1025, 4),
+                write(1026, 2),
+                read(1024, 0),
+                read(1025, 4),
+            ]
+        );
+    }
+
     #[test]
     fn test_ldfx55() {
         let mut cpu = new_cpu();
@@ -1002,7 +1542,7 @@ mod tests {
         cpu.set6xnn(5, 5);
 
         cpu.i_reg = 1024;
-        cpu.ldfx55(5);
+        cpu.ldfx55(5).unwrap();
 
         assert_eq!(cpu.mem[1024], 0);
         assert_eq!(cpu.mem[1025], 1);
@@ -1012,6 +1552,21 @@ mod tests {
         assert_eq!(cpu.mem[1029], 5);
     }
 
+    #[test]
+    fn test_ldfx55_old() {
+        let mut cpu = new_cpu();
+
+        cpu.set6xnn(0, 7);
+        cpu.set6xnn(1, 8);
+        cpu.set6xnn(2, 9);
+
+        cpu.i_reg = 1024;
+        cpu.ldfx55_old(2).unwrap();
+
+        assert_eq!(cpu.mem[1024..1027], [7, 8, 9]);
+        assert_eq!(cpu.i_reg, 1027);
+    }
+
     #[test]
     fn test_ldfx65() {
         let mut cpu = new_cpu();
@@ -1024,7 +1579,7 @@ mod tests {
         cpu.mem[1029] = 5;
 
         cpu.i_reg = 1024;
-        cpu.ldfx65(5);
+        cpu.ldfx65(5).unwrap();
 
         assert_eq!(cpu.registers[0], 0);
         assert_eq!(cpu.registers[1], 1);
@@ -1033,4 +1588,431 @@ mod tests {
         assert_eq!(cpu.registers[4], 4);
         assert_eq!(cpu.registers[5], 5);
     }
-}
\ No newline at end of file
+
+    fn new_schip_cpu() -> CPU {
+        let mut cpu = CPU::new();
+        cpu.set_platform(Platform::SuperChip);
+        cpu
+    }
+
+    #[test]
+    fn test_high00ff_and_low00fe() {
+        let mut cpu = new_schip_cpu();
+
+        cpu.high00ff();
+        assert_eq!((cpu.width, cpu.height), (128, 64));
+        assert_eq!(cpu.buf.len(), 128 * 64);
+        assert!(cpu.is_hires());
+
+        cpu.low00fe();
+        assert_eq!((cpu.width, cpu.height), (64, 32));
+        assert_eq!(cpu.buf.len(), 64 * 32);
+    }
+
+    #[test]
+    fn superchip_instructions_need_superchip_platform() {
+        let mut cpu = new_cpu();
+
+        assert!(matches!(
+            cpu.execute(Instruction::Hires),
+            Err(Chip8Error::InvalidOpcode { opcode: 0x00FF, .. })
+        ));
+        assert_eq!(cpu.width, 64);
+    }
+
+    #[test]
+    fn test_scd00cn() {
+        let mut cpu = new_schip_cpu();
+        cpu.buf[0] = 1;
+
+        cpu.scd00cn(2);
+
+        assert_eq!(cpu.buf[0], 0);
+        assert_eq!(cpu.buf[2 * 64], 1);
+    }
+
+    #[test]
+    fn test_scr00fb_and_scl00fc() {
+        let mut cpu = new_schip_cpu();
+        cpu.buf[0] = 1;
+        cpu.buf[63] = 1;
+
+        cpu.scr00fb();
+        assert_eq!(cpu.buf[..6], [0, 0, 0, 0, 1, 0]);
+        assert_eq!(cpu.buf[63], 0);
+
+        cpu.scl00fc();
+        cpu.scl00fc();
+        assert_eq!(cpu.buf[..6], [0; 6]);
+    }
+
+    #[test]
+    fn test_draw_16x16_sprite() {
+        let mut cpu = new_schip_cpu();
+        cpu.high00ff();
+
+        cpu.i_reg = 1024;
+        cpu.mem[1024..1056].fill(0xFF);
+        cpu.drwdxyn(0, 0, 0).unwrap();
+
+        assert_eq!(cpu.buf[..17], [[1; 16].as_slice(), &[0]].concat());
+        assert_eq!(cpu.buf[15 * 128], 1);
+        assert_eq!(cpu.buf[16 * 128], 0);
+        assert_eq!(cpu.registers[0xF], 0);
+    }
+
+    #[test]
+    fn test_ldfx30() {
+        let mut cpu = new_schip_cpu();
+
+        cpu.set6xnn(0, 0x3);
+        cpu.ldfx30(0);
+
+        assert_eq!(cpu.i_reg, BIG_FONT_ADDR + 30);
+        assert_eq!(cpu.mem[cpu.i_reg as usize..][..10], BIG_FONT[30..40]);
+    }
+
+    #[test]
+    fn test_ldfx75_and_ldfx85() {
+        let mut cpu = new_schip_cpu();
+
+        cpu.set6xnn(0, 4);
+        cpu.set6xnn(1, 2);
+        cpu.ldfx75(1);
+
+        cpu.registers = [0; 16];
+        cpu.ldfx85(1);
+
+        assert_eq!(cpu.registers[..2], [4, 2]);
+        assert_eq!(cpu.rpl[..2], [4, 2]);
+    }
+
+    #[test]
+    fn exit00fd_ends_the_run() {
+        let mut cpu = new_schip_cpu();
+        cpu.mem[0x200..0x202].copy_from_slice(&[0x00, 0xFD]);
+
+        assert_eq!(cpu.step().unwrap(), StepOutcome::Exited);
+        assert_eq!(cpu.step().unwrap(), StepOutcome::Exited);
+        assert_eq!(cpu.pc, 0x202);
+
+        cpu.run().unwrap();
+    }
+
+    fn new_xochip_cpu(program: &[u8]) -> CPU {
+        CPU::with_platform(Platform::XoChip, program).unwrap()
+    }
+
+    #[test]
+    fn xochip_i_wraps_at_the_end_of_memory() {
+        let mut cpu = new_xochip_cpu(&[]);
+        cpu.quirks = Quirks::xochip();
+        cpu.registers[..2].copy_from_slice(&[0xAB, 0xCD]);
+        cpu.i_reg = 0xFFFE;
+
+        cpu.execute(Instruction::decode(0xF155).unwrap()).unwrap();
+
+        assert_eq!(cpu.mem[0xFFFE..], [0xAB, 0xCD]);
+        assert_eq!(cpu.i_reg, 0);
+
+        // Reads that run past the end continue at the start of memory
+        cpu.mem[0] = 0xC0;
+        cpu.i_reg = 0xFFFF;
+        cpu.execute(Instruction::decode(0xF165).unwrap()).unwrap();
+        assert_eq!(cpu.registers[..2], [0xCD, 0xC0]);
+
+        cpu.i_reg = 0xFFFF;
+        cpu.registers[..2].copy_from_slice(&[0, 0]);
+        cpu.execute(Instruction::decode(0xD012).unwrap()).unwrap();
+        assert_eq!(cpu.buf[..2], [1, 1]);
+        assert_eq!(cpu.buf[cpu.width..cpu.width + 2], [1, 1]);
+    }
+
+    #[test]
+    fn xochip_instructions_at_the_end_of_memory_wrap() {
+        let mut cpu = new_xochip_cpu(&[]);
+        cpu.pc = 0xFFFE;
+
+        // Waiting for a key keeps the program counter on Fx0A
+        cpu.mem[0xFFFE..].copy_from_slice(&[0xF0, 0x0A]);
+        cpu.step().unwrap();
+        assert_eq!(cpu.pc, 0xFFFE);
+
+        // The address word of F000 nnnn is at the start of memory
+        cpu.mem[0xFFFE..].copy_from_slice(&[0xF0, 0x00]);
+        cpu.mem[..2].copy_from_slice(&[0x12, 0x34]);
+        cpu.step().unwrap();
+        assert_eq!((cpu.i_reg, cpu.pc), (0x1234, 2));
+
+        // Machine code routines are reported where they are
+        cpu.pc = 0xFFFE;
+        cpu.mem[0xFFFE..].copy_from_slice(&[0x01, 0x23]);
+        assert!(matches!(
+            cpu.step(),
+            Err(Chip8Error::InvalidOpcode { pc: 0xFFFE, .. })
+        ));
+    }
+
+    #[test]
+    fn xochip_has_64k_of_memory() {
+        let cpu = new_xochip_cpu(&[0; 0x8000]);
+
+        assert_eq!(cpu.mem.len(), 0x10000);
+        assert!(matches!(
+            CPU::new_with_memory(&[0; 0x8000]),
+            Err(Chip8Error::RomTooLarge { len: 0x8000 })
+        ));
+    }
+
+    #[test]
+    fn test_ldf000() {
+        let mut cpu = new_xochip_cpu(&[0xF0, 0x00, 0xBE, 0xEF]);
+
+        cpu.step().unwrap();
+
+        assert_eq!(cpu.i_reg, 0xBEEF);
+        assert_eq!(cpu.pc, 0x204);
+    }
+
+    #[test]
+    fn skips_over_ldf000() {
+        let mut cpu = new_xochip_cpu(&[0x30, 0x00, 0xF0, 0x00, 0xBE, 0xEF]);
+
+        cpu.step().unwrap();
+
+        assert_eq!(cpu.pc, 0x206);
+    }
+
+    #[test]
+    fn test_save5xy2_and_load5xy3() {
+        let mut cpu = new_xochip_cpu(&[]);
+
+        cpu.set6xnn(2, 20);
+        cpu.set6xnn(3, 30);
+        cpu.set6xnn(4, 40);
+        cpu.i_reg = 1024;
+
+        cpu.save5xy2(2, 4).unwrap();
+        assert_eq!(cpu.mem[1024..1027], [20, 30, 40]);
+        assert_eq!(cpu.i_reg, 1024);
+
+        // Backwards
+        cpu.save5xy2(4, 2).unwrap();
+        assert_eq!(cpu.mem[1024..1027], [40, 30, 20]);
+
+        cpu.load5xy3(5, 7).unwrap();
+        assert_eq!(cpu.registers[5..8], [40, 30, 20]);
+    }
+
+    #[test]
+    fn draw_on_both_planes() {
+        let mut cpu = new_xochip_cpu(&[]);
+
+        // One row for plane 1 followed by one row for plane 2
+        cpu.i_reg = 1024;
+        cpu.mem[1024] = 0b1100_0000;
+        cpu.mem[1025] = 0b1010_0000;
+
+        cpu.planefn01(3);
+        cpu.drwdxyn(0, 0, 1).unwrap();
+
+        assert_eq!(cpu.buf[..4], [3, 1, 2, 0]);
+
+        // Only plane 2 is cleared
+        cpu.planefn01(2);
+        cpu.cls00e0();
+        assert_eq!(cpu.buf[..4], [1, 1, 0, 0]);
+    }
+
+    #[test]
+    fn test_scu00dn() {
+        let mut cpu = new_xochip_cpu(&[]);
+        cpu.buf[3 * 64] = 3;
+
+        // Only plane 1 moves
+        cpu.scu00dn(2);
+
+        assert_eq!(cpu.buf[3 * 64], 2);
+        assert_eq!(cpu.buf[64], 1);
+    }
+
+    #[test]
+    fn test_audiof002_and_pitchfx3a() {
+        let mut cpu = new_xochip_cpu(&[]);
+
+        cpu.i_reg = 1024;
+        cpu.mem[1024..1040].fill(0xAA);
+        cpu.audiof002().unwrap();
+
+        cpu.set6xnn(0, 112);
+        cpu.pitchfx3a(0);
+
+        assert_eq!(cpu.audio_pattern, [0xAA; 16]);
+        assert_eq!(cpu.pitch, 112);
+    }
+
+    #[test]
+    fn xochip_instructions_need_xochip_platform() {
+        let mut cpu = new_schip_cpu();
+
+        assert!(matches!(
+            cpu.execute(Instruction::Plane(2)),
+            Err(Chip8Error::InvalidOpcode { opcode: 0xF201, .. })
+        ));
+    }
+
+    #[test]
+    fn vip_memory_layout() {
+        let cpu = CPU::with_platform(Platform::CosmacVip, &[]).unwrap();
+
+        assert_eq!(cpu.mem[..0x200], [0; 0x200]);
+        assert_eq!(cpu.mem[0xE50..0xEA0], FONT);
+        assert!(matches!(
+            CPU::with_platform(Platform::CosmacVip, &[0; 0xC51]),
+            Err(Chip8Error::RomTooLarge { len: 0xC51 })
+        ));
+    }
+
+    #[test]
+    fn vip_stack_lives_in_memory() {
+        let mut cpu = CPU::with_platform(Platform::CosmacVip, &[]).unwrap();
+
+        cpu.pc = 0x234;
+        cpu.call2nnn(0x300).unwrap();
+        assert_eq!(cpu.mem[0xEA0..0xEA2], [0x02, 0x34]);
+
+        // A program that overwrites the stack changes where it returns to
+        cpu.mem[0xEA1] = 0x50;
+        cpu.ret00ee().unwrap();
+        assert_eq!(cpu.pc, 0x250);
+    }
+
+    #[test]
+    fn vip_stack_has_12_levels() {
+        let mut cpu = CPU::with_platform(Platform::CosmacVip, &[]).unwrap();
+
+        for _ in 0..12 {
+            cpu.call2nnn(0x300).unwrap();
+        }
+
+        assert!(matches!(
+            cpu.call2nnn(0x300),
+            Err(Chip8Error::StackOverflow)
+        ));
+    }
+
+    #[test]
+    fn vip_ldfx29_points_at_the_top_of_memory() {
+        let mut cpu = CPU::with_platform(Platform::CosmacVip, &[]).unwrap();
+
+        cpu.set6xnn(0, 0x2);
+        cpu.ldfx29(0);
+
+        assert_eq!(cpu.i_reg, 0xE50 + 10);
+    }
+
+    #[test]
+    fn hires_chip8_is_detected_from_its_header() {
+        let mut cpu = CPU::new_with_memory(&[0x12, 0x60]).unwrap();
+
+        assert_eq!(cpu.platform, Platform::HiresChip8);
+        assert_eq!((cpu.width, cpu.height), (64, 64));
+
+        // The header skips straight to the program
+        cpu.step().unwrap();
+        assert_eq!(cpu.pc, 0x2C0);
+    }
+
+    #[test]
+    fn hires_chip8_clears_with_0230() {
+        let mut cpu = CPU::with_platform(Platform::HiresChip8, &[0x02, 0x30]).unwrap();
+        cpu.buf[64 * 63] = 1;
+
+        cpu.step().unwrap();
+
+        assert_eq!(cpu.buf[64 * 63], 0);
+    }
+
+    #[test]
+    fn chip8x_loads_at_0x300() {
+        let cpu = CPU::with_platform(Platform::Chip8X, &[0xAB, 0xCD]).unwrap();
+
+        assert_eq!(cpu.pc, 0x300);
+        assert_eq!(cpu.fetch().unwrap(), 0xABCD);
+    }
+
+    #[test]
+    fn test_bgc02a0() {
+        let mut cpu = CPU::with_platform(Platform::Chip8X, &[0x02, 0xA0]).unwrap();
+
+        cpu.step().unwrap();
+
+        assert_eq!(cpu.colours.as_ref().unwrap().background, 2);
+    }
+
+    #[test]
+    fn test_colbxyn_zones() {
+        let mut cpu = CPU::with_platform(Platform::Chip8X, &[]).unwrap();
+
+        // Zones 1-2 across and 0-1 down, in green
+        cpu.set6xnn(0, 0x21);
+        cpu.set6xnn(1, 0x10);
+        cpu.set6xnn(2, 4);
+        cpu.colbxyn(0, 2, 0);
+
+        let colours = cpu.colours.as_ref().unwrap();
+        assert_eq!(colours.foreground_at(8, 0, 64), 4);
+        assert_eq!(colours.foreground_at(23, 7, 64), 4);
+        assert_eq!(colours.foreground_at(24, 0, 64), 1);
+        assert_eq!(colours.foreground_at(8, 8, 64), 1);
+    }
+
+    #[test]
+    fn test_colbxyn_rows() {
+        let mut cpu = CPU::with_platform(Platform::Chip8X, &[]).unwrap();
+
+        cpu.set6xnn(0, 20);
+        cpu.set6xnn(1, 5);
+        cpu.set6xnn(2, 7);
+        cpu.colbxyn(0, 2, 2);
+
+        let colours = cpu.colours.as_ref().unwrap();
+        assert_eq!(colours.foreground_at(16, 5, 64), 7);
+        assert_eq!(colours.foreground_at(23, 6, 64), 7);
+        assert_eq!(colours.foreground_at(16, 7, 64), 1);
+    }
+
+    #[test]
+    fn chip8x_replaces_bnnn() {
+        let mut cpu = CPU::with_platform(Platform::Chip8X, &[0xB0, 0x10]).unwrap();
+
+        cpu.step().unwrap();
+
+        assert_eq!(cpu.pc, 0x302);
+    }
+
+    #[test]
+    fn test_skpexf2_uses_second_keypad() {
+        struct SecondKeypad;
+
+        impl Keypad for SecondKeypad {
+            fn is_key_pressed(&mut self, _key: u8) -> bool {
+                false
+            }
+
+            fn is_second_key_pressed(&mut self, key: u8) -> bool {
+                key == 0x5
+            }
+        }
+
+        let mut cpu = CPU::with_platform(Platform::Chip8X, &[]).unwrap();
+        cpu.keypad = Box::new(SecondKeypad);
+        cpu.set6xnn(0, 0x5);
+
+        cpu.skpexf2(0);
+        assert_eq!(cpu.pc, 0x302);
+
+        cpu.sknpexf5(0);
+        assert_eq!(cpu.pc, 0x302);
+    }
+}
@@ -0,0 +1,1001 @@
+use super::cpu::{AccessKind, MemoryAccess, CPU};
+use super::disasm::{Disassembly, Syntax};
+use super::headless::screen_text;
+use super::opcode::Instruction;
+use super::rewind::Rewind;
+use std::collections::BTreeMap;
+use std::fmt::{self, Write as _};
+use std::io::{self, BufRead, Write};
+
+/// How many instructions `disasm` lists
+const DISASM_LINES: usize = 10;
+
+/// How many bytes `mem` shows when no length is given
+const DEFAULT_MEM_LEN: usize = 16;
+
+const HELP: &str = "\
+break <addr>      stop when the program counter gets to addr
+break <addr> if <condition>
+                  stop at addr only when the condition holds, like V3 == 7
+break if <condition>
+                  stop before any instruction when the condition holds,
+                  like I in 0x300..0x310
+watch <addr> [len]
+                  stop after an instruction writes to len bytes at addr
+rwatch <addr> [len]
+                  stop after an instruction reads them
+awatch <addr> [len]
+                  stop after an instruction reads or writes them
+breakpoints       list the breakpoints and watchpoints
+delete [addr]     remove the breakpoints and watchpoints at addr, or all of them
+step [n]          run n instructions, 1 by default
+next              run one instruction, running calls until they return
+finish            run until the current subroutine returns
+continue          run until a breakpoint or the end of the program
+back [n]          go back n times to the start of a command or frame that ran
+regs              show the registers
+stack             show the call stack
+mem <addr> [len]  show memory
+set <reg> <value> change V0-VF, I, PC, DT or ST
+disasm [addr]     disassemble from addr, or from the program counter
+screen            show the screen
+quit              leave the debugger
+An empty line repeats the last command.
+";
+
+/// Addresses to stop at. There's one bit for every address, so checking the program counter
+/// before each instruction is a single lookup.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breakpoints {
+    bits: Vec<u64>,
+}
+
+impl Default for Breakpoints {
+    fn default() -> Self {
+        Self {
+            bits: vec![0; 0x10000 / 64],
+        }
+    }
+}
+
+impl Breakpoints {
+    /// Adds a breakpoint, returning `false` if there already was one at `addr`
+    pub fn insert(&mut self, addr: u16) -> bool {
+        let was_set = self.contains(addr);
+        self.bits[addr as usize / 64] |= 1 << (addr % 64);
+
+        !was_set
+    }
+
+    /// Removes a breakpoint, returning `false` if there wasn't one at `addr`
+    pub fn remove(&mut self, addr: u16) -> bool {
+        let was_set = self.contains(addr);
+        self.bits[addr as usize / 64] &= !(1 << (addr % 64));
+
+        was_set
+    }
+
+    pub fn clear(&mut self) {
+        self.bits.fill(0);
+    }
+
+    #[inline]
+    pub fn contains(&self, addr: u16) -> bool {
+        self.bits[addr as usize / 64] & 1 << (addr % 64) != 0
+    }
+
+    /// Every breakpoint from the lowest address up
+    pub fn iter(&self) -> impl Iterator<Item = u16> + '_ {
+        (0..=u16::MAX).filter(|&addr| self.contains(addr))
+    }
+}
+
+/// A register breakpoint conditions and `set` work with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Register {
+    V(u8),
+    I,
+    Pc,
+    Dt,
+    St,
+}
+
+impl Register {
+    fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "I" => Some(Self::I),
+            "PC" => Some(Self::Pc),
+            "DT" => Some(Self::Dt),
+            "ST" => Some(Self::St),
+            name => name
+                .strip_prefix('V')
+                .filter(|digit| digit.len() == 1)
+                .and_then(|digit| u8::from_str_radix(digit, 16).ok())
+                .map(Self::V),
+        }
+    }
+
+    fn get(self, cpu: &CPU) -> u16 {
+        match self {
+            Self::V(x) => cpu.registers[x as usize] as u16,
+            Self::I => cpu.i_reg,
+            Self::Pc => cpu.pc,
+            Self::Dt => cpu.timers.delay as u16,
+            Self::St => cpu.timers.sound as u16,
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::V(x) => write!(f, "V{x:X}"),
+            Self::I => write!(f, "I"),
+            Self::Pc => write!(f, "PC"),
+            Self::Dt => write!(f, "DT"),
+            Self::St => write!(f, "ST"),
+        }
+    }
+}
+
+/// The comparisons conditions can use, longest first so `<=` isn't read as `<`
+const COMPARISONS: [&str; 6] = ["==", "!=", "<=", ">=", "<", ">"];
+
+/// What has to hold for a conditional breakpoint to stop
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Condition {
+    /// A register compared with a number, like `V3 == 7`
+    Compare(Register, &'static str, u16),
+
+    /// I pointing into `start..end`, which doesn't include `end`
+    IIn(u16, u16),
+}
+
+impl Condition {
+    fn parse(text: &str) -> Result<Self, String> {
+        if let Some((register, range)) = text.split_once(" in ") {
+            if Register::parse(register.trim()) != Some(Register::I) {
+                return Err("only I can be checked against a range".to_string());
+            }
+            let (start, end) = range
+                .trim()
+                .split_once("..")
+                .ok_or("expected a range like 0x300..0x310")?;
+
+            return Ok(Self::IIn(parse_addr(Some(start))?, parse_addr(Some(end))?));
+        }
+
+        let (register, comparison, value) = COMPARISONS
+            .iter()
+            .find_map(|&comparison| {
+                let (register, value) = text.split_once(comparison)?;
+                Some((register.trim(), comparison, value.trim()))
+            })
+            .ok_or_else(|| format!("invalid condition '{text}'"))?;
+
+        let register =
+            Register::parse(register).ok_or_else(|| format!("unknown register '{register}'"))?;
+        let value = parse_number(value)
+            .and_then(|value| u16::try_from(value).ok())
+            .ok_or_else(|| format!("invalid value '{value}'"))?;
+
+        Ok(Self::Compare(register, comparison, value))
+    }
+
+    fn holds(&self, cpu: &CPU) -> bool {
+        match *self {
+            Self::Compare(register, comparison, value) => {
+                let register = register.get(cpu);
+                match comparison {
+                    "==" => register == value,
+                    "!=" => register != value,
+                    "<=" => register <= value,
+                    ">=" => register >= value,
+                    "<" => register < value,
+                    _ => register > value,
+                }
+            }
+            Self::IIn(start, end) => (start..end).contains(&cpu.i_reg),
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Compare(register, comparison, value) => {
+                write!(f, "{register} {comparison} 0x{value:02X}")
+            }
+            Self::IIn(start, end) => write!(f, "I in 0x{start:03X}..0x{end:03X}"),
+        }
+    }
+}
+
+/// Memory to stop after an instruction reads or writes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Watchpoint {
+    addr: u16,
+    len: u16,
+
+    /// The kind of access to stop for, `None` for both
+    kind: Option<AccessKind>,
+}
+
+impl Watchpoint {
+    fn matches(&self, access: &MemoryAccess) -> bool {
+        access.addr.wrapping_sub(self.addr) < self.len
+            && self.kind.is_none_or(|kind| kind == access.kind)
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            Some(AccessKind::Read) => "reads from",
+            Some(AccessKind::Write) => "writes to",
+            None => "accesses to",
+        };
+
+        match self.len {
+            1 => write!(f, "Watchpoint on {kind} 0x{:03X}", self.addr),
+            len => write!(
+                f,
+                "Watchpoint on {kind} 0x{:03X}..0x{:03X}",
+                self.addr,
+                self.addr as u32 + len as u32
+            ),
+        }
+    }
+}
+
+/// Why [`Debugger::run_until`] stopped early
+enum Stop {
+    Breakpoint,
+
+    /// The instruction at `pc` made `access`
+    Watchpoint {
+        pc: u16,
+        access: MemoryAccess,
+    },
+}
+
+/// An interactive debugger that runs the CPU a bit at a time between commands
+#[derive(Debug, Clone, Default)]
+pub struct Debugger {
+    pub breakpoints: Breakpoints,
+
+    /// The conditions of the breakpoints that have one
+    conditions: BTreeMap<u16, Condition>,
+
+    /// Conditions checked before every instruction, wherever the program counter is
+    anywhere: Vec<Condition>,
+
+    watchpoints: Vec<Watchpoint>,
+
+    /// Captures the machine before every command that runs it and after every frame, so
+    /// `back` can undo them. `None` turns `back` off.
+    pub rewind: Option<Rewind>,
+
+    /// The command an empty line repeats
+    last_command: String,
+}
+
+/// Parses a decimal or `0x` hex number
+fn parse_number(text: &str) -> Option<u32> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn parse_addr(text: Option<&str>) -> Result<u16, String> {
+    let text = text.ok_or("expected an address")?;
+
+    parse_number(text)
+        .and_then(|addr| u16::try_from(addr).ok())
+        .ok_or_else(|| format!("invalid address '{text}'"))
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads commands from `input` until it ends or `quit`, writing the replies to `output`
+    pub fn repl(
+        &mut self,
+        cpu: &mut CPU,
+        input: impl BufRead,
+        mut output: impl Write,
+    ) -> io::Result<()> {
+        writeln!(output, "{}", self.location(cpu))?;
+
+        let mut lines = input.lines();
+        loop {
+            write!(output, "(sschip8) ")?;
+            output.flush()?;
+
+            let Some(line) = lines.next() else {
+                return Ok(());
+            };
+            match self.command(cpu, &line?) {
+                Some(reply) => write!(output, "{reply}")?,
+                None => return Ok(()),
+            }
+        }
+    }
+
+    /// Runs one command and returns what to print, or `None` to quit
+    pub fn command(&mut self, cpu: &mut CPU, line: &str) -> Option<String> {
+        let line = match line.trim() {
+            "" => self.last_command.clone(),
+            line => line.to_string(),
+        };
+        self.last_command.clone_from(&line);
+
+        let mut words = line.split_whitespace();
+        let reply = match words.next().unwrap_or_default() {
+            "" => Ok(String::new()),
+            "quit" | "q" => return None,
+            "help" | "h" => Ok(HELP.to_string()),
+            "break" | "b" => self.add_breakpoint(&words.collect::<Vec<_>>()),
+            "watch" | "w" => {
+                self.add_watchpoint(words.next(), words.next(), Some(AccessKind::Write))
+            }
+            "rwatch" => self.add_watchpoint(words.next(), words.next(), Some(AccessKind::Read)),
+            "awatch" => self.add_watchpoint(words.next(), words.next(), None),
+            "breakpoints" | "info" => Ok(self.list_breakpoints()),
+            "delete" | "d" => self.delete_breakpoint(words.next()),
+            "step" | "s" => match words.next().map(parse_number) {
+                None => Ok(self.step(cpu, 1)),
+                Some(Some(n)) if n > 0 => Ok(self.step(cpu, n)),
+                Some(_) => Err("expected a number of instructions".to_string()),
+            },
+            "next" | "n" => Ok(self.next(cpu)),
+            "finish" | "f" => self.finish(cpu),
+            "continue" | "c" => Ok(self.run_until(cpu, |_| false)),
+            "back" | "bk" => match words.next().map(parse_number) {
+                None => self.back(cpu, 1),
+                Some(Some(n)) if n > 0 => self.back(cpu, n),
+                Some(_) => Err("expected a number of times to go back".to_string()),
+            },
+            "regs" | "r" => Ok(registers(cpu)),
+            "stack" => Ok(stack(cpu)),
+            "mem" | "m" => memory(cpu, words.next(), words.next()),
+            "set" => set(cpu, words.next(), words.next()),
+            "disasm" | "l" => match words.next() {
+                Some(addr) => parse_addr(Some(addr)).map(|addr| self.disassemble(cpu, addr)),
+                None => Ok(self.disassemble(cpu, cpu.pc)),
+            },
+            "screen" => Ok(screen_text(cpu)),
+            command => Err(format!("unknown command '{command}', try 'help'")),
+        };
+
+        Some(reply.unwrap_or_else(|err| format!("Error: {err}\n")))
+    }
+
+    /// Adds a breakpoint from `break`'s arguments: an address, a condition after `if`, or
+    /// both. Adding a condition to an existing breakpoint replaces its old one.
+    fn add_breakpoint(&mut self, args: &[&str]) -> Result<String, String> {
+        let (addr, condition) = match args {
+            ["if", condition @ ..] => (None, Some(condition)),
+            [addr, "if", condition @ ..] => (Some(parse_addr(Some(addr))?), Some(condition)),
+            [addr] => (Some(parse_addr(Some(addr))?), None),
+            [] => return Err("expected an address".to_string()),
+            _ => return Err("expected an address, then 'if' and a condition".to_string()),
+        };
+        let condition = condition
+            .map(|words| Condition::parse(&words.join(" ")))
+            .transpose()?;
+
+        match (addr, condition) {
+            (Some(addr), Some(condition)) => {
+                let reply = format!("Breakpoint at 0x{addr:03X} if {condition}\n");
+                self.breakpoints.insert(addr);
+                self.conditions.insert(addr, condition);
+
+                Ok(reply)
+            }
+            (Some(addr), None) => {
+                // Leaving out the condition makes a conditional breakpoint unconditional
+                if self.breakpoints.insert(addr) || self.conditions.remove(&addr).is_some() {
+                    Ok(format!("Breakpoint at 0x{addr:03X}\n"))
+                } else {
+                    Err(format!("there already is a breakpoint at 0x{addr:03X}"))
+                }
+            }
+            (None, Some(condition)) => {
+                let reply = format!("Breakpoint when {condition}\n");
+                self.anywhere.push(condition);
+
+                Ok(reply)
+            }
+            (None, None) => unreachable!("a breakpoint has an address or a condition"),
+        }
+    }
+
+    fn add_watchpoint(
+        &mut self,
+        addr: Option<&str>,
+        len: Option<&str>,
+        kind: Option<AccessKind>,
+    ) -> Result<String, String> {
+        let addr = parse_addr(addr)?;
+        let len = match len {
+            Some(len) => parse_number(len)
+                .and_then(|len| u16::try_from(len).ok())
+                .filter(|&len| len > 0)
+                .ok_or_else(|| format!("invalid length '{len}'"))?,
+            None => 1,
+        };
+
+        let watchpoint = Watchpoint { addr, len, kind };
+        if self.watchpoints.contains(&watchpoint) {
+            return Err("there already is a watchpoint like that".to_string());
+        }
+        self.watchpoints.push(watchpoint);
+
+        Ok(format!("{watchpoint}\n"))
+    }
+
+    fn list_breakpoints(&self) -> String {
+        let mut list = String::new();
+
+        // Writing to a String can't fail
+        for addr in self.breakpoints.iter() {
+            let _ = match self.conditions.get(&addr) {
+                Some(condition) => writeln!(list, "Breakpoint at 0x{addr:03X} if {condition}"),
+                None => writeln!(list, "Breakpoint at 0x{addr:03X}"),
+            };
+        }
+        for condition in &self.anywhere {
+            let _ = writeln!(list, "Breakpoint when {condition}");
+        }
+        for watchpoint in &self.watchpoints {
+            let _ = writeln!(list, "{watchpoint}");
+        }
+
+        if list.is_empty() {
+            "There are no breakpoints or watchpoints\n".to_string()
+        } else {
+            list
+        }
+    }
+
+    fn delete_breakpoint(&mut self, addr: Option<&str>) -> Result<String, String> {
+        if addr.is_none() {
+            self.breakpoints.clear();
+            self.conditions.clear();
+            self.anywhere.clear();
+            self.watchpoints.clear();
+            return Ok("Deleted every breakpoint and watchpoint\n".to_string());
+        }
+
+        let addr = parse_addr(addr)?;
+        let watchpoints = self.watchpoints.len();
+        self.watchpoints
+            .retain(|watchpoint| watchpoint.addr != addr);
+        self.conditions.remove(&addr);
+
+        match (
+            self.breakpoints.remove(addr),
+            self.watchpoints.len() < watchpoints,
+        ) {
+            (true, false) => Ok(format!("Deleted the breakpoint at 0x{addr:03X}\n")),
+            (false, true) => Ok(format!("Deleted the watchpoints at 0x{addr:03X}\n")),
+            (true, true) => Ok(format!(
+                "Deleted the breakpoint and watchpoints at 0x{addr:03X}\n"
+            )),
+            (false, false) => Err(format!(
+                "there's no breakpoint or watchpoint at 0x{addr:03X}"
+            )),
+        }
+    }
+
+    fn step(&mut self, cpu: &mut CPU, n: u32) -> String {
+        let mut executed = 0;
+
+        self.run_until(cpu, |_| {
+            executed += 1;
+            executed >= n
+        })
+    }
+
+    /// Steps over calls, running the subroutine until it returns to the next instruction
+    fn next(&mut self, cpu: &mut CPU) -> String {
+        match cpu.fetch_instruction() {
+            Ok(Instruction::Call(_)) => {
+                let (ret, sp) = (cpu.pc + 2, cpu.sp);
+                self.run_until(cpu, |cpu| cpu.pc == ret && cpu.sp == sp)
+            }
+            _ => self.step(cpu, 1),
+        }
+    }
+
+    fn finish(&mut self, cpu: &mut CPU) -> Result<String, String> {
+        let sp = cpu.sp;
+        if sp == 0 {
+            return Err("not in a subroutine".to_string());
+        }
+
+        Ok(self.run_until(cpu, |cpu| cpu.sp < sp))
+    }
+
+    /// Restores the machine to where it was `times` captures ago, stopping early at the
+    /// oldest one
+    fn back(&mut self, cpu: &mut CPU, times: u32) -> Result<String, String> {
+        let rewind = self
+            .rewind
+            .as_mut()
+            .ok_or("rewinding is off, start with --rewind <seconds>")?;
+
+        // Where the machine is now becomes the latest capture, so going back once undoes
+        // whatever ran since the one before it
+        rewind.capture(cpu);
+
+        let mut went_back = 0;
+        'back: while went_back < times {
+            // A command that stopped right after a frame captured the same state twice
+            let current = cpu.save_state();
+            loop {
+                if !rewind.step_back(cpu) {
+                    break 'back;
+                }
+                if cpu.save_state() != current {
+                    break;
+                }
+            }
+            went_back += 1;
+        }
+
+        if went_back == 0 {
+            return Err("there's nothing to go back to".to_string());
+        }
+        Ok(format!("{}\n", self.location(cpu)))
+    }
+
+    /// Runs until `done`, a breakpoint or a watchpoint. Breakpoints and `done` are checked
+    /// before every instruction but the first, so running from a breakpoint doesn't stop
+    /// straight away. Watchpoints stop after the instruction that touched the memory. The
+    /// frames run like they always do, so the timers keep ticking at the same rate.
+    fn run_until(&mut self, cpu: &mut CPU, mut done: impl FnMut(&CPU) -> bool) -> String {
+        if cpu.exited {
+            return "The program has exited\n".to_string();
+        }
+
+        if let Some(rewind) = &mut self.rewind {
+            rewind.capture(cpu);
+        }
+
+        // Memory accesses are only logged while something's watching them
+        cpu.accesses = (!self.watchpoints.is_empty()).then(Vec::new);
+
+        let mut first = true;
+        let mut stop = None;
+        let mut pc = cpu.pc;
+
+        let result = loop {
+            // How much of the access log has been checked, it's cleared after every frame
+            let mut seen = 0;
+
+            let stopped = cpu.run_frame_until(|cpu| {
+                if let Some(access) = self.watched(cpu, &mut seen) {
+                    stop = Some(Stop::Watchpoint { pc, access });
+                    return true;
+                }
+                pc = cpu.pc;
+
+                if std::mem::take(&mut first) {
+                    return false;
+                }
+                if self.breaks_at(cpu) {
+                    stop = Some(Stop::Breakpoint);
+                    return true;
+                }
+
+                done(cpu)
+            });
+
+            // The last instruction of the frame hasn't had its accesses checked yet
+            if let Ok(false) = stopped {
+                if let Some(access) = self.watched(cpu, &mut seen) {
+                    stop = Some(Stop::Watchpoint { pc, access });
+                }
+            }
+            if let Some(accesses) = &mut cpu.accesses {
+                accesses.clear();
+            }
+            if let (Ok(false), Some(rewind)) = (&stopped, &mut self.rewind) {
+                rewind.capture(cpu);
+            }
+
+            match stopped {
+                Ok(false) if !cpu.exited && stop.is_none() => {}
+                result => break result,
+            }
+        };
+        cpu.accesses = None;
+
+        match (result, stop) {
+            (Err(err), _) => format!("Error: {err}\n{}\n", self.location(cpu)),
+            (Ok(_), Some(Stop::Watchpoint { pc, access })) => {
+                let (verb, preposition) = match access.kind {
+                    AccessKind::Read => ("read", "from"),
+                    AccessKind::Write => ("wrote", "to"),
+                };
+                format!(
+                    "Watchpoint: 0x{pc:03X} {verb} 0x{:02X} {preposition} 0x{:03X}\n{}\n",
+                    access.value,
+                    access.addr,
+                    self.location(cpu)
+                )
+            }
+            (Ok(_), _) if cpu.exited => "The program exited\n".to_string(),
+            (Ok(_), Some(Stop::Breakpoint)) => format!("Breakpoint\n{}\n", self.location(cpu)),
+            (Ok(_), None) => format!("{}\n", self.location(cpu)),
+        }
+    }
+
+    /// Whether a breakpoint stops the program before the instruction at the program counter
+    fn breaks_at(&self, cpu: &CPU) -> bool {
+        let at_pc = self.breakpoints.contains(cpu.pc)
+            && self
+                .conditions
+                .get(&cpu.pc)
+                .is_none_or(|condition| condition.holds(cpu));
+
+        at_pc || self.anywhere.iter().any(|condition| condition.holds(cpu))
+    }
+
+    /// The first access logged after `seen` that a watchpoint is watching, moving `seen`
+    /// to the end of the log
+    fn watched(&self, cpu: &CPU, seen: &mut usize) -> Option<MemoryAccess> {
+        let accesses = cpu.accesses.as_deref()?;
+        let new = &accesses[*seen..];
+        *seen = accesses.len();
+
+        new.iter()
+            .find(|access| self.watchpoints.iter().any(|watch| watch.matches(access)))
+            .copied()
+    }
+
+    /// The instruction at the program counter
+    fn location(&self, cpu: &CPU) -> String {
+        self.disassemble_line(cpu, cpu.pc).0
+    }
+
+    /// Disassembles the instruction at `addr`, returning the line and where the next
+    /// instruction starts
+    fn disassemble_line(&self, cpu: &CPU, addr: u16) -> (String, u16) {
+        let marker = match (addr == cpu.pc, self.breakpoints.contains(addr)) {
+            (true, _) => "=>",
+            (false, true) => " *",
+            (false, false) => "  ",
+        };
+
+        let word = |addr: u16| cpu.read_word(addr).ok();
+        let Some(opcode) = word(addr) else {
+            return (format!("{marker} 0x{addr:03X}: out of memory"), addr);
+        };
+        let next = word(addr.wrapping_add(2)).unwrap_or(0);
+
+        let (text, size) = match cpu.platform.decode(opcode, next) {
+            Ok(instruction) if cpu.platform.supports(&instruction) => (
+                Disassembly::default().format_instruction(&instruction, Syntax::Octo),
+                instruction.size(),
+            ),
+            _ => (format!("0x{opcode:04X} (data)"), 2),
+        };
+
+        (
+            format!("{marker} 0x{addr:03X}: {text}"),
+            addr.wrapping_add(size),
+        )
+    }
+
+    fn disassemble(&self, cpu: &CPU, mut addr: u16) -> String {
+        let mut listing = String::new();
+
+        for _ in 0..DISASM_LINES {
+            let (line, next) = self.disassemble_line(cpu, addr);
+            listing.push_str(&line);
+            listing.push('\n');
+
+            if next == addr {
+                break;
+            }
+            addr = next;
+        }
+
+        listing
+    }
+}
+
+fn registers(cpu: &CPU) -> String {
+    let mut text = String::new();
+
+    for (row, values) in cpu.registers.chunks(8).enumerate() {
+        let values: Vec<_> = values
+            .iter()
+            .enumerate()
+            .map(|(i, value)| format!("V{:X}=0x{value:02X}", row * 8 + i))
+            .collect();
+        text.push_str(&values.join(" "));
+        text.push('\n');
+    }
+
+    // Writing to a String can't fail
+    let _ = writeln!(
+        text,
+        "I=0x{:03X} PC=0x{:03X} SP={} DT={} ST={}",
+        cpu.i_reg, cpu.pc, cpu.sp, cpu.timers.delay, cpu.timers.sound
+    );
+
+    text
+}
+
+fn stack(cpu: &CPU) -> String {
+    if cpu.sp == 0 {
+        return "The stack is empty\n".to_string();
+    }
+
+    // The most recent call first
+    cpu.stack[..cpu.sp as usize]
+        .iter()
+        .enumerate()
+        .rev()
+        .map(|(i, addr)| format!("#{i} returns to 0x{addr:03X}\n"))
+        .collect()
+}
+
+fn memory(cpu: &CPU, addr: Option<&str>, len: Option<&str>) -> Result<String, String> {
+    let addr = parse_addr(addr)?;
+    let len = match len {
+        Some(len) => parse_number(len).ok_or_else(|| format!("invalid length '{len}'"))? as usize,
+        None => DEFAULT_MEM_LEN,
+    };
+    let bytes: Vec<u8> = cpu
+        .mem_addrs(addr, len)
+        .map_err(|err| err.to_string())?
+        .map(|addr| cpu.mem[addr])
+        .collect();
+
+    Ok(bytes
+        .chunks(16)
+        .enumerate()
+        .map(|(row, bytes)| {
+            let bytes: Vec<_> = bytes.iter().map(|byte| format!("{byte:02X}")).collect();
+            let row_addr = addr.wrapping_add(row as u16 * 16);
+            format!("0x{row_addr:03X}: {}\n", bytes.join(" "))
+        })
+        .collect())
+}
+
+fn set(cpu: &mut CPU, target: Option<&str>, value: Option<&str>) -> Result<String, String> {
+    let (Some(target), Some(value)) = (target, value) else {
+        return Err("expected a register and a value".to_string());
+    };
+    let number = parse_number(value).ok_or_else(|| format!("invalid value '{value}'"))?;
+    let too_large = || format!("{value} is too large for {target}");
+
+    let register = Register::parse(target).ok_or_else(|| format!("unknown register '{target}'"))?;
+    match register {
+        Register::I => cpu.i_reg = u16::try_from(number).map_err(|_| too_large())?,
+        Register::Pc => cpu.pc = u16::try_from(number).map_err(|_| too_large())?,
+        Register::Dt => cpu.timers.delay = u8::try_from(number).map_err(|_| too_large())?,
+        Register::St => cpu.timers.sound = u8::try_from(number).map_err(|_| too_large())?,
+        Register::V(x) => {
+            cpu.registers[x as usize] = u8::try_from(number).map_err(|_| too_large())?;
+        }
+    }
+
+    Ok(format!("{register} = 0x{number:02X}\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Calls a subroutine that sets V1, then sets V0 and loops forever
+    const PROGRAM: [u8; 12] = [
+        0x22, 0x08, // 200: call 0x208
+        0x60, 0x01, // 202: v0 := 1
+        0x12, 0x04, // 204: jump 0x204
+        0x00, 0x00, // 206
+        0x61, 0x02, // 208: v1 := 2
+        0x00, 0xEE, // 20A: return
+    ];
+
+    fn cpu() -> CPU {
+        CPU::new_with_memory(&PROGRAM).unwrap()
+    }
+
+    /// Counts V3 up from 7, saving V0-V3 at 0x300 and drawing the byte after them each time
+    const MEMORY_PROGRAM: [u8; 12] = [
+        0x63, 0x07, // 200: v3 := 7
+        0xA3, 0x00, // 202: i := 0x300
+        0xF3, 0x55, // 204: save v3
+        0xD0, 0x01, // 206: sprite v0 v0 1
+        0x73, 0x01, // 208: v3 += 1
+        0x12, 0x02, // 20A: jump 0x202
+    ];
+
+    #[test]
+    fn breakpoints() {
+        let mut breakpoints = Breakpoints::default();
+
+        assert!(breakpoints.insert(0x204));
+        assert!(!breakpoints.insert(0x204));
+        assert!(breakpoints.insert(0xFFFF));
+        assert!(breakpoints.contains(0x204));
+        assert!(!breakpoints.contains(0x205));
+        assert_eq!(breakpoints.iter().collect::<Vec<_>>(), [0x204, 0xFFFF]);
+        assert!(breakpoints.remove(0x204));
+        assert!(!breakpoints.remove(0x204));
+    }
+
+    #[test]
+    fn stepping() {
+        let mut debugger = Debugger::new();
+        let mut cpu = cpu();
+
+        debugger.command(&mut cpu, "step");
+        assert_eq!((cpu.pc, cpu.sp), (0x208, 1));
+
+        let reply = debugger.command(&mut cpu, "finish").unwrap();
+        assert_eq!((cpu.pc, cpu.sp), (0x202, 0));
+        assert_eq!(reply, "=> 0x202: v0 := 0x01\n");
+        assert_eq!(cpu.registers[1], 2);
+
+        // An empty line repeats `finish`, which only works in a subroutine
+        assert!(debugger.command(&mut cpu, "").unwrap().starts_with("Error"));
+
+        let mut cpu = self::cpu();
+        debugger.command(&mut cpu, "next");
+        assert_eq!((cpu.pc, cpu.sp, cpu.registers[1]), (0x202, 0, 2));
+
+        let mut cpu = self::cpu();
+        debugger.command(&mut cpu, "step 3");
+        assert_eq!(cpu.pc, 0x202);
+    }
+
+    #[test]
+    fn continues_to_breakpoints() {
+        let mut debugger = Debugger::new();
+        let mut cpu = cpu();
+
+        debugger.command(&mut cpu, "break 0x20A");
+        let reply = debugger.command(&mut cpu, "continue").unwrap();
+
+        assert_eq!(reply, "Breakpoint\n=> 0x20A: return\n");
+        assert_eq!(cpu.pc, 0x20A);
+
+        // Continuing from a breakpoint runs the instruction there
+        debugger.command(&mut cpu, "break 0x204");
+        debugger.command(&mut cpu, "c");
+        assert_eq!(cpu.pc, 0x204);
+    }
+
+    #[test]
+    fn inspecting_and_changing_state() {
+        let mut debugger = Debugger::new();
+        let mut cpu = cpu();
+        let mut run = |command| debugger.command(&mut cpu, command).unwrap();
+
+        assert_eq!(run("set V3 0x10"), "V3 = 0x10\n");
+        assert_eq!(run("set I 0x300"), "I = 0x300\n");
+        assert!(run("set V3 256").starts_with("Error"));
+        assert!(run("regs").contains("V3=0x10"));
+        assert!(run("regs").contains("I=0x300 PC=0x200"));
+        assert_eq!(run("mem 0x200 4"), "0x200: 22 08 60 01\n");
+        assert!(run("mem 0xFFF 2").starts_with("Error"));
+        assert_eq!(run("stack"), "The stack is empty\n");
+        assert!(run("disasm").starts_with("=> 0x200: :call 0x208\n   0x202: v0 := 0x01\n"));
+        assert!(run("bogus").starts_with("Error: unknown command"));
+
+        run("step");
+        assert_eq!(run("stack"), "#0 returns to 0x202\n");
+        assert!(debugger.command(&mut cpu, "quit").is_none());
+    }
+
+    #[test]
+    fn watchpoints() {
+        let mut debugger = Debugger::new();
+        let mut cpu = CPU::new_with_memory(&MEMORY_PROGRAM).unwrap();
+        let mut run = |command| debugger.command(&mut cpu, command).unwrap();
+
+        assert_eq!(run("watch 0x303"), "Watchpoint on writes to 0x303\n");
+        assert!(run("watch 0x303").starts_with("Error"));
+        assert!(run("continue").starts_with("Watchpoint: 0x204 wrote 0x07 to 0x303\n=> 0x206:"));
+        assert!(run("continue").starts_with("Watchpoint: 0x204 wrote 0x08 to 0x303\n"));
+
+        run("delete 0x303");
+        assert_eq!(
+            run("rwatch 0x304 2"),
+            "Watchpoint on reads from 0x304..0x306\n"
+        );
+        assert!(run("continue").starts_with("Watchpoint: 0x206 read 0x00 from 0x304\n=> 0x208:"));
+        assert_eq!(
+            run("breakpoints"),
+            "Watchpoint on reads from 0x304..0x306\n"
+        );
+
+        run("delete");
+        assert_eq!(
+            run("breakpoints"),
+            "There are no breakpoints or watchpoints\n"
+        );
+        assert_eq!(run("step 2"), "=> 0x202: i := 0x300\n");
+    }
+
+    #[test]
+    fn conditional_breakpoints() {
+        let mut debugger = Debugger::new();
+        let mut cpu = CPU::new_with_memory(&MEMORY_PROGRAM).unwrap();
+
+        assert_eq!(
+            debugger.command(&mut cpu, "break 0x204 if V3 == 10"),
+            Some("Breakpoint at 0x204 if V3 == 0x0A\n".to_string())
+        );
+        debugger.command(&mut cpu, "continue");
+        assert_eq!((cpu.pc, cpu.registers[3]), (0x204, 10));
+
+        debugger.command(&mut cpu, "delete");
+        debugger.command(&mut cpu, "break if v3 >= 0x20");
+        debugger.command(&mut cpu, "continue");
+        assert_eq!((cpu.pc, cpu.registers[3]), (0x20A, 0x20));
+
+        let mut cpu = CPU::new_with_memory(&MEMORY_PROGRAM).unwrap();
+        debugger.command(&mut cpu, "delete");
+        debugger.command(&mut cpu, "break if I in 0x300..0x310");
+        debugger.command(&mut cpu, "continue");
+        assert_eq!(cpu.pc, 0x204);
+
+        for bad in [
+            "break 0x204 if V3 ~ 1",
+            "break if V3 in 0..1",
+            "break 0x204 V3",
+        ] {
+            assert!(debugger
+                .command(&mut cpu, bad)
+                .unwrap()
+                .starts_with("Error"));
+        }
+    }
+
+    #[test]
+    fn back_undoes_commands_and_frames() {
+        let mut debugger = Debugger::new();
+        let mut cpu = CPU::new_with_memory(&MEMORY_PROGRAM).unwrap();
+
+        assert!(debugger
+            .command(&mut cpu, "back")
+            .unwrap()
+            .starts_with("Error: rewinding is off"));
+
+        debugger.rewind = Some(Rewind::new(60));
+        assert_eq!(
+            debugger.command(&mut cpu, "back"),
+            Some("Error: there's nothing to go back to\n".to_string())
+        );
+
+        debugger.command(&mut cpu, "step 3");
+        debugger.command(&mut cpu, "step");
+        assert_eq!(cpu.pc, 0x208);
+        assert_eq!(
+            debugger.command(&mut cpu, "back"),
+            Some("=> 0x206: sprite v0 v0 1\n".to_string())
+        );
+
+        // A long run goes back a frame at a time
+        debugger.command(&mut cpu, "break if V3 >= 0x20");
+        debugger.command(&mut cpu, "continue");
+        let frames = cpu.frames;
+        debugger.command(&mut cpu, "delete");
+        debugger.command(&mut cpu, "back 2");
+        assert_eq!(cpu.frames, frames - 1);
+
+        debugger.command(&mut cpu, "back 1000");
+        assert_eq!(cpu.pc, 0x200);
+    }
+}
@@ -0,0 +1,509 @@
+use super::opcode::Instruction;
+use super::variant::{Platform, HIRES_HEADER};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// How many data bytes go on one line
+const BYTES_PER_LINE: usize = 8;
+
+/// The assembly language a disassembly is written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Syntax {
+    /// Octo's high level assembly, e.g. `v0 := 0x12` and `if v1 == v2 then`
+    #[default]
+    Octo,
+
+    /// The mnemonics of Cowgod's CHIP-8 technical reference, e.g. `LD V0, 0x12`
+    Cowgod,
+}
+
+impl Syntax {
+    /// Looks up a syntax by name, `octo` or `cowgod`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "octo" => Some(Syntax::Octo),
+            "cowgod" => Some(Syntax::Cowgod),
+            _ => None,
+        }
+    }
+
+    /// Starts a comment that runs to the end of the line
+    fn comment(&self) -> &'static str {
+        match self {
+            Syntax::Octo => "#",
+            Syntax::Cowgod => ";",
+        }
+    }
+}
+
+/// Why an address got a label. When an address is used in several ways the earlier kind
+/// wins, so a subroutine that's also jumped to is still named like a subroutine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LabelKind {
+    /// Where the program starts
+    Entry,
+
+    /// The target of a `2nnn` call
+    Subroutine,
+
+    /// The target of a `1nnn` or `Bnnn` jump
+    Jump,
+
+    /// An address loaded into I, usually sprites or other data
+    Data,
+}
+
+/// A ROM split into the instructions that can be reached from the entry point and the
+/// data around them
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Disassembly {
+    /// Where the ROM is loaded
+    pub load_addr: u16,
+
+    /// The ROM as the platform sees it
+    pub rom: Vec<u8>,
+
+    /// Every reachable instruction by its address
+    pub code: BTreeMap<u16, Instruction>,
+
+    /// The addresses inside the ROM that instructions refer to
+    pub labels: BTreeMap<u16, LabelKind>,
+}
+
+/// Disassembles `rom` for `platform` by following every path the program can take from the
+/// entry point. Bytes that no path reaches are data. Hires CHIP-8 ROMs are disassembled
+/// the way the emulator runs them, with the header jumping straight to the program.
+pub fn disassemble(rom: &[u8], platform: Platform) -> Disassembly {
+    let load_addr = platform.load_addr();
+    let mut rom = rom.to_vec();
+    if platform == Platform::HiresChip8 && rom.starts_with(&HIRES_HEADER) {
+        rom[..2].copy_from_slice(&[0x12, 0xC0]);
+    }
+
+    let mut disassembly = Disassembly {
+        load_addr,
+        rom,
+        code: BTreeMap::new(),
+        labels: BTreeMap::new(),
+    };
+    // Which bytes already belong to an instruction
+    let mut claimed = vec![false; disassembly.rom.len()];
+
+    disassembly.add_label(load_addr, LabelKind::Entry);
+    let mut pending = vec![load_addr];
+
+    while let Some(addr) = pending.pop() {
+        if disassembly.code.contains_key(&addr) {
+            continue;
+        }
+
+        let Some(instruction) = disassembly.decode(addr, platform) else {
+            continue;
+        };
+
+        // Instructions that would overlap an earlier one are most likely data
+        let offset = (addr - load_addr) as usize;
+        let bytes = offset..offset + instruction.size() as usize;
+        if claimed[bytes.clone()].iter().any(|&claimed| claimed) {
+            continue;
+        }
+        claimed[bytes].fill(true);
+        disassembly.code.insert(addr, instruction);
+
+        let next = addr.wrapping_add(instruction.size());
+        match instruction {
+            Instruction::Jp(target) => {
+                disassembly.add_label(target, LabelKind::Jump);
+                pending.push(target);
+            }
+
+            // Usually a jump table, whose first entry is at the target
+            Instruction::JpV0(target) => {
+                disassembly.add_label(target, LabelKind::Jump);
+                pending.push(target);
+            }
+
+            Instruction::Call(target) => {
+                disassembly.add_label(target, LabelKind::Subroutine);
+                pending.extend([target, next]);
+            }
+
+            Instruction::Ret | Instruction::Exit => {}
+
+            // Skips continue after the next instruction, which is 4 bytes long for
+            // XO-CHIP's `F000 nnnn`
+            Instruction::Se(..)
+            | Instruction::Sne(..)
+            | Instruction::SeReg(..)
+            | Instruction::SneReg(..)
+            | Instruction::Skp(_)
+            | Instruction::Sknp(_)
+            | Instruction::SkpKeypad2(_)
+            | Instruction::SknpKeypad2(_) => {
+                let skipped = disassembly
+                    .decode(next, platform)
+                    .map_or(2, |instruction| instruction.size());
+
+                pending.extend([next, next.wrapping_add(skipped)]);
+            }
+
+            Instruction::LdI(target) | Instruction::LdILong(target) => {
+                disassembly.add_label(target, LabelKind::Data);
+                pending.push(next);
+            }
+
+            _ => pending.push(next),
+        }
+    }
+
+    // A label inside an instruction can't be written, its address is used instead
+    let code = &disassembly.code;
+    disassembly.labels.retain(|&addr, _| {
+        code.range(..addr)
+            .next_back()
+            .is_none_or(|(&start, instruction)| start + instruction.size() <= addr)
+    });
+
+    disassembly
+}
+
+impl Disassembly {
+    /// Whether `addr` is inside the ROM
+    fn contains(&self, addr: u16) -> bool {
+        (self.load_addr as usize..self.load_addr as usize + self.rom.len())
+            .contains(&(addr as usize))
+    }
+
+    fn add_label(&mut self, addr: u16, kind: LabelKind) {
+        if self.contains(addr) {
+            let label = self.labels.entry(addr).or_insert(kind);
+            *label = (*label).min(kind);
+        }
+    }
+
+    /// Decodes the instruction at `addr` if it's a valid one for the platform
+    fn decode(&self, addr: u16, platform: Platform) -> Option<Instruction> {
+        let word = |addr: u16| -> Option<u16> {
+            let offset = addr.checked_sub(self.load_addr)? as usize;
+            let bytes = self.rom.get(offset..offset + 2)?;
+
+            Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+        };
+
+        let opcode = word(addr)?;
+        let next = word(addr.wrapping_add(2)).unwrap_or(0);
+
+        let instruction = platform.decode(opcode, next).ok()?;
+        if instruction == Instruction::LdILong(next) && word(addr.wrapping_add(2)).is_none() {
+            return None;
+        }
+
+        platform.supports(&instruction).then_some(instruction)
+    }
+
+    /// The name of the label at `addr`, if it has one
+    pub fn label(&self, addr: u16) -> Option<String> {
+        let name = match self.labels.get(&addr)? {
+            LabelKind::Entry => return Some("main".to_string()),
+            LabelKind::Subroutine => "sub",
+            LabelKind::Jump => "label",
+            LabelKind::Data => "data",
+        };
+
+        Some(format!("{name}_{addr:03X}"))
+    }
+
+    /// An address operand, by label if it has one
+    fn target(&self, addr: u16) -> String {
+        self.label(addr).unwrap_or_else(|| format!("0x{addr:03X}"))
+    }
+
+    /// Writes the disassembly as source code, optionally with every line's address and
+    /// bytes in a comment
+    pub fn render(&self, syntax: Syntax, addresses: bool) -> String {
+        let mut out = String::new();
+        let end = self.load_addr as usize + self.rom.len();
+        let mut addr = self.load_addr as usize;
+
+        while addr < end {
+            let offset = addr - self.load_addr as usize;
+
+            // Writing to a String can't fail
+            if let Some(label) = self.label(addr as u16) {
+                let _ = match syntax {
+                    Syntax::Octo => writeln!(out, ": {label}"),
+                    Syntax::Cowgod => writeln!(out, "{label}:"),
+                };
+            }
+
+            let (text, len) = match self.code.get(&(addr as u16)) {
+                Some(instruction) => (
+                    self.format_instruction(instruction, syntax),
+                    instruction.size() as usize,
+                ),
+                None => {
+                    // Data runs until the next line of code or label or the end of the line
+                    let len = (addr + 1..end)
+                        .take(BYTES_PER_LINE - 1)
+                        .take_while(|&next| {
+                            !self.code.contains_key(&(next as u16))
+                                && !self.labels.contains_key(&(next as u16))
+                        })
+                        .count()
+                        + 1;
+
+                    (
+                        self.format_data(&self.rom[offset..offset + len], syntax),
+                        len,
+                    )
+                }
+            };
+
+            if addresses {
+                let bytes: Vec<_> = self.rom[offset..offset + len]
+                    .iter()
+                    .map(|byte| format!("{byte:02X}"))
+                    .collect();
+
+                let _ = writeln!(
+                    out,
+                    "\t{text:<24} {} 0x{addr:03X}: {}",
+                    syntax.comment(),
+                    bytes.join(" ")
+                );
+            } else {
+                let _ = writeln!(out, "\t{text}");
+            }
+
+            addr += len;
+        }
+
+        out
+    }
+
+    fn format_data(&self, bytes: &[u8], syntax: Syntax) -> String {
+        let bytes: Vec<_> = bytes.iter().map(|byte| format!("0x{byte:02X}")).collect();
+
+        match syntax {
+            Syntax::Octo => bytes.join(" "),
+            Syntax::Cowgod => format!("db {}", bytes.join(", ")),
+        }
+    }
+
+    /// Writes one instruction in `syntax`
+    pub fn format_instruction(&self, instruction: &Instruction, syntax: Syntax) -> String {
+        match syntax {
+            Syntax::Octo => self.format_octo(instruction),
+            Syntax::Cowgod => self.format_cowgod(instruction),
+        }
+    }
+
+    fn format_octo(&self, instruction: &Instruction) -> String {
+        // Octo has no mnemonics for these, so they're written as raw bytes
+        let raw = |instruction: &Instruction| {
+            let [high, low] = instruction.encode().to_be_bytes();
+            format!("0x{high:02X} 0x{low:02X}")
+        };
+
+        match *instruction {
+            Instruction::Sys(_)
+            | Instruction::StepBackground
+            | Instruction::Colour(..)
+            | Instruction::SkpKeypad2(_)
+            | Instruction::SknpKeypad2(_) => raw(instruction),
+            Instruction::Cls => "clear".to_string(),
+            Instruction::Ret => "return".to_string(),
+            Instruction::ScrollDown(n) => format!("scroll-down {n}"),
+            Instruction::ScrollUp(n) => format!("scroll-up {n}"),
+            Instruction::ScrollRight => "scroll-right".to_string(),
+            Instruction::ScrollLeft => "scroll-left".to_string(),
+            Instruction::Exit => "exit".to_string(),
+            Instruction::Lores => "lores".to_string(),
+            Instruction::Hires => "hires".to_string(),
+            Instruction::Jp(addr) => format!("jump {}", self.target(addr)),
+            // A bare number is a data byte in Octo, so calls without a label need `:call`
+            Instruction::Call(addr) => self
+                .label(addr)
+                .unwrap_or_else(|| format!(":call 0x{addr:03X}")),
+            // Octo's conditions say when the next instruction runs, the opposite of the skip
+            Instruction::Se(x, nn) => format!("if v{x:x} != 0x{nn:02X} then"),
+            Instruction::Sne(x, nn) => format!("if v{x:x} == 0x{nn:02X} then"),
+            Instruction::SeReg(x, y) => format!("if v{x:x} != v{y:x} then"),
+            Instruction::SneReg(x, y) => format!("if v{x:x} == v{y:x} then"),
+            Instruction::Skp(x) => format!("if v{x:x} -key then"),
+            Instruction::Sknp(x) => format!("if v{x:x} key then"),
+            Instruction::SaveRange(x, y) => format!("save v{x:x} - v{y:x}"),
+            Instruction::LoadRange(x, y) => format!("load v{x:x} - v{y:x}"),
+            Instruction::Ld(x, nn) => format!("v{x:x} := 0x{nn:02X}"),
+            Instruction::Add(x, nn) => format!("v{x:x} += 0x{nn:02X}"),
+            Instruction::LdReg(x, y) => format!("v{x:x} := v{y:x}"),
+            Instruction::Or(x, y) => format!("v{x:x} |= v{y:x}"),
+            Instruction::And(x, y) => format!("v{x:x} &= v{y:x}"),
+            Instruction::Xor(x, y) => format!("v{x:x} ^= v{y:x}"),
+            Instruction::AddReg(x, y) => format!("v{x:x} += v{y:x}"),
+            Instruction::Sub(x, y) => format!("v{x:x} -= v{y:x}"),
+            Instruction::Shr(x, y) => format!("v{x:x} >>= v{y:x}"),
+            Instruction::Subn(x, y) => format!("v{x:x} =- v{y:x}"),
+            Instruction::Shl(x, y) => format!("v{x:x} <<= v{y:x}"),
+            Instruction::LdI(addr) => format!("i := {}", self.target(addr)),
+            Instruction::JpV0(addr) => format!("jump0 {}", self.target(addr)),
+            Instruction::Rnd(x, nn) => format!("v{x:x} := random 0x{nn:02X}"),
+            Instruction::Drw(x, y, n) => format!("sprite v{x:x} v{y:x} {n}"),
+            Instruction::LdILong(addr) => format!("i := long {}", self.target(addr)),
+            Instruction::Plane(n) => format!("plane {n}"),
+            Instruction::LdAudio => "audio".to_string(),
+            Instruction::LdVxDt(x) => format!("v{x:x} := delay"),
+            Instruction::LdVxK(x) => format!("v{x:x} := key"),
+            Instruction::LdDtVx(x) => format!("delay := v{x:x}"),
+            Instruction::LdStVx(x) => format!("buzzer := v{x:x}"),
+            Instruction::AddI(x) => format!("i += v{x:x}"),
+            Instruction::LdF(x) => format!("i := hex v{x:x}"),
+            Instruction::LdHf(x) => format!("i := bighex v{x:x}"),
+            Instruction::Pitch(x) => format!("pitch := v{x:x}"),
+            Instruction::LdB(x) => format!("bcd v{x:x}"),
+            Instruction::LdIVx(x) => format!("save v{x:x}"),
+            Instruction::LdVxI(x) => format!("load v{x:x}"),
+            Instruction::LdRVx(x) => format!("saveflags v{x:x}"),
+            Instruction::LdVxR(x) => format!("loadflags v{x:x}"),
+        }
+    }
+
+    fn format_cowgod(&self, instruction: &Instruction) -> String {
+        match *instruction {
+            Instruction::Sys(addr) => format!("SYS {}", self.target(addr)),
+            Instruction::Cls => "CLS".to_string(),
+            Instruction::Ret => "RET".to_string(),
+            Instruction::ScrollDown(n) => format!("SCD {n}"),
+            Instruction::ScrollUp(n) => format!("SCU {n}"),
+            Instruction::ScrollRight => "SCR".to_string(),
+            Instruction::ScrollLeft => "SCL".to_string(),
+            Instruction::Exit => "EXIT".to_string(),
+            Instruction::Lores => "LOW".to_string(),
+            Instruction::Hires => "HIGH".to_string(),
+            Instruction::StepBackground => "BGC".to_string(),
+            Instruction::Jp(addr) => format!("JP {}", self.target(addr)),
+            Instruction::Call(addr) => format!("CALL {}", self.target(addr)),
+            Instruction::Se(x, nn) => format!("SE V{x:X}, 0x{nn:02X}"),
+            Instruction::Sne(x, nn) => format!("SNE V{x:X}, 0x{nn:02X}"),
+            Instruction::SeReg(x, y) => format!("SE V{x:X}, V{y:X}"),
+            Instruction::SaveRange(x, y) => format!("SAVE V{x:X}, V{y:X}"),
+            Instruction::LoadRange(x, y) => format!("LOAD V{x:X}, V{y:X}"),
+            Instruction::Ld(x, nn) => format!("LD V{x:X}, 0x{nn:02X}"),
+            Instruction::Add(x, nn) => format!("ADD V{x:X}, 0x{nn:02X}"),
+            Instruction::LdReg(x, y) => format!("LD V{x:X}, V{y:X}"),
+            Instruction::Or(x, y) => format!("OR V{x:X}, V{y:X}"),
+            Instruction::And(x, y) => format!("AND V{x:X}, V{y:X}"),
+            Instruction::Xor(x, y) => format!("XOR V{x:X}, V{y:X}"),
+            Instruction::AddReg(x, y) => format!("ADD V{x:X}, V{y:X}"),
+            Instruction::Sub(x, y) => format!("SUB V{x:X}, V{y:X}"),
+            Instruction::Shr(x, y) => format!("SHR V{x:X}, V{y:X}"),
+            Instruction::Subn(x, y) => format!("SUBN V{x:X}, V{y:X}"),
+            Instruction::Shl(x, y) => format!("SHL V{x:X}, V{y:X}"),
+            Instruction::SneReg(x, y) => format!("SNE V{x:X}, V{y:X}"),
+            Instruction::LdI(addr) => format!("LD I, {}", self.target(addr)),
+            Instruction::JpV0(addr) => format!("JP V0, {}", self.target(addr)),
+            Instruction::Colour(x, y, n) => format!("COL V{x:X}, V{y:X}, {n}"),
+            Instruction::Rnd(x, nn) => format!("RND V{x:X}, 0x{nn:02X}"),
+            Instruction::Drw(x, y, n) => format!("DRW V{x:X}, V{y:X}, {n}"),
+            Instruction::Skp(x) => format!("SKP V{x:X}"),
+            Instruction::Sknp(x) => format!("SKNP V{x:X}"),
+            Instruction::SkpKeypad2(x) => format!("SKP2 V{x:X}"),
+            Instruction::SknpKeypad2(x) => format!("SKNP2 V{x:X}"),
+            Instruction::LdILong(addr) => format!("LD I, LONG {}", self.target(addr)),
+            Instruction::Plane(n) => format!("PLANE {n}"),
+            Instruction::LdAudio => "AUDIO".to_string(),
+            Instruction::LdVxDt(x) => format!("LD V{x:X}, DT"),
+            Instruction::LdVxK(x) => format!("LD V{x:X}, K"),
+            Instruction::LdDtVx(x) => format!("LD DT, V{x:X}"),
+            Instruction::LdStVx(x) => format!("LD ST, V{x:X}"),
+            Instruction::AddI(x) => format!("ADD I, V{x:X}"),
+            Instruction::LdF(x) => format!("LD F, V{x:X}"),
+            Instruction::LdHf(x) => format!("LD HF, V{x:X}"),
+            Instruction::Pitch(x) => format!("PITCH V{x:X}"),
+            Instruction::LdB(x) => format!("LD B, V{x:X}"),
+            Instruction::LdIVx(x) => format!("LD [I], V{x:X}"),
+            Instruction::LdVxI(x) => format!("LD V{x:X}, [I]"),
+            Instruction::LdRVx(x) => format!("LD R, V{x:X}"),
+            Instruction::LdVxR(x) => format!("LD V{x:X}, R"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Calls a subroutine that draws a sprite, then loops forever
+    const PROGRAM: [u8; 14] = [
+        0x22, 0x06, // 200: call 0x206
+        0x12, 0x04, // 202: jump 0x204 (falls through into the loop)
+        0x12, 0x04, // 204: jump 0x204
+        0xA2, 0x0C, // 206: i := 0x20C
+        0xD0, 0x01, // 208: sprite v0 v0 1
+        0x00, 0xEE, // 20A: return
+        0xFF, 0x81, // 20C: sprite data
+    ];
+
+    #[test]
+    fn follows_calls_and_jumps() {
+        let disassembly = disassemble(&PROGRAM, Platform::Chip8);
+
+        assert_eq!(
+            disassembly.code.keys().copied().collect::<Vec<_>>(),
+            [0x200, 0x202, 0x204, 0x206, 0x208, 0x20A]
+        );
+        assert_eq!(disassembly.labels[&0x206], LabelKind::Subroutine);
+        assert_eq!(disassembly.labels[&0x20C], LabelKind::Data);
+    }
+
+    #[test]
+    fn octo_listing() {
+        let listing = disassemble(&PROGRAM, Platform::Chip8).render(Syntax::Octo, false);
+
+        assert_eq!(
+            listing,
+            ": main\n\tsub_206\n\tjump label_204\n: label_204\n\tjump label_204\n\
+             : sub_206\n\ti := data_20C\n\tsprite v0 v0 1\n\treturn\n: data_20C\n\t0xFF 0x81\n"
+        );
+    }
+
+    #[test]
+    fn cowgod_listing() {
+        let listing = disassemble(&PROGRAM, Platform::Chip8).render(Syntax::Cowgod, true);
+
+        assert!(listing.contains(&format!("\t{:<24} ; 0x200: 22 06\n", "CALL sub_206")));
+        assert!(listing.contains("data_20C:\n\tdb 0xFF, 0x81"));
+    }
+
+    #[test]
+    fn skips_can_step_over_long_instructions() {
+        let program = [
+            0x30, 0x00, // se v0, 0
+            0xF0, 0x00, 0x02, 0x0A, // i := long 0x20A
+            0x00, 0xE0, // clear
+            0x00, 0xFD, // exit
+            0x42, // data
+        ];
+
+        let disassembly = disassemble(&program, Platform::XoChip);
+
+        assert_eq!(disassembly.code[&0x202], Instruction::LdILong(0x20A));
+        assert!(disassembly.code.contains_key(&0x206));
+        assert!(!disassembly.code.contains_key(&0x20A));
+
+        // Without XO-CHIP F000 isn't an instruction, the skip leads to 0x204 instead
+        let disassembly = disassemble(&program, Platform::Chip8);
+        assert!(!disassembly.code.contains_key(&0x202));
+    }
+
+    #[test]
+    fn unsupported_instructions_are_data() {
+        let disassembly = disassemble(&[0x00, 0xFF], Platform::Chip8);
+
+        assert!(disassembly.code.is_empty());
+        assert_eq!(
+            disassembly.render(Syntax::Cowgod, false),
+            "main:\n\tdb 0x00, 0xFF\n"
+        );
+    }
+}
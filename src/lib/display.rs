@@ -1,73 +1,229 @@
 use super::cpu::CPU;
+use super::error::Chip8Error;
+use std::ops::Range;
 
-const WIDTH: u8 = 64;
+/// The colours of CHIP-8X's VP-590 colour board. Foreground colours are 0 black, 1 red,
+/// 2 blue, 3 violet, 4 green, 5 yellow, 6 aqua and 7 white. Background colours are 0 blue,
+/// 1 black, 2 green and 3 red.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Colours {
+    /// The foreground colour of every 8x1 pixel block, row by row
+    pub foreground: Vec<u8>,
+
+    /// The background colour of the whole screen
+    pub background: u8,
+}
+
+impl Colours {
+    /// Red on black for a `width` by `height` screen
+    pub fn new(width: usize, height: usize) -> Self {
+        Colours {
+            foreground: vec![1; width.div_ceil(8) * height],
+            background: 1,
+        }
+    }
+
+    /// The foreground colour of the pixel at (x, y) on a screen `width` pixels wide
+    pub fn foreground_at(&self, x: usize, y: usize, width: usize) -> u8 {
+        self.foreground
+            .get(x / 8 + y * width.div_ceil(8))
+            .copied()
+            .unwrap_or(1)
+    }
+}
 
 impl CPU {
-    /// Clears the display
+    /// Clears the selected bitplanes of the display
     pub fn clear(&mut self) {
-        self.buf = [0; 2048];
-        self.update();
+        let planes = self.planes;
+
+        for pixel in self.buf.iter_mut() {
+            *pixel &= !planes;
+        }
+        self.dirty = true;
     }
 
-    #[cfg(feature = "show_commands")]
-    pub fn update(&mut self) {}
+    /// Switches between the platform's low and high resolution screens, which clears the
+    /// display. Platforms without a high resolution mode stay in low resolution.
+    pub fn set_hires(&mut self, hires: bool) {
+        let lores = self.platform.lores_size();
+        let (width, height) = if hires {
+            self.platform.hires_size().unwrap_or(lores)
+        } else {
+            lores
+        };
+
+        self.width = width;
+        self.height = height;
+        self.buf = vec![0; width * height];
+        self.dirty = true;
+    }
+
+    /// Whether the screen is currently in high resolution mode
+    pub fn is_hires(&self) -> bool {
+        self.platform.hires_size() == Some((self.width, self.height))
+    }
 
-    #[cfg(not(feature = "show_commands"))]
+    /// Shows the frame buffer on the display if it changed since the last update
     pub fn update(&mut self) {
-        print!("\x1B[2J\x1B[1;1H");
-        for (i, item) in self.buf.iter_mut().enumerate() {
-            if i != 0 && (i - 1) % (WIDTH as usize) == 0 {
-                println!();
-            }
-            if *item == 1 {
-                print!("■");
-            } else {
-                print!(" ");
+        if !self.dirty {
+            return;
+        }
+
+        if let Some(colours) = &self.colours {
+            self.display.set_colours(colours);
+        }
+        self.display.present(&self.buf, self.width, self.height);
+        self.dirty = false;
+    }
+
+    /// Steps the CHIP-8X background through its 4 colours
+    pub fn step_background(&mut self) {
+        if let Some(colours) = &mut self.colours {
+            colours.background = (colours.background + 1) % 4;
+            self.dirty = true;
+        }
+    }
+
+    /// Sets the CHIP-8X foreground colour of the 8x1 pixel blocks in `columns` (counted in
+    /// blocks) and `rows` (counted in pixels). Blocks off the screen are ignored.
+    pub fn set_colour(&mut self, columns: Range<usize>, rows: Range<usize>, colour: u8) {
+        let blocks_per_row = self.width.div_ceil(8);
+        let height = self.height;
+
+        let Some(colours) = &mut self.colours else {
+            return;
+        };
+
+        for row in rows.start.min(height)..rows.end.min(height) {
+            for column in columns.start.min(blocks_per_row)..columns.end.min(blocks_per_row) {
+                colours.foreground[column + row * blocks_per_row] = colour & 0x7;
             }
         }
-        println!();
+        self.dirty = true;
     }
 
-    pub fn draw(&mut self, x: u8, y: u8, n: usize) {
-        let x = self.registers[x as usize] % 64;
-        let y = self.registers[y as usize] % 32;
-        self.vf = 0;
-
-        let sprite = &self.mem[(self.i_reg as usize)..(self.i_reg as usize + n as usize)];
-
-        // Convert the coordinates to an index in the frame buffer
-        let mut pos_in_buf = x as usize + (WIDTH as usize * y as usize);
-
-        // Loop through each row in the sprite
-        sprite.iter().for_each(|byte_row| {
-            // This loop pushes the bits one by one to the right for each iteration,
-            // see if it's on or off (using the & 1) and then write it to the frame
-            // buffer
-            for j in (0..8).rev() {
-                // The current bit value, can be 1 or 0
-                let current_bit_value = (byte_row >> j) & 1;
-
-                // Set VF to 1 if the current bit is already on and the updated bit is also on.
-                // Also turn off the bit.
-                if (self.buf[pos_in_buf] == 1) && (current_bit_value == 1) {
-                    self.vf = 1;
-                    self.buf[pos_in_buf] = 0;
+    /// Moves every pixel down by `n` rows, rows scrolled in at the top are blank
+    pub fn scroll_down(&mut self, n: usize) {
+        self.shift(0, n as isize);
+    }
+
+    /// Moves every pixel up by `n` rows, rows scrolled in at the bottom are blank
+    pub fn scroll_up(&mut self, n: usize) {
+        self.shift(0, -(n as isize));
+    }
+
+    /// Moves every pixel right by `n` columns, columns scrolled in on the left are blank
+    pub fn scroll_right(&mut self, n: usize) {
+        self.shift(n as isize, 0);
+    }
+
+    /// Moves every pixel left by `n` columns, columns scrolled in on the right are blank
+    pub fn scroll_left(&mut self, n: usize) {
+        self.shift(-(n as isize), 0);
+    }
+
+    /// Moves the selected bitplanes by `dx` columns and `dy` rows, the other planes stay
+    /// where they are
+    fn shift(&mut self, dx: isize, dy: isize) {
+        let planes = self.planes;
+        let (width, height) = (self.width as isize, self.height as isize);
+        let old = self.buf.clone();
+
+        for y in 0..height {
+            for x in 0..width {
+                let (from_x, from_y) = (x - dx, y - dy);
+
+                let moved = if (0..width).contains(&from_x) && (0..height).contains(&from_y) {
+                    old[(from_x + from_y * width) as usize] & planes
                 } else {
-                    // Just write to the display buffer by default.
-                    self.buf[pos_in_buf] = current_bit_value;
-                }
+                    0
+                };
+
+                let pixel = &mut self.buf[(x + y * width) as usize];
+                *pixel = (*pixel & !planes) | moved;
+            }
+        }
+        self.dirty = true;
+    }
+
+    pub fn draw(&mut self, x: u8, y: u8, n: usize) -> Result<(), Chip8Error> {
+        let x = self.registers[x as usize] as usize % self.width;
+        let y = self.registers[y as usize] as usize % self.height;
+        let mut collision = 0;
+
+        // SUPER-CHIP draws a 16x16 sprite, two bytes per row, when n is 0
+        let (sprite_width, rows) = if n == 0 && self.platform.has_superchip() {
+            (16, 16)
+        } else {
+            (8, n)
+        };
+        let bytes_per_row = sprite_width / 8;
 
-                // If we reached the end of the screen, stop rendering the row
-                if (pos_in_buf % (WIDTH as usize)) == 0 {
-                    break;
+        let sprite_len = rows * bytes_per_row;
+
+        // XO-CHIP draws one sprite per selected bitplane, stored one after the other
+        let planes: Vec<u8> = (0..self.platform.planes())
+            .map(|bit| 1 << bit)
+            .filter(|plane| self.planes & plane != 0)
+            .collect();
+
+        let sprite = self.read_mem(self.i_reg, sprite_len * planes.len())?;
+        self.dirty = true;
+
+        for (&plane, plane_sprite) in planes.iter().zip(sprite.chunks(sprite_len.max(1))) {
+            // Loop through each row in the sprite
+            for (row, row_bytes) in plane_sprite.chunks(bytes_per_row).enumerate() {
+                let mut pixel_y = y + row;
+
+                // Rows that fall off the bottom are either clipped or wrapped to the top
+                if pixel_y >= self.height {
+                    if self.quirks.clip_sprites {
+                        break;
+                    }
+                    pixel_y %= self.height;
                 }
 
-                // Incrementing X coordinate
-                pos_in_buf += 1;
+                // Join the bytes of the row so the leftmost pixel is the highest bit
+                let bits = row_bytes
+                    .iter()
+                    .fold(0u16, |bits, &byte| bits << 8 | byte as u16);
+
+                // This loop pushes the bits one by one to the right for each iteration,
+                // see if it's on or off (using the & 1) and then write it to the frame
+                // buffer
+                for j in 0..sprite_width {
+                    let mut pixel_x = x + j;
+
+                    // Same as above but for the right edge of the screen
+                    if pixel_x >= self.width {
+                        if self.quirks.clip_sprites {
+                            break;
+                        }
+                        pixel_x %= self.width;
+                    }
+
+                    // The current bit value, can be 1 or 0
+                    let current_bit_value = (bits >> (sprite_width - 1 - j)) & 1;
+
+                    if current_bit_value == 0 {
+                        continue;
+                    }
+
+                    // Convert the coordinates to an index in the frame buffer
+                    let pos_in_buf = pixel_x + (self.width * pixel_y);
+
+                    // Set VF to 1 if the current bit is already on, XOR-ing turns it off
+                    if self.buf[pos_in_buf] & plane != 0 {
+                        collision = 1;
+                    }
+                    self.buf[pos_in_buf] ^= plane;
+                }
             }
+        }
+
+        self.registers[0xF] = collision;
 
-            // Incrementing Y coordinate
-            pos_in_buf += (WIDTH - 8) as usize;
-        });
+        Ok(())
     }
 }
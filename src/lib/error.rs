@@ -0,0 +1,59 @@
+use std::{fmt, io};
+
+/// Errors that can occur while loading or running a CHIP-8 program
+#[derive(Debug)]
+pub enum Chip8Error {
+    /// The bytes at `pc` don't form a known instruction
+    InvalidOpcode { pc: u16, opcode: u16 },
+
+    /// A subroutine was called with all stack entries in use
+    StackOverflow,
+
+    /// A subroutine returned with nothing on the stack
+    StackUnderflow,
+
+    /// An instruction tried to access memory past the end of RAM
+    MemoryOutOfBounds { addr: u16 },
+
+    /// The ROM doesn't fit in the memory available to programs
+    RomTooLarge { len: usize },
+
+    /// Reading a ROM or input failed
+    Io(io::Error),
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Chip8Error::InvalidOpcode { pc, opcode } => {
+                write!(f, "invalid opcode 0x{opcode:04X} at 0x{pc:03X}")
+            }
+            Chip8Error::StackOverflow => {
+                write!(f, "stack overflow, too many nested subroutine calls")
+            }
+            Chip8Error::StackUnderflow => {
+                write!(f, "stack underflow, returned from outside a subroutine")
+            }
+            Chip8Error::MemoryOutOfBounds { addr } => {
+                write!(f, "memory access out of bounds at 0x{addr:03X}")
+            }
+            Chip8Error::RomTooLarge { len } => write!(f, "ROM is too large ({len} bytes)"),
+            Chip8Error::Io(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for Chip8Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Chip8Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Chip8Error {
+    fn from(err: io::Error) -> Self {
+        Chip8Error::Io(err)
+    }
+}
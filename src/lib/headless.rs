@@ -0,0 +1,337 @@
+use super::cpu::CPU;
+use super::error::Chip8Error;
+use super::movie::KeyEvent;
+use std::fmt::{self, Write};
+
+/// How long a headless run waiting for `until_pc` goes on by default, one emulated minute
+pub const DEFAULT_HEADLESS_FRAMES: u64 = 60 * 60;
+
+/// The grey level of every pixel value in dumped PNGs: off, plane 1, plane 2 and both
+const PNG_GREYS: [u8; 4] = [0x00, 0xFF, 0xAA, 0x55];
+
+/// Why a headless run stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    /// All the frames ran
+    FrameLimit,
+
+    /// The program counter reached `until_pc`, the instruction there hasn't run yet
+    ReachedPc,
+
+    /// The program ran `00FD`
+    Exited,
+}
+
+impl Stop {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stop::FrameLimit => "frames",
+            Stop::ReachedPc => "pc",
+            Stop::Exited => "exited",
+        }
+    }
+}
+
+/// Runs a ROM with nothing attached but whatever keypad the CPU already has
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeadlessRun {
+    /// The most frames to run
+    pub frames: u64,
+
+    /// Stop as soon as the program counter gets here
+    pub until_pc: Option<u16>,
+}
+
+impl HeadlessRun {
+    pub fn run(&self, cpu: &mut CPU) -> Result<Stop, Chip8Error> {
+        let until_pc = self.until_pc;
+
+        for _ in 0..self.frames {
+            if cpu.run_frame_until(|cpu| Some(cpu.pc) == until_pc)? {
+                return Ok(Stop::ReachedPc);
+            }
+
+            if cpu.exited {
+                return Ok(Stop::Exited);
+            }
+        }
+
+        Ok(Stop::FrameLimit)
+    }
+}
+
+/// An error in an input script, `line` counts from 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptError {
+    pub line: usize,
+    pub message: &'static str,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+/// Parses an input script into the key changes of a movie. Every line holds a frame number
+/// followed by the hex keys held from that frame on, a frame without keys releases them
+/// all. Empty lines and lines starting with `#` are skipped.
+///
+/// ```text
+/// # Hold 5 for a second, then press 5 and 6 together
+/// 60 5
+/// 120
+/// 180 5 6
+/// 190
+/// ```
+pub fn parse_input_script(script: &str) -> Result<Vec<KeyEvent>, ScriptError> {
+    let mut events: Vec<KeyEvent> = Vec::new();
+
+    for (i, line) in script.lines().enumerate() {
+        let error = |message| ScriptError {
+            line: i + 1,
+            message,
+        };
+
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut words = line.split_whitespace();
+        let frame = words
+            .next()
+            .and_then(|frame| frame.parse().ok())
+            .ok_or(error("expected a frame number"))?;
+
+        if events.last().is_some_and(|event| event.frame >= frame) {
+            return Err(error("frames have to go up"));
+        }
+
+        let keys = words.try_fold(0u32, |keys, key| match u8::from_str_radix(key, 16) {
+            Ok(key) if key < 16 => Ok(keys | 1 << key),
+            _ => Err(error("keys are hex digits from 0 to F")),
+        })?;
+
+        events.push(KeyEvent { frame, keys });
+    }
+
+    Ok(events)
+}
+
+/// The frame buffer as text, one line per row. Pixels that are off are `.`, pixels that
+/// are on are `#`, or the pixel value from 1 to 3 when XO-CHIP's second plane is in use.
+pub fn screen_text(cpu: &CPU) -> String {
+    let mut text = String::with_capacity((cpu.width + 1) * cpu.height);
+    let planes = cpu.platform.planes();
+
+    for row in cpu.buf.chunks(cpu.width) {
+        for &pixel in row {
+            text.push(match pixel {
+                0 => '.',
+                1 if planes == 1 => '#',
+                _ => char::from(b'0' + pixel.min(9)),
+            });
+        }
+        text.push('\n');
+    }
+
+    text
+}
+
+/// The frame buffer as an 8 bit greyscale PNG with one image pixel per screen pixel
+pub fn screen_png(cpu: &CPU) -> Vec<u8> {
+    let mut header = Vec::new();
+    header.extend_from_slice(&(cpu.width as u32).to_be_bytes());
+    header.extend_from_slice(&(cpu.height as u32).to_be_bytes());
+    // 8 bits per pixel, greyscale, the only compression, filter and interlace methods
+    header.extend_from_slice(&[8, 0, 0, 0, 0]);
+
+    // Every row starts with the filter type, 0 for none
+    let mut pixels = Vec::with_capacity((cpu.width + 1) * cpu.height);
+    for row in cpu.buf.chunks(cpu.width) {
+        pixels.push(0);
+        pixels.extend(row.iter().map(|&pixel| PNG_GREYS[pixel as usize & 3]));
+    }
+
+    let mut png = vec![0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+    png_chunk(&mut png, b"IHDR", &header);
+    png_chunk(&mut png, b"IDAT", &zlib_stored(&pixels));
+    png_chunk(&mut png, b"IEND", &[]);
+
+    png
+}
+
+fn png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Wraps `data` in a zlib stream of uncompressed deflate blocks. Screens are tiny, so
+/// compressing them isn't worth it.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut zlib = vec![0x78, 0x01];
+
+    let mut blocks = data.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        zlib.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+
+        zlib.push(last as u8);
+        zlib.extend_from_slice(&len.to_le_bytes());
+        zlib.extend_from_slice(&(!len).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+
+    let (a, b) = data.iter().fold((1u32, 0u32), |(a, b), &byte| {
+        let a = (a + byte as u32) % 65521;
+        (a, (b + a) % 65521)
+    });
+    zlib.extend_from_slice(&(b << 16 | a).to_be_bytes());
+
+    zlib
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| {
+            (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg())
+        })
+    })
+}
+
+/// The registers and the rest of the CPU state as a JSON object
+pub fn registers_json(cpu: &CPU, stop: Stop) -> String {
+    let list = |values: &mut dyn Iterator<Item = u16>| {
+        values
+            .map(|value| value.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    let mut json = String::from("{\n");
+    // Writing to a String can't fail
+    let _ = writeln!(json, "  \"stop\": \"{}\",", stop.name());
+    let _ = writeln!(json, "  \"pc\": {},", cpu.pc);
+    let _ = writeln!(json, "  \"i\": {},", cpu.i_reg);
+    let _ = writeln!(
+        json,
+        "  \"v\": [{}],",
+        list(&mut cpu.registers.iter().map(|&v| v as u16))
+    );
+    let _ = writeln!(json, "  \"sp\": {},", cpu.sp);
+    let _ = writeln!(
+        json,
+        "  \"stack\": [{}],",
+        list(&mut cpu.stack[..cpu.sp as usize].iter().copied())
+    );
+    let _ = writeln!(json, "  \"delay\": {},", cpu.timers.delay);
+    let _ = writeln!(json, "  \"sound\": {},", cpu.timers.sound);
+    let _ = writeln!(json, "  \"cycles\": {},", cpu.cycles);
+    let _ = writeln!(json, "  \"frames\": {},", cpu.frames);
+    let _ = writeln!(json, "  \"exited\": {}", cpu.exited);
+    json.push('}');
+
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stops_at_pc() {
+        // ADD V0, 1; SE V0, 3; JP 0x200; JP 0x206
+        let program = [0x70, 0x01, 0x30, 0x03, 0x12, 0x00, 0x12, 0x06];
+        let mut cpu = CPU::new_with_memory(&program).unwrap();
+
+        let run = HeadlessRun {
+            frames: 10,
+            until_pc: Some(0x206),
+        };
+
+        assert_eq!(run.run(&mut cpu).unwrap(), Stop::ReachedPc);
+        assert_eq!(cpu.registers[0], 3);
+        assert_eq!(cpu.pc, 0x206);
+    }
+
+    #[test]
+    fn stops_after_frames() {
+        let mut cpu = CPU::new_with_memory(&[0x12, 0x00]).unwrap();
+
+        let run = HeadlessRun {
+            frames: 5,
+            until_pc: Some(0x300),
+        };
+
+        assert_eq!(run.run(&mut cpu).unwrap(), Stop::FrameLimit);
+        assert_eq!(cpu.frames, 5);
+    }
+
+    #[test]
+    fn input_scripts() {
+        let script = "# comment\n\n10 5 a\n20\n";
+
+        assert_eq!(
+            parse_input_script(script),
+            Ok(vec![
+                KeyEvent {
+                    frame: 10,
+                    keys: 1 << 5 | 1 << 0xA
+                },
+                KeyEvent { frame: 20, keys: 0 },
+            ])
+        );
+        assert_eq!(
+            parse_input_script("10\n5 1").unwrap_err(),
+            ScriptError {
+                line: 2,
+                message: "frames have to go up"
+            }
+        );
+        assert!(parse_input_script("10 G").is_err());
+    }
+
+    #[test]
+    fn text_dump() {
+        let mut cpu = CPU::new();
+        cpu.buf[1] = 1;
+
+        let text = screen_text(&cpu);
+
+        assert_eq!(text.lines().count(), 32);
+        assert!(text.starts_with(".#......"));
+    }
+
+    #[test]
+    fn png_dump() {
+        let png = screen_png(&CPU::new());
+
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&png[12..16], b"IHDR");
+        assert!(png.ends_with(&[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]));
+    }
+
+    #[test]
+    fn registers_dump() {
+        let mut cpu = CPU::new();
+        cpu.registers[1] = 7;
+
+        let json = registers_json(&cpu, Stop::Exited);
+
+        assert!(json.contains("\"stop\": \"exited\""));
+        assert!(json.contains("\"v\": [0, 7, 0"));
+        assert!(json.contains("\"stack\": []"));
+    }
+}
@@ -1,236 +1,278 @@
-#![allow(unused_imports)]
 use super::cpu::CPU;
-use rand::Rng;
-use std::{
-    io::{stdin, Read},
-    thread::sleep,
-    time::{Duration, SystemTime, UNIX_EPOCH},
-};
+use super::error::Chip8Error;
 
 impl CPU {
     /// Clear the display.
     pub fn cls00e0(&mut self) {
-        #[cfg(feature = "simulate_frequency")]
-        sleep(Duration::from_micros(109));
-
         self.clear();
     }
 
+    /// Scroll the display down by n pixels.
+    pub fn scd00cn(&mut self, n: u8) {
+        self.scroll_down(n as usize);
+    }
+
+    /// Step the background to the next colour.
+    pub fn bgc02a0(&mut self) {
+        self.step_background();
+    }
+
+    /// Set the foreground colour of an area to Vy.
+    pub fn colbxyn(&mut self, x: u8, y: u8, n: u8) {
+        let horizontal = self.registers[x as usize] as usize;
+        let vertical = self.registers[(x as usize + 1) & 0xF] as usize;
+        let colour = self.registers[y as usize];
+
+        if n == 0 {
+            // Zones are 8x4 pixels, the nibbles give the first and last zone
+            let columns = (horizontal & 0xF)..((horizontal >> 4) + 1);
+            let rows = (vertical & 0xF) * 4..((vertical >> 4) + 1) * 4;
+            self.set_colour(columns, rows, colour);
+        } else {
+            let column = horizontal / 8;
+            self.set_colour(
+                column..(column + 1),
+                vertical..(vertical + n as usize),
+                colour,
+            );
+        }
+    }
+
+    /// Scroll the display up by n pixels.
+    pub fn scu00dn(&mut self, n: u8) {
+        self.scroll_up(n as usize);
+    }
+
+    /// Scroll the display right by 4 pixels.
+    pub fn scr00fb(&mut self) {
+        self.scroll_right(4);
+    }
+
+    /// Scroll the display left by 4 pixels.
+    pub fn scl00fc(&mut self) {
+        self.scroll_left(4);
+    }
+
+    /// Exit the interpreter.
+    pub fn exit00fd(&mut self) {
+        self.exited = true;
+    }
+
+    /// Switch to low resolution mode.
+    pub fn low00fe(&mut self) {
+        self.set_hires(false);
+    }
+
+    /// Switch to high resolution mode.
+    pub fn high00ff(&mut self) {
+        self.set_hires(true);
+    }
+
     /// Jump to location nnn.   
     pub fn jp1nnn(&mut self, nnn: u16) {
-        #[cfg(feature = "simulate_frequency")]
-        sleep(Duration::from_micros(105));
-
         self.pc = nnn;
     }
 
     /// Set Vx = nn.
     pub fn set6xnn(&mut self, x: u8, nn: u8) {
-        #[cfg(feature = "simulate_frequency")]
-        sleep(Duration::from_micros(27));
-
         self.registers[x as usize] = nn;
     }
 
     /// Set Vx = Vx + nn.
     pub fn add7xnn(&mut self, x: u8, nn: u8) {
-        #[cfg(feature = "simulate_frequency")]
-        sleep(Duration::from_micros(1000));
         let sum: u16 = self.registers[x as usize] as u16 + nn as u16;
         self.registers[x as usize] = (sum & 0xFF) as u8;
     }
 
     /// Set I = nnn.
     pub fn setannn(&mut self, nnn: u16) {
-        #[cfg(feature = "simulate_frequency")]
-        sleep(Duration::from_micros(55));
-
         self.i_reg = nnn;
     }
 
     /// Display n-byte sprite starting at memory location I at (Vx, Vy), set VF =  collision.
-    pub fn drwdxyn(&mut self, x: u8, y: u8, n: u8) {
-        #[cfg(feature = "simulate_frequency")]
-        sleep(Duration::from_micros(22734));
-
-        self.draw(x, y, n as usize);
+    pub fn drwdxyn(&mut self, x: u8, y: u8, n: u8) -> Result<(), Chip8Error> {
+        self.draw(x, y, n as usize)
     }
 
     /// Return from a subroutine.
-    pub fn ret00ee(&mut self) {
-        #[cfg(feature = "simulate_frequency")]
-        sleep(Duration::from_micros(105));
+    pub fn ret00ee(&mut self) -> Result<(), Chip8Error> {
+        if self.sp == 0 {
+            return Err(Chip8Error::StackUnderflow);
+        }
 
-        self.pc = self.stack[self.sp as usize];
         self.sp -= 1;
+
+        // Programs can overwrite a stack that lives in memory, so memory has the final say
+        if let Some(stack_addr) = self.platform.stack_addr() {
+            let bytes = self.read_mem(stack_addr + self.sp as u16 * 2, 2)?;
+            self.stack[self.sp as usize] = u16::from_be_bytes([bytes[0], bytes[1]]);
+        }
+        self.pc = self.stack[self.sp as usize];
+
+        Ok(())
     }
 
     /// Call subroutine at nnn.
-    pub fn call2nnn(&mut self, nnn: u16) {
-        #[cfg(feature = "simulate_frequency")]
-        sleep(Duration::from_micros(105));
+    pub fn call2nnn(&mut self, nnn: u16) -> Result<(), Chip8Error> {
+        if self.sp as usize >= self.platform.stack_depth() {
+            return Err(Chip8Error::StackOverflow);
+        }
 
-        self.sp += 1;
         self.stack[self.sp as usize] = self.pc;
+        if let Some(stack_addr) = self.platform.stack_addr() {
+            self.write_mem(stack_addr + self.sp as u16 * 2, &self.pc.to_be_bytes())?;
+        }
+        self.sp += 1;
 
         self.pc = nnn;
+
+        Ok(())
+    }
+
+    /// Skips over the next instruction. On XO-CHIP `F000 nnnn` is 4 bytes long and is
+    /// skipped as a whole.
+    pub fn skip_next(&mut self) {
+        let long = self.platform.has_xochip() && matches!(self.fetch(), Ok(0xF000));
+
+        self.pc = self.pc.wrapping_add(if long { 4 } else { 2 });
     }
 
     /// Skip next instruction if Vx = nn.
     pub fn se3xnn(&mut self, x: u8, nn: u8) {
-        #[cfg(feature = "simulate_frequency")]
-        sleep(Duration::from_micros(55));
-
         if self.registers[x as usize] == nn {
-            self.pc += 2;
+            self.skip_next();
         }
     }
 
     /// Skip next instruction if Vx != nn.
     pub fn sne4xnn(&mut self, x: u8, nn: u8) {
-        #[cfg(feature = "simulate_frequency")]
-        sleep(Duration::from_micros(55));
-
         if self.registers[x as usize] != nn {
-            self.pc += 2;
+            self.skip_next();
         }
     }
 
     /// Skip next instruction if Vx = Vy.
     pub fn se5xy0(&mut self, x: u8, y: u8) {
-        #[cfg(feature = "simulate_frequency")]
-        sleep(Duration::from_micros(73));
-
         if self.registers[x as usize] == self.registers[y as usize] {
-            self.pc += 2;
+            self.skip_next();
         }
     }
 
     /// Skip next instruction if Vx != Vy.
     pub fn sne9xy0(&mut self, x: u8, y: u8) {
-        #[cfg(feature = "simulate_frequency")]
-        sleep(Duration::from_micros(73));
-
         if self.registers[x as usize] != self.registers[y as usize] {
-            self.pc += 2;
+            self.skip_next();
+        }
+    }
+
+    /// Store registers Vx through Vy in memory starting at location I. Registers are
+    /// stored in reverse order when x > y.
+    pub fn save5xy2(&mut self, x: u8, y: u8) -> Result<(), Chip8Error> {
+        let values: Vec<u8> = register_range(x, y)
+            .map(|register| self.registers[register])
+            .collect();
+
+        self.write_mem(self.i_reg, &values)
+    }
+
+    /// Read registers Vx through Vy from memory starting at location I. Registers are
+    /// read in reverse order when x > y.
+    pub fn load5xy3(&mut self, x: u8, y: u8) -> Result<(), Chip8Error> {
+        let bytes = self.read_mem(self.i_reg, x.abs_diff(y) as usize + 1)?;
+
+        for (value, register) in bytes.into_iter().zip(register_range(x, y)) {
+            self.registers[register] = value;
         }
+
+        Ok(())
     }
 
     /// Stores the value of register Vy in register Vx.
     pub fn ld8xy0(&mut self, x: u8, y: u8) {
-        #[cfg(feature = "simulate_frequency")]
-        sleep(Duration::from_micros(200));
-
         self.registers[x as usize] = self.registers[y as usize];
     }
 
     /// Set Vx = Vx OR Vy.
     pub fn or8xy1(&mut self, x: u8, y: u8) {
-        #[cfg(feature = "simulate_frequency")]
-        sleep(Duration::from_micros(200));
-
         self.registers[x as usize] |= self.registers[y as usize];
+
+        if self.quirks.logic_resets_vf {
+            self.registers[0xF] = 0;
+        }
     }
 
     /// Set Vx = Vx AND Vy.
     pub fn and8xy2(&mut self, x: u8, y: u8) {
-        #[cfg(feature = "simulate_frequency")]
-        sleep(Duration::from_micros(200));
-
         self.registers[x as usize] &= self.registers[y as usize];
+
+        if self.quirks.logic_resets_vf {
+            self.registers[0xF] = 0;
+        }
     }
 
     /// Set Vx = Vx XOR Vy.
     pub fn xor8xy3(&mut self, x: u8, y: u8) {
-        #[cfg(feature = "simulate_frequency")]
-        sleep(Duration::from_micros(200));
-
         self.registers[x as usize] ^= self.registers[y as usize];
+
+        if self.quirks.logic_resets_vf {
+            self.registers[0xF] = 0;
+        }
     }
 
     /// Set Vx = Vx + Vy, set VF = carry.
     pub fn add8xy4(&mut self, x: u8, y: u8) {
-        #[cfg(feature = "simulate_frequency")]
-        sleep(Duration::from_micros(200));
-
-        // Set VF to 0
-        self.vf = 0;
-
         // The sum casted into a u16
         let sum = self.registers[x as usize] as u16 + self.registers[y as usize] as u16;
 
-        // If the sum overflows set VF to 1 and only write the first byte to Vx
-        if sum > 255 {
-            self.vf = 1;
-            self.registers[x as usize] = (sum & 0xFF) as u8;
-        } else {
-            self.registers[x as usize] = sum as u8;
-        }
+        // Only write the first byte to Vx, VF is written last so the carry wins when x is F
+        self.registers[x as usize] = (sum & 0xFF) as u8;
+        self.registers[0xF] = (sum > 255) as u8;
     }
 
     /// Set Vx = Vx - Vy, set VF = NOT borrow.
     pub fn sub8xy5(&mut self, x: u8, y: u8) {
-        #[cfg(feature = "simulate_frequency")]
-        sleep(Duration::from_micros(200));
-
-        let difference = self.registers[x as usize] as i16 - self.registers[y as usize] as i16;
-        self.vf = 0;
+        let (difference, borrow) =
+            self.registers[x as usize].overflowing_sub(self.registers[y as usize]);
 
-        if difference > 0 {
-            self.vf = 1;
-        }
-        self.registers[x as usize] = difference as u8;
+        self.registers[x as usize] = difference;
+        self.registers[0xF] = !borrow as u8;
     }
 
     /// Set Vx = Vy - Vx, set VF = NOT borrow.
     pub fn sub8xy7(&mut self, x: u8, y: u8) {
-        #[cfg(feature = "simulate_frequency")]
-        sleep(Duration::from_micros(200));
-
-        let difference = self.registers[y as usize] as i16 - self.registers[x as usize] as i16;
-        self.vf = 0;
+        let (difference, borrow) =
+            self.registers[y as usize].overflowing_sub(self.registers[x as usize]);
 
-        if difference > 0 {
-            self.vf = 1;
-        }
-        self.registers[x as usize] = difference as u8;
+        self.registers[x as usize] = difference;
+        self.registers[0xF] = !borrow as u8;
     }
 
     /// Set Vx = Vy SHR 1, set VF = shifted bit
     pub fn shr8xy6_usey(&mut self, x: u8, y: u8) {
-        #[cfg(feature = "simulate_frequency")]
-        sleep(Duration::from_micros(200));
-
         // Get the last bit
         let last_bit = self.registers[y as usize] & 1;
 
         // The shifted bit
         let y_shifted = self.registers[y as usize] >> 1;
 
-        self.vf = last_bit;
         self.registers[x as usize] = y_shifted;
+        self.registers[0xF] = last_bit;
     }
 
     /// Set Vx = Vx SHR 1, set VF = shifted bit
     pub fn shr8xy6_usex(&mut self, x: u8, _y: u8) {
-        #[cfg(feature = "simulate_frequency")]
-        sleep(Duration::from_micros(200));
-
         // Get the last bit
         let last_bit = self.registers[x as usize] & 1;
 
         // The shifted bit
         let x_shifted = self.registers[x as usize] >> 1;
 
-        self.vf = last_bit;
         self.registers[x as usize] = x_shifted;
+        self.registers[0xF] = last_bit;
     }
 
     /// Set Vx = Vy SHL 1, set VF = shifted bit
     pub fn shl8xye_usey(&mut self, x: u8, y: u8) {
-        #[cfg(feature = "simulate_frequency")]
-        sleep(Duration::from_micros(200));
-
         // Get the last bit by checking if the byte is greater than 127
         let last_bit = if self.registers[y as usize] > 127 {
             1
@@ -241,15 +283,12 @@ impl CPU {
         // The shifted bit
         let y_shifted = self.registers[y as usize] << 1;
 
-        self.vf = last_bit;
         self.registers[x as usize] = y_shifted;
+        self.registers[0xF] = last_bit;
     }
 
     /// Set Vx = Vx SHL 1, set VF = shifted bit
     pub fn shl8xye_usex(&mut self, x: u8, _y: u8) {
-        #[cfg(feature = "simulate_frequency")]
-        sleep(Duration::from_micros(200));
-
         // Get the last bit by checking if the byte is greater than 127
         let last_bit = if self.registers[x as usize] > 127 {
             1
@@ -259,135 +298,122 @@ impl CPU {
         // The shifted bit
         let x_shifted = self.registers[x as usize] << 1;
 
-        self.vf = last_bit;
         self.registers[x as usize] = x_shifted;
+        self.registers[0xF] = last_bit;
     }
 
     /// Jump to location nnn + V0.
     pub fn jpbnnn(&mut self, nnn: u16) {
-        #[cfg(feature = "simulate_frequency")]
-        sleep(Duration::from_micros(105));
-
         self.pc = nnn + (self.registers[0] as u16);
     }
 
+    /// Jump to location xnn + Vx.
+    pub fn jpbxnn(&mut self, x: u8, xnn: u16) {
+        self.pc = xnn + (self.registers[x as usize] as u16);
+    }
+
     /// Set Vx = random byte AND kk.
     pub fn rndcxnn(&mut self, x: u8, nn: u8) {
-        #[cfg(feature = "simulate_frequency")]
-        sleep(Duration::from_micros(164));
-
-        let random_number = rand::thread_rng().gen_range(0..=255) as u8;
+        let random_number = self.random.next_byte();
 
         self.registers[x as usize] = random_number & nn;
     }
 
     /// Skip next instruction if key with the value of Vx is pressed.
     pub fn skpex9e(&mut self, x: u8) {
-        #[cfg(feature = "simulate_frequency")]
-        sleep(Duration::from_micros(73));
-
         if self.is_key_pressed(self.registers[x as usize]) {
-            self.pc += 2;
+            self.skip_next();
         }
     }
 
     /// Skip next instruction if key with the value of Vx is not pressed.
     pub fn skpexa1(&mut self, x: u8) {
-        #[cfg(feature = "simulate_frequency")]
-        sleep(Duration::from_micros(73));
-
         if !self.is_key_pressed(self.registers[x as usize]) {
-            self.pc += 2;
+            self.skip_next();
         }
     }
 
-    /// Wait for a key press, store the value of the key in Vx.
+    /// Skip next instruction if key with the value of Vx is pressed on the second keypad.
+    pub fn skpexf2(&mut self, x: u8) {
+        if self.is_second_key_pressed(self.registers[x as usize]) {
+            self.skip_next();
+        }
+    }
+
+    /// Skip next instruction if key with the value of Vx is not pressed on the second keypad.
+    pub fn sknpexf5(&mut self, x: u8) {
+        if !self.is_second_key_pressed(self.registers[x as usize]) {
+            self.skip_next();
+        }
+    }
+
+    /// Wait for a key press, store the value of the key in Vx. Like the VIP, the key is
+    /// only stored once it's released again. Until then the instruction repeats itself so
+    /// timers and the display keep running while waiting.
     pub fn ldfx0a(&mut self, x: u8) {
-        let stdin = stdin();
-        let stdin = stdin.lock();
-
-        let mut bytes = stdin.bytes();
-        let char = bytes.next().unwrap().unwrap();
-        let char = match char {
-            b'1' => 0x1,
-            b'2' => 0x2,
-            b'3' => 0x3,
-            b'4' => 0x4,
-            b'5' => 0x5,
-            b'6' => 0x6,
-            b'7' => 0x7,
-            b'8' => 0x8,
-            b'9' => 0x9,
-            b'0' => 0x0,
-            b'a' | b'A' => 0xA,
-            b'b' | b'B' => 0xB,
-            b'c' | b'C' => 0xC,
-            b'd' | b'D' => 0xD,
-            b'e' | b'E' => 0xE,
-            b'f' | b'F' => 0xF,
-            _ => {
-                0x00
+        let pressed_key = self.pressed_key;
+
+        match pressed_key {
+            Some(key) if !self.is_key_pressed(key) => {
+                self.pressed_key = None;
+                self.registers[x as usize] = key;
+                return;
             }
-        };
+            Some(_) => {}
+            None => self.pressed_key = (0..16).find(|&key| self.is_key_pressed(key)),
+        }
 
-        self.registers[x as usize] = char;
+        self.pc = self.pc.wrapping_sub(2);
     }
 
-    /// Set Vx = delay timer value.
-    pub fn ldfx07(&mut self, x: u8) {
-        #[cfg(feature = "simulate_frequency")]
-        sleep(Duration::from_micros(45));
+    /// Set I = nnnn.
+    pub fn ldf000(&mut self, nnnn: u16) {
+        self.i_reg = nnnn;
+    }
 
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_millis();
+    /// Select the bitplanes n for drawing, clearing and scrolling.
+    pub fn planefn01(&mut self, n: u8) {
+        self.planes = n & 0x3;
+    }
 
-        let then = self.last_dt_write;
+    /// Load the 16 byte audio pattern starting at location I.
+    pub fn audiof002(&mut self) -> Result<(), Chip8Error> {
+        let bytes = self.read_mem(self.i_reg, 16)?;
+        self.audio_pattern.copy_from_slice(&bytes);
+        self.audio.set_pattern(&self.audio_pattern, self.pitch);
 
-        let elapsed_time_wrapped = ((now - then) % 255) as u8;
+        Ok(())
+    }
 
-        self.registers[x as usize] = elapsed_time_wrapped;
+    /// Set the audio pattern playback rate = Vx.
+    pub fn pitchfx3a(&mut self, x: u8) {
+        self.pitch = self.registers[x as usize];
+        self.audio.set_pattern(&self.audio_pattern, self.pitch);
+    }
+
+    /// Set Vx = delay timer value.
+    pub fn ldfx07(&mut self, x: u8) {
+        self.registers[x as usize] = self.timers.delay;
     }
 
     /// Set delay timer = Vx.
     pub fn ldfx15(&mut self, x: u8) {
-        #[cfg(feature = "simulate_frequency")]
-        sleep(Duration::from_micros(45));
-
-        self.last_dt_write = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_millis();
-        self.delay_timer = x;
+        self.timers.delay = self.registers[x as usize];
     }
 
     /// Set sound timer = Vx.
     pub fn ldfx18(&mut self, x: u8) {
-        #[cfg(feature = "simulate_frequency")]
-        sleep(Duration::from_micros(45));
-
-        self.last_st_write = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_millis();
-        self.sound_timer = x;
+        self.timers.sound = self.registers[x as usize];
     }
 
     /// Set I = I + Vx.
     pub fn addfx1e(&mut self, x: u8) {
-        #[cfg(feature = "simulate_frequency")]
-        sleep(Duration::from_micros(86));
-
-        self.i_reg += self.registers[x as usize] as u16;
+        self.i_reg = self.i_reg.wrapping_add(self.registers[x as usize] as u16);
     }
 
     /// Set I = location of sprite for digit Vx.
     pub fn ldfx29(&mut self, x: u8) {
-        #[cfg(feature = "simulate_frequency")]
-        sleep(Duration::from_micros(91));
-
-        self.i_reg = 0x50
+        self.i_reg = self.platform.font_addr()
             + match self.registers[x as usize] {
                 0x0 => 0,
                 0x1 => 5,
@@ -409,66 +435,66 @@ impl CPU {
             }
     }
 
-    /// Store BCD representation of Vx in memory locations I, I+1, and I+2.
-    pub fn ldfx33(&mut self, x: u8) {
-        #[cfg(feature = "simulate_frequency")]
-        sleep(Duration::from_micros(920));
+    /// Set I = location of the large sprite for digit Vx.
+    pub fn ldfx30(&mut self, x: u8) {
+        let big_font = self.platform.big_font_addr().unwrap_or_default();
+        self.i_reg = big_font + (self.registers[x as usize] & 0xF) as u16 * 10;
+    }
 
+    /// Store BCD representation of Vx in memory locations I, I+1, and I+2.
+    pub fn ldfx33(&mut self, x: u8) -> Result<(), Chip8Error> {
         let num = self.registers[x as usize];
 
-        let mut digits: [u8; 3] = [0; 3];
-
-        let num = num.to_string();
-
-        for (i, char) in num.chars().enumerate() {
-            digits[i] = char.to_digit(10).unwrap() as u8;
-        }
+        let digits: [u8; 3] = [num / 100, (num / 10) % 10, num % 10];
 
-        self.mem[self.i_reg as usize] = digits[0];
-        self.mem[self.i_reg as usize + 1] = digits[1];
-        self.mem[self.i_reg as usize + 2] = digits[2];
+        self.write_mem(self.i_reg, &digits)
     }
 
     /// Store registers V0 through Vx in memory starting at location I.
-    pub fn ldfx55(&mut self, x: u8) {
-        #[cfg(feature = "simulate_frequency")]
-        sleep(Duration::from_micros(605));
+    pub fn ldfx55(&mut self, x: u8) -> Result<(), Chip8Error> {
+        let registers = self.registers;
 
-        for i in 0..(x + 1) {
-            self.mem[(self.i_reg + i as u16) as usize] = self.registers[i as usize];
-        }
+        self.write_mem(self.i_reg, &registers[..=(x as usize)])
     }
 
     /// Store registers V0 through Vx in memory starting at location I. Uses old conventions where I is incremented
-    pub fn ldfx55_old(&mut self, x: u8) {
-        #[cfg(feature = "simulate_frequency")]
-        sleep(Duration::from_micros(605));
-
-        for i in 0..(x + 1) {
-            self.mem[(self.i_reg) as usize] = self.registers[i as usize];
+    pub fn ldfx55_old(&mut self, x: u8) -> Result<(), Chip8Error> {
+        self.ldfx55(x)?;
+        self.i_reg = self.i_reg.wrapping_add(x as u16 + 1);
 
-            self.i_reg += i as u16;
-        }
+        Ok(())
     }
 
     /// Read registers V0 through Vx from memory starting at location I.
-    pub fn ldfx65(&mut self, x: u8) {
-        #[cfg(feature = "simulate_frequency")]
-        sleep(Duration::from_micros(605));
+    pub fn ldfx65(&mut self, x: u8) -> Result<(), Chip8Error> {
+        let bytes = self.read_mem(self.i_reg, x as usize + 1)?;
+        self.registers[..=(x as usize)].copy_from_slice(&bytes);
 
-        for i in 0..(x + 1) {
-            self.registers[i as usize] = self.mem[(self.i_reg + i as u16) as usize];
-        }
+        Ok(())
     }
 
     /// Read registers V0 through Vx from memory starting at location I. Uses old conventions where I is incremented
-    pub fn ldfx65_old(&mut self, x: u8) {
-        #[cfg(feature = "simulate_frequency")]
-        sleep(Duration::from_micros(605));
+    pub fn ldfx65_old(&mut self, x: u8) -> Result<(), Chip8Error> {
+        self.ldfx65(x)?;
+        self.i_reg = self.i_reg.wrapping_add(x as u16 + 1);
 
-        for i in 0..(x + 1) {
-            self.registers[i as usize] = self.mem[self.i_reg as usize];
-            self.i_reg += i as u16;
-        }
+        Ok(())
+    }
+
+    /// Store registers V0 through Vx in the RPL user flags.
+    pub fn ldfx75(&mut self, x: u8) {
+        self.rpl[..=(x as usize)].copy_from_slice(&self.registers[..=(x as usize)]);
     }
-}
\ No newline at end of file
+
+    /// Read registers V0 through Vx from the RPL user flags.
+    pub fn ldfx85(&mut self, x: u8) {
+        self.registers[..=(x as usize)].copy_from_slice(&self.rpl[..=(x as usize)]);
+    }
+}
+
+/// The register indices from x to y, counting down when x > y
+fn register_range(x: u8, y: u8) -> impl Iterator<Item = usize> {
+    let (x, y) = (x as usize, y as usize);
+
+    (0..=x.abs_diff(y)).map(move |i| if x <= y { x + i } else { x - i })
+}
@@ -1,3 +1,25 @@
+pub mod asm;
 pub mod cpu;
+pub mod debugger;
+pub mod disasm;
 pub mod display;
+pub mod error;
+pub mod headless;
 pub mod instructions;
+pub mod movie;
+pub mod octo;
+pub mod opcode;
+pub mod platform;
+pub mod quirks;
+pub mod random;
+pub mod render;
+pub mod rewind;
+pub mod scheduler;
+pub mod state;
+#[cfg(unix)]
+pub mod terminal;
+pub mod timers;
+pub mod variant;
+
+#[cfg(all(windows, feature = "windows"))]
+pub mod windows;
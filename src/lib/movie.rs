@@ -0,0 +1,452 @@
+use super::cpu::CPU;
+use super::error::Chip8Error;
+use super::platform::Keypad;
+use super::quirks::Quirks;
+use super::random::Random;
+use super::scheduler::FrameBudget;
+use super::state::{rom_hash, StateError, StateReader, StateWriter};
+use super::variant::Platform;
+use std::{cell::RefCell, fmt, rc::Rc};
+
+/// The bytes every input movie starts with
+pub const MOVIE_MAGIC: [u8; 4] = *b"SSCM";
+
+/// The version of the movie format written by [`Movie::to_bytes`]
+pub const MOVIE_VERSION: u16 = 1;
+
+/// Errors that can occur while reading an input movie
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MovieError {
+    /// The data doesn't start with `MOVIE_MAGIC`
+    NotAMovie,
+
+    /// The data was written in a format version this build can't read
+    UnsupportedVersion(u16),
+
+    /// The data ends in the middle of a field
+    Truncated,
+
+    /// A field holds a value no recording could have, e.g. an unknown platform
+    Invalid(&'static str),
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MovieError::NotAMovie => write!(f, "not an input movie"),
+            MovieError::UnsupportedVersion(version) => {
+                write!(f, "unsupported format version {version}")
+            }
+            MovieError::Truncated => write!(f, "the data is truncated"),
+            MovieError::Invalid(field) => write!(f, "invalid {field}"),
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+/// Movies are read with the save state reader, which reports their fields' errors
+impl From<StateError> for MovieError {
+    fn from(err: StateError) -> Self {
+        match err {
+            StateError::NotAState => MovieError::NotAMovie,
+            StateError::UnsupportedVersion(version) => MovieError::UnsupportedVersion(version),
+            StateError::Truncated => MovieError::Truncated,
+            StateError::Invalid(field) => MovieError::Invalid(field),
+        }
+    }
+}
+
+/// The keys held from a frame on. Bits 0-15 are the keypad, bits 16-31 are CHIP-8X's
+/// second keypad.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub frame: u64,
+    pub keys: u32,
+}
+
+/// A recording of every change to the held keys together with everything else a run
+/// depends on, so replaying it on the same ROM ends in exactly the same state.
+///
+/// Keys are sampled once at the start of every frame, so a movie replays the same way
+/// whether it runs in real time or headless.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    /// The [`rom_hash`] of the ROM the movie was recorded with
+    pub rom_hash: u64,
+
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub frame_budget: FrameBudget,
+
+    /// The random number generator as it was when recording started
+    pub random: Random,
+
+    /// The RPL flags loaded from disk when recording started
+    pub rpl: [u8; 16],
+
+    /// Every change to the held keys, in frame order
+    pub events: Vec<KeyEvent>,
+
+    /// How many frames were recorded
+    pub frames: u64,
+
+    /// The [`frame_hash`] of the frame buffer after the last frame
+    pub buf_hash: u64,
+}
+
+/// Hashes a frame buffer the same way ROMs are hashed, for comparing the end of a movie
+pub fn frame_hash(buf: &[u8]) -> u64 {
+    rom_hash(buf)
+}
+
+impl Movie {
+    /// Starts a movie for `cpu`, which should have just been powered on with its ROM
+    pub fn new(cpu: &CPU) -> Self {
+        Movie {
+            rom_hash: cpu.rom_hash,
+            platform: cpu.platform,
+            quirks: cpu.quirks,
+            frame_budget: cpu.frame_budget,
+            random: cpu.random.clone(),
+            rpl: cpu.rpl,
+            events: Vec::new(),
+            frames: 0,
+            buf_hash: frame_hash(&cpu.buf),
+        }
+    }
+
+    /// Records where `cpu` ended up after the recording
+    pub fn finish(&mut self, cpu: &CPU, events: Vec<KeyEvent>) {
+        self.events = events;
+        self.frames = cpu.frames;
+        self.buf_hash = frame_hash(&cpu.buf);
+    }
+
+    /// Sets up a freshly loaded `cpu` the way the movie was recorded and replaces its
+    /// keypad with the movie's input
+    pub fn apply(&self, cpu: &mut CPU) {
+        cpu.quirks = self.quirks;
+        cpu.frame_budget = self.frame_budget;
+        cpu.random = self.random.clone();
+        cpu.rpl = self.rpl;
+        cpu.keypad = Box::new(MoviePlayer::new(self.events.clone()));
+    }
+
+    /// Replays the movie on `rom` without a display and returns the machine after the last
+    /// recorded frame
+    pub fn replay(&self, rom: &[u8]) -> Result<CPU, Chip8Error> {
+        let mut cpu = CPU::with_platform(self.platform, rom)?;
+        self.apply(&mut cpu);
+
+        while cpu.frames < self.frames && !cpu.exited {
+            cpu.run_frame()?;
+        }
+
+        Ok(cpu)
+    }
+
+    /// Whether `cpu` shows the same frame the recording ended with
+    pub fn matches(&self, cpu: &CPU) -> bool {
+        cpu.frames == self.frames && frame_hash(&cpu.buf) == self.buf_hash
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut movie = StateWriter::default();
+
+        movie.bytes(&MOVIE_MAGIC);
+        movie.u16(MOVIE_VERSION);
+        movie.u64(self.rom_hash);
+
+        movie.platform(self.platform);
+        movie.u8(quirk_bits(&self.quirks));
+        match self.frame_budget {
+            FrameBudget::Instructions(n) => {
+                movie.u8(0);
+                movie.u32(n);
+            }
+            FrameBudget::VipTiming => movie.u8(1),
+        }
+        movie.random(&self.random);
+        movie.bytes(&self.rpl);
+
+        movie.u64(self.frames);
+        movie.u64(self.buf_hash);
+
+        movie.u32(self.events.len() as u32);
+        for event in &self.events {
+            movie.u64(event.frame);
+            movie.u32(event.keys);
+        }
+
+        movie.0
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, MovieError> {
+        let mut movie = StateReader::new(data);
+
+        if movie.take(MOVIE_MAGIC.len()).ok() != Some(&MOVIE_MAGIC[..]) {
+            return Err(MovieError::NotAMovie);
+        }
+        let version = movie.u16()?;
+        if version != MOVIE_VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+
+        let rom_hash = movie.u64()?;
+        let platform = movie.platform()?;
+        let quirks = quirks_from_bits(movie.u8()?);
+        let frame_budget = match movie.u8()? {
+            0 => FrameBudget::Instructions(movie.u32()?),
+            1 => FrameBudget::VipTiming,
+            _ => return Err(MovieError::Invalid("frame budget")),
+        };
+        let random = movie.random()?;
+        let rpl = movie.array()?;
+
+        let frames = movie.u64()?;
+        let buf_hash = movie.u64()?;
+
+        let len = movie.u32()?;
+        let events = (0..len)
+            .map(|_| {
+                Ok(KeyEvent {
+                    frame: movie.u64()?,
+                    keys: movie.u32()?,
+                })
+            })
+            .collect::<Result<Vec<_>, StateError>>()?;
+
+        Ok(Movie {
+            rom_hash,
+            platform,
+            quirks,
+            frame_budget,
+            random,
+            rpl,
+            events,
+            frames,
+            buf_hash,
+        })
+    }
+}
+
+fn quirk_bits(quirks: &Quirks) -> u8 {
+    [
+        quirks.shift_uses_vy,
+        quirks.memory_increments_i,
+        quirks.jump_uses_vx,
+        quirks.logic_resets_vf,
+        quirks.clip_sprites,
+        quirks.display_wait,
+    ]
+    .iter()
+    .enumerate()
+    .fold(0, |bits, (bit, &on)| bits | (on as u8) << bit)
+}
+
+fn quirks_from_bits(bits: u8) -> Quirks {
+    let on = |bit: u8| bits & (1 << bit) != 0;
+
+    Quirks {
+        shift_uses_vy: on(0),
+        memory_increments_i: on(1),
+        jump_uses_vx: on(2),
+        logic_resets_vf: on(3),
+        clip_sprites: on(4),
+        display_wait: on(5),
+    }
+}
+
+/// A keypad that passes another keypad through and records every change to its keys.
+/// Going back to an earlier frame, e.g. by rewinding, drops the changes recorded after it.
+pub struct MovieRecorder {
+    inner: Box<dyn Keypad>,
+    events: Rc<RefCell<Vec<KeyEvent>>>,
+    keys: u32,
+}
+
+impl MovieRecorder {
+    pub fn new(inner: impl Keypad + 'static) -> Self {
+        MovieRecorder {
+            inner: Box::new(inner),
+            events: Rc::default(),
+            keys: 0,
+        }
+    }
+
+    /// The recorded changes, shared with the recorder so they can be read after it was
+    /// handed to the CPU
+    pub fn events(&self) -> Rc<RefCell<Vec<KeyEvent>>> {
+        Rc::clone(&self.events)
+    }
+}
+
+impl Keypad for MovieRecorder {
+    fn is_key_pressed(&mut self, key: u8) -> bool {
+        self.keys & (1 << (key & 0xF)) != 0
+    }
+
+    fn is_second_key_pressed(&mut self, key: u8) -> bool {
+        self.keys & (1 << (16 + (key & 0xF))) != 0
+    }
+
+    fn start_frame(&mut self, frame: u64) {
+        self.inner.start_frame(frame);
+
+        let keys = (0..16).fold(0, |keys, key| {
+            let first = self.inner.is_key_pressed(key) as u32;
+            let second = self.inner.is_second_key_pressed(key) as u32;
+
+            keys | first << key | second << (16 + key)
+        });
+
+        let mut events = self.events.borrow_mut();
+        events.retain(|event| event.frame < frame);
+        let previous = events.last().map_or(0, |event| event.keys);
+
+        if keys != previous {
+            events.push(KeyEvent { frame, keys });
+        }
+        self.keys = keys;
+    }
+}
+
+/// A keypad that holds the keys a movie recorded for the current frame
+#[derive(Debug, Clone)]
+pub struct MoviePlayer {
+    events: Vec<KeyEvent>,
+    keys: u32,
+}
+
+impl MoviePlayer {
+    pub fn new(events: Vec<KeyEvent>) -> Self {
+        MoviePlayer { events, keys: 0 }
+    }
+}
+
+impl Keypad for MoviePlayer {
+    fn is_key_pressed(&mut self, key: u8) -> bool {
+        self.keys & (1 << (key & 0xF)) != 0
+    }
+
+    fn is_second_key_pressed(&mut self, key: u8) -> bool {
+        self.keys & (1 << (16 + (key & 0xF))) != 0
+    }
+
+    fn start_frame(&mut self, frame: u64) {
+        // The latest change at or before the frame, which also works when going backwards
+        let next = self.events.partition_point(|event| event.frame <= frame);
+
+        self.keys = next.checked_sub(1).map_or(0, |last| self.events[last].keys);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Waits for a key with `Fx0A`, draws its digit and waits again
+    const PROGRAM: [u8; 10] = [
+        0xF0, 0x0A, // LD V0, K
+        0xF0, 0x29, // LD F, V0
+        0xD1, 0x15, // DRW V1, V1, 5
+        0x71, 0x05, // ADD V1, 5
+        0x12, 0x00, // JP 0x200
+    ];
+
+    /// Presses each key for a few frames, one after the other
+    struct ScriptedKeypad {
+        frame: u64,
+    }
+
+    impl Keypad for ScriptedKeypad {
+        fn is_key_pressed(&mut self, key: u8) -> bool {
+            self.frame % 10 < 3 && self.frame / 10 % 16 == key as u64
+        }
+
+        fn start_frame(&mut self, frame: u64) {
+            self.frame = frame;
+        }
+    }
+
+    fn record(frames: u64) -> Movie {
+        let mut cpu = CPU::new_with_memory(&PROGRAM).unwrap();
+        cpu.random = Random::seeded(99);
+        let mut movie = Movie::new(&cpu);
+
+        let recorder = MovieRecorder::new(ScriptedKeypad { frame: 0 });
+        let events = recorder.events();
+        cpu.keypad = Box::new(recorder);
+
+        for _ in 0..frames {
+            cpu.run_frame().unwrap();
+        }
+        movie.finish(&cpu, events.take());
+
+        movie
+    }
+
+    #[test]
+    fn replay_ends_on_the_same_frame() {
+        let movie = record(100);
+        assert_eq!(movie.events.len(), 20);
+        assert_eq!(movie.events[1], KeyEvent { frame: 3, keys: 0 });
+
+        let cpu = movie.replay(&PROGRAM).unwrap();
+
+        assert!(movie.matches(&cpu));
+        assert_ne!(movie.buf_hash, frame_hash(&CPU::new().buf));
+    }
+
+    #[test]
+    fn movie_file_round_trip() {
+        let mut movie = record(30);
+        movie.quirks = Quirks::vip();
+        movie.frame_budget = FrameBudget::VipTiming;
+
+        assert_eq!(Movie::from_bytes(&movie.to_bytes()), Ok(movie));
+        assert_eq!(
+            Movie::from_bytes(&CPU::new().save_state()),
+            Err(MovieError::NotAMovie)
+        );
+    }
+
+    #[test]
+    fn recording_over_earlier_frames_replaces_them() {
+        let mut recorder = MovieRecorder::new(ScriptedKeypad { frame: 0 });
+        let events = recorder.events();
+
+        for frame in (0..25).chain(5..8) {
+            recorder.start_frame(frame);
+        }
+
+        assert_eq!(
+            *events.borrow(),
+            [
+                KeyEvent { frame: 0, keys: 1 },
+                KeyEvent { frame: 3, keys: 0 }
+            ]
+        );
+    }
+
+    #[test]
+    fn player_follows_the_frame() {
+        let mut player = MoviePlayer::new(vec![
+            KeyEvent {
+                frame: 2,
+                keys: 1 << 5,
+            },
+            KeyEvent { frame: 4, keys: 0 },
+        ]);
+
+        player.start_frame(3);
+        assert!(player.is_key_pressed(5));
+        player.start_frame(4);
+        assert!(!player.is_key_pressed(5));
+        player.start_frame(2);
+        assert!(player.is_key_pressed(5));
+        player.start_frame(0);
+        assert!(!player.is_key_pressed(5));
+    }
+}
@@ -0,0 +1,1269 @@
+use super::asm::AsmError;
+use super::opcode::Instruction;
+use super::variant::Platform;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Where Octo programs are loaded and start running
+const START_ADDR: u16 = 0x200;
+
+/// How many macro invocations a program can expand before the compiler assumes a macro
+/// calls itself forever
+const MAX_EXPANSIONS: usize = 10_000;
+
+/// A compiled Octo program
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    /// The bytes to load at 0x200
+    pub rom: Vec<u8>,
+
+    /// The least capable platform that has every instruction the program uses and enough
+    /// memory for it
+    pub platform: Platform,
+}
+
+/// Compiles [Octo](https://github.com/JohnEarnest/Octo) source into a ROM. Octo's
+/// statements, `if`/`loop` control flow, the SUPER-CHIP and XO-CHIP instructions and the
+/// `:alias`, `:const`, `:calc`, `:macro`, `:next`, `:org`, `:byte`, `:pointer`, `:call` and
+/// `:unpack` directives are supported. Numbers and constants on their own are emitted as
+/// bytes, which is how sprites are written, and any other name on its own calls the
+/// subroutine with that label.
+///
+/// Programs start at `: main`. When something else comes first, 0x200 holds a jump to
+/// `main`.
+pub fn compile(source: &str) -> Result<Program> {
+    Compiler::new(source).compile()
+}
+
+/// Compiles the Octo source file at `path` like [`compile`]
+pub fn compile_file(path: &Path) -> Result<Program> {
+    let error = |line, column, message: String| AsmError {
+        file: Some(path.to_path_buf()),
+        line,
+        column,
+        message,
+    };
+
+    let source = std::fs::read_to_string(path).map_err(|err| error(0, 0, err.to_string()))?;
+
+    compile(&source).map_err(|err| error(err.line, err.column, err.message))
+}
+
+/// A whitespace separated word of the source and where it starts
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Token {
+    text: String,
+    line: usize,
+    column: usize,
+}
+
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+
+    for (i, line) in source.lines().enumerate() {
+        // Everything after `#` is a comment
+        let line = line.split('#').next().unwrap_or_default();
+        let mut start = None;
+
+        for (column, c) in line.chars().chain([' ']).enumerate() {
+            match (c.is_whitespace(), start) {
+                (false, None) => start = Some(column),
+                (true, Some(first)) => {
+                    tokens.push(Token {
+                        text: line.chars().skip(first).take(column - first).collect(),
+                        line: i + 1,
+                        column: first + 1,
+                    });
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    tokens
+}
+
+/// Parses a number literal: decimal, `0x` hex or `0b` binary, optionally negative
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+
+    Some(if negative { -value } else { value })
+}
+
+/// The register index of `v0` to `vf`
+fn parse_register(text: &str) -> Option<u8> {
+    let digit = text.strip_prefix(['v', 'V'])?;
+
+    match digit.len() {
+        1 => u8::from_str_radix(digit, 16).ok(),
+        _ => None,
+    }
+}
+
+/// How a label's address is filled in once it's known
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FixupKind {
+    /// The low 12 bits of the opcode at the address
+    Addr12,
+
+    /// The 16 bit word at the address
+    Addr16,
+
+    /// `:unpack`'s high byte, the nibble followed by the top 4 bits of the address
+    UnpackHigh(u8),
+
+    /// The low byte of the address
+    Low8,
+}
+
+/// A reference to a label that wasn't defined yet
+#[derive(Debug, Clone)]
+struct Fixup {
+    addr: u16,
+    kind: FixupKind,
+    token: Token,
+}
+
+/// An address operand, which can refer to a label that's defined further down
+enum Target {
+    Known(i64),
+    Forward(Token),
+}
+
+/// An `if ... begin` waiting for its `else` or `end`, with the address of the jump that
+/// skips the block
+enum Branch {
+    If(u16),
+    Else(u16),
+}
+
+/// A `loop` waiting for its `again`
+struct Loop {
+    start: u16,
+
+    /// The jumps out of the loop from every `while`
+    breaks: Vec<u16>,
+
+    /// How many branches were open at the `loop`, so a missing `end` can be reported
+    branches: usize,
+}
+
+struct Macro {
+    args: Vec<String>,
+    body: Vec<Token>,
+    calls: usize,
+}
+
+struct Compiler {
+    /// The tokens still to read, in reverse so the next one is at the end
+    tokens: Vec<Token>,
+
+    /// The last token read, for errors at the end of the source
+    last: Token,
+
+    /// Memory from 0x200, and which bytes have been written
+    rom: Vec<u8>,
+    used: Vec<bool>,
+    here: u32,
+
+    labels: HashMap<String, u16>,
+    consts: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    expansions: usize,
+
+    fixups: Vec<Fixup>,
+    branches: Vec<Branch>,
+    loops: Vec<Loop>,
+
+    /// The label `:next` defines at the second byte of the next instruction
+    next: Option<Token>,
+
+    /// Whether `: main` comes later, so 0x200 needs a jump to it
+    main_later: bool,
+
+    platform: Platform,
+}
+
+type Result<T> = std::result::Result<T, AsmError>;
+
+fn error(token: &Token, message: impl Into<String>) -> AsmError {
+    AsmError {
+        file: None,
+        line: token.line,
+        column: token.column,
+        message: message.into(),
+    }
+}
+
+impl Compiler {
+    fn new(source: &str) -> Self {
+        let mut tokens = tokenize(source);
+        let main_later = tokens
+            .windows(2)
+            .any(|pair| pair[0].text == ":" && pair[1].text == "main");
+        tokens.reverse();
+
+        Self {
+            tokens,
+            last: Token {
+                text: String::new(),
+                line: 1,
+                column: 1,
+            },
+            rom: Vec::new(),
+            used: Vec::new(),
+            here: START_ADDR as u32,
+            labels: HashMap::new(),
+            consts: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            expansions: 0,
+            fixups: Vec::new(),
+            branches: Vec::new(),
+            loops: Vec::new(),
+            next: None,
+            main_later,
+            platform: Platform::Chip8,
+        }
+    }
+
+    fn compile(mut self) -> Result<Program> {
+        while let Some(token) = self.tokens.pop() {
+            self.last = token.clone();
+            self.statement(token)?;
+        }
+
+        if !self.branches.is_empty() {
+            return Err(error(&self.last, "expected 'end'"));
+        }
+        if !self.loops.is_empty() {
+            return Err(error(&self.last, "expected 'again'"));
+        }
+        if let Some(token) = &self.next {
+            return Err(error(token, "':next' needs an instruction after it"));
+        }
+
+        for fixup in std::mem::take(&mut self.fixups) {
+            let name = &fixup.token.text;
+            let Some(&addr) = self.labels.get(name) else {
+                return Err(error(&fixup.token, format!("undefined name '{name}'")));
+            };
+            let at = (fixup.addr - START_ADDR) as usize;
+
+            match fixup.kind {
+                FixupKind::Addr12 | FixupKind::UnpackHigh(_) if addr > 0xFFF => {
+                    return Err(error(&fixup.token, format!("'{name}' is above 0xFFF")));
+                }
+                FixupKind::Addr12 => {
+                    self.rom[at] = self.rom[at] & 0xF0 | (addr >> 8) as u8;
+                    self.rom[at + 1] = addr as u8;
+                }
+                FixupKind::Addr16 => self.rom[at..at + 2].copy_from_slice(&addr.to_be_bytes()),
+                FixupKind::UnpackHigh(nibble) => self.rom[at] = nibble << 4 | (addr >> 8) as u8,
+                FixupKind::Low8 => self.rom[at] = addr as u8,
+            }
+        }
+
+        let end = START_ADDR as usize + self.rom.len();
+        if end > Platform::SuperChip.program_end() {
+            self.platform = Platform::XoChip;
+        }
+
+        Ok(Program {
+            rom: self.rom,
+            platform: self.platform,
+        })
+    }
+
+    fn next(&mut self) -> Result<Token> {
+        let token = self
+            .tokens
+            .pop()
+            .ok_or_else(|| error(&self.last, "unexpected end of the source"))?;
+        self.last = token.clone();
+
+        Ok(token)
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.last().map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, text: &str) -> Result<()> {
+        let token = self.next()?;
+
+        if token.text == text {
+            Ok(())
+        } else {
+            Err(error(&token, format!("expected '{text}'")))
+        }
+    }
+
+    /// Reads a name for a new label, constant, alias or macro
+    fn new_name(&mut self) -> Result<Token> {
+        let token = self.next()?;
+        let name = &token.text;
+
+        if parse_number(name).is_some() || parse_register(name).is_some() {
+            return Err(error(&token, format!("'{name}' can't be used as a name")));
+        }
+        if self.labels.contains_key(name)
+            || self.consts.contains_key(name)
+            || self.aliases.contains_key(name)
+            || self.macros.contains_key(name)
+        {
+            return Err(error(&token, format!("'{name}' is already defined")));
+        }
+
+        Ok(token)
+    }
+
+    fn define_label(&mut self, token: &Token, addr: u32) -> Result<()> {
+        if token.text == "main" {
+            self.main_later = false;
+        }
+
+        let addr = u16::try_from(addr).map_err(|_| error(token, "the program is too large"))?;
+        self.labels.insert(token.text.clone(), addr);
+
+        Ok(())
+    }
+
+    fn register(&mut self) -> Result<u8> {
+        let token = self.next()?;
+
+        self.as_register(&token.text).ok_or_else(|| {
+            error(
+                &token,
+                format!("expected a register, found '{}'", token.text),
+            )
+        })
+    }
+
+    fn as_register(&self, text: &str) -> Option<u8> {
+        parse_register(text).or_else(|| self.aliases.get(text).copied())
+    }
+
+    /// Reads a number, a constant, a label that's already defined or a `{ }` calculation
+    fn value(&mut self) -> Result<(f64, Token)> {
+        let token = self.next()?;
+
+        if token.text == "{" {
+            let value = self.calc()?;
+            return Ok((value, token));
+        }
+
+        match self.known_value(&token.text) {
+            Some(value) => Ok((value, token)),
+            None => Err(error(&token, format!("undefined name '{}'", token.text))),
+        }
+    }
+
+    fn known_value(&self, text: &str) -> Option<f64> {
+        parse_number(text)
+            .map(|value| value as f64)
+            .or_else(|| self.consts.get(text).copied())
+            .or_else(|| self.labels.get(text).map(|&addr| addr as f64))
+    }
+
+    /// Reads a value and checks it's between `min` and `max`
+    fn ranged(&mut self, min: i64, max: i64, what: &str) -> Result<i64> {
+        let (value, token) = self.value()?;
+        let value = value as i64;
+
+        if !(min..=max).contains(&value) {
+            return Err(error(&token, format!("{what} {value} is out of range")));
+        }
+
+        Ok(value)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        self.ranged(-0x80, 0xFF, "byte").map(|byte| byte as u8)
+    }
+
+    fn nibble(&mut self) -> Result<u8> {
+        self.ranged(0, 0xF, "nibble").map(|nibble| nibble as u8)
+    }
+
+    /// Reads an address operand, which can be a label that isn't defined yet
+    fn target(&mut self) -> Result<Target> {
+        let Some(text) = self.peek() else {
+            return Err(error(&self.last, "unexpected end of the source"));
+        };
+
+        if text != "{" && self.known_value(text).is_none() {
+            return Ok(Target::Forward(self.next()?));
+        }
+
+        let (value, _) = self.value()?;
+        Ok(Target::Known(value as i64))
+    }
+
+    /// Evaluates the `:calc` expression up to the closing `}`. Like in Octo every operator
+    /// binds equally tightly and they're applied from right to left.
+    fn calc(&mut self) -> Result<f64> {
+        let mut tokens = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token.text == "}" {
+                break;
+            }
+            tokens.push(token);
+        }
+
+        let mut pos = 0;
+        let value = self.calc_expr(&tokens, &mut pos)?;
+        if let Some(token) = tokens.get(pos) {
+            return Err(error(token, format!("unexpected '{}'", token.text)));
+        }
+
+        Ok(value)
+    }
+
+    fn calc_expr(&self, tokens: &[Token], pos: &mut usize) -> Result<f64> {
+        let left = self.calc_term(tokens, pos)?;
+
+        let Some(op) = tokens.get(*pos).filter(|token| token.text != ")") else {
+            return Ok(left);
+        };
+        *pos += 1;
+        let right = self.calc_expr(tokens, pos)?;
+
+        let int = |value: f64| value as i64;
+        let bool = |value: bool| value as i64 as f64;
+
+        Ok(match op.text.as_str() {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" => left / right,
+            "%" => left % right,
+            "pow" => left.powf(right),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "&" => (int(left) & int(right)) as f64,
+            "|" => (int(left) | int(right)) as f64,
+            "^" => (int(left) ^ int(right)) as f64,
+            "<<" => int(left).wrapping_shl(int(right) as u32) as f64,
+            ">>" => int(left).wrapping_shr(int(right) as u32) as f64,
+            "<" => bool(left < right),
+            ">" => bool(left > right),
+            "<=" => bool(left <= right),
+            ">=" => bool(left >= right),
+            "==" => bool(left == right),
+            "!=" => bool(left != right),
+            _ => return Err(error(op, format!("unknown operator '{}'", op.text))),
+        })
+    }
+
+    fn calc_term(&self, tokens: &[Token], pos: &mut usize) -> Result<f64> {
+        let Some(token) = tokens.get(*pos) else {
+            return Err(error(
+                tokens.last().unwrap_or(&self.last),
+                "expected a value",
+            ));
+        };
+        *pos += 1;
+
+        let unary = |pos: &mut usize| self.calc_term(tokens, pos);
+
+        Ok(match token.text.as_str() {
+            "(" => {
+                let value = self.calc_expr(tokens, pos)?;
+                match tokens.get(*pos) {
+                    Some(close) if close.text == ")" => *pos += 1,
+                    _ => return Err(error(token, "expected ')'")),
+                }
+                value
+            }
+            "-" => -unary(pos)?,
+            "~" => !(unary(pos)? as i64) as f64,
+            "!" => (unary(pos)? == 0.0) as i64 as f64,
+            "sin" => unary(pos)?.sin(),
+            "cos" => unary(pos)?.cos(),
+            "tan" => unary(pos)?.tan(),
+            "exp" => unary(pos)?.exp(),
+            "log" => unary(pos)?.ln(),
+            "abs" => unary(pos)?.abs(),
+            "sqrt" => unary(pos)?.sqrt(),
+            "sign" => unary(pos)?.signum(),
+            "ceil" => unary(pos)?.ceil(),
+            "floor" => unary(pos)?.floor(),
+            // The byte already compiled at an address
+            "@" => {
+                let addr = unary(pos)? as i64 - START_ADDR as i64;
+                usize::try_from(addr)
+                    .ok()
+                    .and_then(|addr| self.rom.get(addr))
+                    .map_or(0.0, |&byte| byte as f64)
+            }
+            "HERE" => self.here as f64,
+            "PI" => std::f64::consts::PI,
+            "E" => std::f64::consts::E,
+            text => self
+                .known_value(text)
+                .ok_or_else(|| error(token, format!("undefined name '{text}'")))?,
+        })
+    }
+
+    /// Writes bytes at the current address
+    fn emit(&mut self, bytes: &[u8], token: &Token) -> Result<()> {
+        for &byte in bytes {
+            let at = (self.here - START_ADDR as u32) as usize;
+            if self.here > 0xFFFF {
+                return Err(error(token, "the program is too large"));
+            }
+            if at >= self.rom.len() {
+                self.rom.resize(at + 1, 0);
+                self.used.resize(at + 1, false);
+            }
+            if self.used[at] {
+                return Err(error(token, format!("overwrites 0x{:03X}", self.here)));
+            }
+
+            self.rom[at] = byte;
+            self.used[at] = true;
+            self.here += 1;
+        }
+
+        Ok(())
+    }
+
+    fn instruction(&mut self, instruction: Instruction, token: &Token) -> Result<()> {
+        if let Some(next) = self.next.take() {
+            self.define_label(&next, self.here + 1)?;
+        }
+
+        if instruction.is_xochip() {
+            self.platform = Platform::XoChip;
+        } else if instruction.is_superchip() && self.platform == Platform::Chip8 {
+            self.platform = Platform::SuperChip;
+        }
+
+        self.emit(&instruction.to_bytes(), token)
+    }
+
+    /// Writes an instruction with an address, leaving a fixup if the label isn't defined yet
+    fn emit_jump(
+        &mut self,
+        make: fn(u16) -> Instruction,
+        target: Target,
+        token: &Token,
+    ) -> Result<()> {
+        let long = make(0).size() == 4;
+        let max = if long { 0xFFFF } else { 0xFFF };
+
+        match target {
+            Target::Known(addr) if (0..=max).contains(&addr) => {
+                self.instruction(make(addr as u16), token)
+            }
+            Target::Known(addr) => Err(error(token, format!("address {addr} is out of range"))),
+            Target::Forward(label) => {
+                // `:next` labels point into this instruction, so the address is taken first
+                let addr = self.here as u16;
+                self.instruction(make(0), token)?;
+
+                let (addr, kind) = if long {
+                    (addr + 2, FixupKind::Addr16)
+                } else {
+                    (addr, FixupKind::Addr12)
+                };
+                self.fixups.push(Fixup {
+                    addr,
+                    kind,
+                    token: label,
+                });
+                Ok(())
+            }
+        }
+    }
+
+    /// Writes a jump to be filled in later and returns its address
+    fn placeholder_jump(&mut self, token: &Token) -> Result<u16> {
+        let addr = self.here as u16;
+        self.instruction(Instruction::Jp(0), token)?;
+
+        Ok(addr)
+    }
+
+    /// Points the jump at `addr` to the current address
+    fn patch_jump(&mut self, addr: u16) {
+        let [high, low] = Instruction::Jp(self.here as u16).encode().to_be_bytes();
+        let at = (addr - START_ADDR) as usize;
+
+        self.rom[at] = high;
+        self.rom[at + 1] = low;
+    }
+
+    /// Reads a condition and returns the instructions that prepare it, the skip that runs
+    /// the next instruction only when the condition holds and the skip that runs it only
+    /// when it doesn't
+    fn condition(&mut self) -> Result<(Vec<Instruction>, Instruction, Instruction)> {
+        let x = self.register()?;
+        let op = self.next()?;
+
+        let rhs_register = self.peek().and_then(|text| self.as_register(text));
+
+        Ok(match op.text.as_str() {
+            "key" => (vec![], Instruction::Sknp(x), Instruction::Skp(x)),
+            "-key" => (vec![], Instruction::Skp(x), Instruction::Sknp(x)),
+            "==" | "!=" => {
+                let (equal, not_equal) = match rhs_register {
+                    Some(y) => {
+                        self.next()?;
+                        (Instruction::SeReg(x, y), Instruction::SneReg(x, y))
+                    }
+                    None => {
+                        let nn = self.byte()?;
+                        (Instruction::Se(x, nn), Instruction::Sne(x, nn))
+                    }
+                };
+
+                match op.text.as_str() {
+                    "==" => (vec![], not_equal, equal),
+                    _ => (vec![], equal, not_equal),
+                }
+            }
+            // Compared by subtracting in VF, whose borrow flag ends up 0 or 1
+            "<" | ">" | "<=" | ">=" => {
+                let load = match rhs_register {
+                    Some(y) => {
+                        self.next()?;
+                        Instruction::LdReg(0xF, y)
+                    }
+                    None => Instruction::Ld(0xF, self.byte()?),
+                };
+                let (subtract, flag) = match op.text.as_str() {
+                    // VF = rhs - Vx borrows when Vx > rhs
+                    ">" => (Instruction::Sub(0xF, x), 0),
+                    "<=" => (Instruction::Sub(0xF, x), 1),
+                    // VF = Vx - rhs borrows when Vx < rhs
+                    "<" => (Instruction::Subn(0xF, x), 0),
+                    _ => (Instruction::Subn(0xF, x), 1),
+                };
+
+                (
+                    vec![load, subtract],
+                    Instruction::Sne(0xF, flag),
+                    Instruction::Se(0xF, flag),
+                )
+            }
+            text => return Err(error(&op, format!("unknown comparison '{text}'"))),
+        })
+    }
+
+    fn statement(&mut self, token: Token) -> Result<()> {
+        let text = token.text.as_str();
+
+        // Something comes before `: main`, so the program has to start with a jump to it
+        let definition = matches!(text, ":alias" | ":const" | ":calc" | ":macro")
+            || (text == ":" && self.peek() == Some("main"));
+        if self.main_later && !definition && self.here == START_ADDR as u32 {
+            self.main_later = false;
+            let main = Token {
+                text: "main".to_string(),
+                ..token.clone()
+            };
+            self.emit_jump(Instruction::Jp, Target::Forward(main), &token)?;
+        }
+
+        if let Some(x) = self.as_register(text) {
+            return self.assignment(x, &token);
+        }
+
+        match text {
+            ":" => {
+                let name = self.new_name()?;
+                self.define_label(&name, self.here)
+            }
+            ":alias" => {
+                let name = self.new_name()?;
+                let x = self.register()?;
+                self.aliases.insert(name.text, x);
+                Ok(())
+            }
+            ":const" => {
+                let name = self.new_name()?;
+                let (value, _) = self.value()?;
+                self.consts.insert(name.text, value);
+                Ok(())
+            }
+            ":calc" => {
+                let name = self.new_name()?;
+                self.expect("{")?;
+                let value = self.calc()?;
+                self.consts.insert(name.text, value);
+                Ok(())
+            }
+            ":macro" => self.define_macro(),
+            ":next" => {
+                self.next = Some(self.new_name()?);
+                Ok(())
+            }
+            ":org" => {
+                let (addr, addr_token) = self.value()?;
+                if !(START_ADDR as f64..=0xFFFF as f64).contains(&addr) {
+                    return Err(error(&addr_token, format!("can't put code at {addr}")));
+                }
+                self.here = addr as u32;
+                Ok(())
+            }
+            ":byte" => {
+                let byte = self.byte()?;
+                self.emit(&[byte], &token)
+            }
+            ":pointer" => match self.target()? {
+                Target::Known(addr) => self.emit(&(addr as u16).to_be_bytes(), &token),
+                Target::Forward(label) => {
+                    self.fixups.push(Fixup {
+                        addr: self.here as u16,
+                        kind: FixupKind::Addr16,
+                        token: label,
+                    });
+                    self.emit(&[0, 0], &token)
+                }
+            },
+            ":call" => {
+                let target = self.target()?;
+                self.emit_jump(Instruction::Call, target, &token)
+            }
+            ":unpack" => {
+                let nibble = self.nibble()?;
+                match self.target()? {
+                    Target::Known(addr) if (0..=0xFFF).contains(&addr) => {
+                        self.instruction(
+                            Instruction::Ld(0, nibble << 4 | (addr >> 8) as u8),
+                            &token,
+                        )?;
+                        self.instruction(Instruction::Ld(1, addr as u8), &token)
+                    }
+                    Target::Known(addr) => {
+                        Err(error(&token, format!("address {addr} is out of range")))
+                    }
+                    Target::Forward(label) => {
+                        self.fixups.push(Fixup {
+                            addr: self.here as u16 + 1,
+                            kind: FixupKind::UnpackHigh(nibble),
+                            token: label.clone(),
+                        });
+                        self.fixups.push(Fixup {
+                            addr: self.here as u16 + 3,
+                            kind: FixupKind::Low8,
+                            token: label,
+                        });
+                        self.instruction(Instruction::Ld(0, 0), &token)?;
+                        self.instruction(Instruction::Ld(1, 0), &token)
+                    }
+                }
+            }
+            // Debugger hints, which don't change the program
+            ":breakpoint" => self.next().map(|_| ()),
+            ":monitor" => {
+                self.next()?;
+                self.next().map(|_| ())
+            }
+
+            ";" | "return" => self.instruction(Instruction::Ret, &token),
+            "clear" => self.instruction(Instruction::Cls, &token),
+            "exit" => self.instruction(Instruction::Exit, &token),
+            "lores" => self.instruction(Instruction::Lores, &token),
+            "hires" => self.instruction(Instruction::Hires, &token),
+            "scroll-left" => self.instruction(Instruction::ScrollLeft, &token),
+            "scroll-right" => self.instruction(Instruction::ScrollRight, &token),
+            "audio" => self.instruction(Instruction::LdAudio, &token),
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.instruction(Instruction::ScrollDown(n), &token)
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.instruction(Instruction::ScrollUp(n), &token)
+            }
+            "plane" => {
+                let n = self.nibble()?;
+                self.instruction(Instruction::Plane(n), &token)
+            }
+            "bcd" => {
+                let x = self.register()?;
+                self.instruction(Instruction::LdB(x), &token)
+            }
+            "saveflags" => {
+                let x = self.register()?;
+                self.instruction(Instruction::LdRVx(x), &token)
+            }
+            "loadflags" => {
+                let x = self.register()?;
+                self.instruction(Instruction::LdVxR(x), &token)
+            }
+            "save" | "load" => {
+                let x = self.register()?;
+                let instruction = if self.peek() == Some("-") {
+                    self.next()?;
+                    let y = self.register()?;
+                    match text {
+                        "save" => Instruction::SaveRange(x, y),
+                        _ => Instruction::LoadRange(x, y),
+                    }
+                } else {
+                    match text {
+                        "save" => Instruction::LdIVx(x),
+                        _ => Instruction::LdVxI(x),
+                    }
+                };
+                self.instruction(instruction, &token)
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                self.instruction(Instruction::Drw(x, y, n), &token)
+            }
+            "jump" => {
+                let target = self.target()?;
+                self.emit_jump(Instruction::Jp, target, &token)
+            }
+            "jump0" => {
+                let target = self.target()?;
+                self.emit_jump(Instruction::JpV0, target, &token)
+            }
+            "native" => {
+                let target = self.target()?;
+                self.emit_jump(Instruction::Sys, target, &token)
+            }
+            "i" => {
+                let op = self.next()?;
+                match op.text.as_str() {
+                    ":=" => match self.peek() {
+                        Some("hex") => {
+                            self.next()?;
+                            let x = self.register()?;
+                            self.instruction(Instruction::LdF(x), &token)
+                        }
+                        Some("bighex") => {
+                            self.next()?;
+                            let x = self.register()?;
+                            self.instruction(Instruction::LdHf(x), &token)
+                        }
+                        Some("long") => {
+                            self.next()?;
+                            let target = self.target()?;
+                            self.emit_jump(Instruction::LdILong, target, &token)
+                        }
+                        _ => {
+                            let target = self.target()?;
+                            self.emit_jump(Instruction::LdI, target, &token)
+                        }
+                    },
+                    "+=" => {
+                        let x = self.register()?;
+                        self.instruction(Instruction::AddI(x), &token)
+                    }
+                    _ => Err(error(&op, "expected ':=' or '+='")),
+                }
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                let instruction = match text {
+                    "delay" => Instruction::LdDtVx(x),
+                    "buzzer" => Instruction::LdStVx(x),
+                    _ => Instruction::Pitch(x),
+                };
+                self.instruction(instruction, &token)
+            }
+
+            "if" => {
+                let (prepare, then, begin) = self.condition()?;
+                for instruction in prepare {
+                    self.instruction(instruction, &token)?;
+                }
+
+                let word = self.next()?;
+                match word.text.as_str() {
+                    "then" => self.instruction(then, &token),
+                    "begin" => {
+                        self.instruction(begin, &token)?;
+                        let jump = self.placeholder_jump(&token)?;
+                        self.branches.push(Branch::If(jump));
+                        Ok(())
+                    }
+                    _ => Err(error(&word, "expected 'then' or 'begin'")),
+                }
+            }
+            "else" => {
+                let Some(Branch::If(skip)) = self.branches.pop() else {
+                    return Err(error(&token, "'else' without 'if ... begin'"));
+                };
+                let jump = self.placeholder_jump(&token)?;
+                self.patch_jump(skip);
+                self.branches.push(Branch::Else(jump));
+                Ok(())
+            }
+            "end" => {
+                let (Some(Branch::If(jump)) | Some(Branch::Else(jump))) = self.branches.pop()
+                else {
+                    return Err(error(&token, "'end' without 'if ... begin'"));
+                };
+                self.patch_jump(jump);
+                Ok(())
+            }
+            "loop" => {
+                self.loops.push(Loop {
+                    start: self.here as u16,
+                    breaks: Vec::new(),
+                    branches: self.branches.len(),
+                });
+                Ok(())
+            }
+            "while" => {
+                if self.loops.is_empty() {
+                    return Err(error(&token, "'while' outside a loop"));
+                }
+
+                let (prepare, _, skip_when_true) = self.condition()?;
+                for instruction in prepare {
+                    self.instruction(instruction, &token)?;
+                }
+                self.instruction(skip_when_true, &token)?;
+
+                let jump = self.placeholder_jump(&token)?;
+                if let Some(innermost) = self.loops.last_mut() {
+                    innermost.breaks.push(jump);
+                }
+                Ok(())
+            }
+            "again" => {
+                let Some(innermost) = self.loops.pop() else {
+                    return Err(error(&token, "'again' without 'loop'"));
+                };
+                if innermost.branches != self.branches.len() {
+                    return Err(error(&token, "expected 'end' before 'again'"));
+                }
+
+                self.instruction(Instruction::Jp(innermost.start), &token)?;
+                for jump in innermost.breaks {
+                    self.patch_jump(jump);
+                }
+                Ok(())
+            }
+
+            _ if self.macros.contains_key(text) => self.expand(&token),
+            _ => match self.known_value(text) {
+                // Numbers and constants on their own are data
+                Some(_) if !self.labels.contains_key(text) => {
+                    self.tokens.push(token.clone());
+                    let byte = self.byte()?;
+                    self.emit(&[byte], &token)
+                }
+                // Anything else calls the subroutine with that name
+                _ => self.emit_jump(Instruction::Call, Target::Forward(token.clone()), &token),
+            },
+        }
+    }
+
+    /// Compiles `vx := ...` and the other register operations
+    fn assignment(&mut self, x: u8, token: &Token) -> Result<()> {
+        let op = self.next()?;
+        let y = self.peek().and_then(|text| self.as_register(text));
+        if y.is_some() {
+            self.next()?;
+        }
+
+        let instruction = match (op.text.as_str(), y) {
+            (":=", Some(y)) => Instruction::LdReg(x, y),
+            (":=", None) => match self.peek() {
+                Some("random") => {
+                    self.next()?;
+                    Instruction::Rnd(x, self.byte()?)
+                }
+                Some("key") => {
+                    self.next()?;
+                    Instruction::LdVxK(x)
+                }
+                Some("delay") => {
+                    self.next()?;
+                    Instruction::LdVxDt(x)
+                }
+                _ => Instruction::Ld(x, self.byte()?),
+            },
+            ("+=", Some(y)) => Instruction::AddReg(x, y),
+            ("+=", None) => Instruction::Add(x, self.byte()?),
+            ("-=", Some(y)) => Instruction::Sub(x, y),
+            ("-=", None) => Instruction::Add(x, self.byte()?.wrapping_neg()),
+            ("=-", Some(y)) => Instruction::Subn(x, y),
+            ("|=", Some(y)) => Instruction::Or(x, y),
+            ("&=", Some(y)) => Instruction::And(x, y),
+            ("^=", Some(y)) => Instruction::Xor(x, y),
+            (">>=", Some(y)) => Instruction::Shr(x, y),
+            ("<<=", Some(y)) => Instruction::Shl(x, y),
+            ("=-" | "|=" | "&=" | "^=" | ">>=" | "<<=", None) => {
+                return Err(error(&self.last, "expected a register"))
+            }
+            (text, _) => return Err(error(&op, format!("unknown operator '{text}'"))),
+        };
+
+        self.instruction(instruction, token)
+    }
+
+    /// Reads `:macro name args... { body }`
+    fn define_macro(&mut self) -> Result<()> {
+        let name = self.new_name()?;
+
+        let mut args = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token.text == "{" {
+                break;
+            }
+            args.push(token.text);
+        }
+
+        let mut body = Vec::new();
+        let mut depth = 0;
+        loop {
+            let token = self.next()?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 0 => break,
+                "}" => depth -= 1,
+                _ => {}
+            }
+            body.push(token);
+        }
+
+        self.macros.insert(
+            name.text,
+            Macro {
+                args,
+                body,
+                calls: 0,
+            },
+        );
+        Ok(())
+    }
+
+    /// Replaces a macro invocation with its body, with the arguments and `CALLS`, the
+    /// number of earlier invocations, filled in
+    fn expand(&mut self, token: &Token) -> Result<()> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(error(
+                token,
+                "too many macro expansions, does a macro call itself?",
+            ));
+        }
+
+        let arg_count = self.macros[&token.text].args.len();
+        let mut values = Vec::with_capacity(arg_count);
+        for _ in 0..arg_count {
+            values.push(self.next()?.text);
+        }
+
+        let Some(definition) = self.macros.get_mut(&token.text) else {
+            return Ok(());
+        };
+        let calls = definition.calls;
+        definition.calls += 1;
+
+        let expanded: Vec<Token> = definition
+            .body
+            .iter()
+            .map(|body_token| {
+                let text = match definition
+                    .args
+                    .iter()
+                    .position(|arg| *arg == body_token.text)
+                {
+                    Some(i) => values[i].clone(),
+                    None if body_token.text == "CALLS" => calls.to_string(),
+                    None => body_token.text.clone(),
+                };
+
+                Token {
+                    text,
+                    ..body_token.clone()
+                }
+            })
+            .collect();
+
+        self.tokens.extend(expanded.into_iter().rev());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::CPU;
+
+    #[test]
+    fn statements_and_sprites() {
+        let source = "
+            : main
+                clear
+                v0 := 5
+                i := sprite      # sprites can come after they're used
+                sprite v0 v0 4
+                loop again
+            : sprite
+                0xF0 0x90 0x90 0xF0
+        ";
+
+        assert_eq!(
+            compile(source).unwrap(),
+            Program {
+                rom: vec![
+                    0x00, 0xE0, 0x60, 0x05, 0xA2, 0x0A, 0xD0, 0x04, 0x12, 0x08, 0xF0, 0x90, 0x90,
+                    0xF0
+                ],
+                platform: Platform::Chip8,
+            }
+        );
+    }
+
+    #[test]
+    fn jumps_to_main() {
+        let source = "
+            : draw
+                sprite v0 v1 5
+                return
+            : main
+                draw
+                exit
+        ";
+
+        assert_eq!(
+            compile(source).unwrap(),
+            Program {
+                rom: vec![0x12, 0x06, 0xD0, 0x15, 0x00, 0xEE, 0x22, 0x02, 0x00, 0xFD],
+                platform: Platform::SuperChip,
+            }
+        );
+    }
+
+    #[test]
+    fn control_flow() {
+        let source = "
+            : main
+                v0 := 3
+                v1 := 0
+                loop
+                    while v0 != 0
+                    v0 -= 1
+                    v1 += 2
+                again
+                if v1 == 6 then v2 := 1
+                if v1 > 5 begin v3 := 1 else v3 := 2 end
+                if v1 < 5 begin v4 := 1 else v4 := 2 end
+                if v1 <= 6 then v5 := 1
+                if v1 >= 7 then v6 := 1
+                if v1 != v0 then v7 := 1
+                exit
+        ";
+        let program = compile(source).unwrap();
+        let mut cpu = CPU::with_platform(program.platform, &program.rom).unwrap();
+
+        while !cpu.exited {
+            cpu.run_frame().unwrap();
+        }
+
+        assert_eq!(cpu.registers[..8], [0, 6, 1, 1, 2, 1, 0, 1]);
+    }
+
+    #[test]
+    fn directives() {
+        let source = "
+            :alias counter v3
+            :const SPEED 2
+            :calc ODD { SPEED * 2 + 1 }  # right to left, so 2 * 3
+            :macro add-to reg n { reg += n }
+            :macro count { :byte CALLS }
+            : main
+                counter := ODD
+                add-to counter SPEED
+                :next patched
+                v0 := 0
+                :unpack 0xA data
+                :pointer data
+                count count
+            :org 0x300
+            : data
+                :byte { patched - 0x200 }
+        ";
+        let rom = compile(source).unwrap().rom;
+
+        assert_eq!(
+            rom[..16],
+            [
+                0x63, 0x06, 0x73, 0x02, 0x60, 0x00, 0x60, 0xA3, 0x61, 0x00, 0x03, 0x00, 0x00, 0x01,
+                0x00, 0x00
+            ]
+        );
+        assert_eq!(rom.len(), 0x101);
+        assert_eq!(rom[0x100], 0x05);
+    }
+
+    #[test]
+    fn platforms() {
+        assert_eq!(compile(": main clear").unwrap().platform, Platform::Chip8);
+        assert_eq!(
+            compile(": main hires").unwrap().platform,
+            Platform::SuperChip
+        );
+        assert_eq!(
+            compile(": main plane 1").unwrap().platform,
+            Platform::XoChip
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let error = |source| compile(source).unwrap_err();
+
+        assert_eq!(
+            error(": main\n  missing"),
+            AsmError {
+                file: None,
+                line: 2,
+                column: 3,
+                message: "undefined name 'missing'".to_string()
+            }
+        );
+        assert_eq!(error("v0 := 300").message, "byte 300 is out of range");
+        assert_eq!(error("loop v0 += 1").message, "expected 'again'");
+        assert_eq!(error("end").message, "'end' without 'if ... begin'");
+        assert_eq!(error(": a : a").message, "'a' is already defined");
+        assert_eq!(
+            error("if v0 < 1 jump").message,
+            "expected 'then' or 'begin'"
+        );
+        assert_eq!(
+            error(":org 0x200 1 :org 0x200 2").message,
+            "overwrites 0x200"
+        );
+        assert_eq!(
+            error(":unpack 0xA 0x1000").message,
+            "address 4096 is out of range"
+        );
+        assert_eq!(
+            error(":unpack 0xA data :org 0x1000 : data").message,
+            "'data' is above 0xFFF"
+        );
+    }
+}
@@ -0,0 +1,518 @@
+use std::fmt;
+
+/// A decoded CHIP-8 instruction. Register operands are register indices (the `x` and `y`
+/// nibbles), not register values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// 0nnn - Jump to a machine code routine at nnn (ignored by modern interpreters)
+    Sys(u16),
+
+    /// 00E0 - Clear the display
+    Cls,
+
+    /// 00EE - Return from a subroutine
+    Ret,
+
+    /// 00Cn - Scroll the display down by n pixels (SUPER-CHIP)
+    ScrollDown(u8),
+
+    /// 00Dn - Scroll the display up by n pixels (XO-CHIP)
+    ScrollUp(u8),
+
+    /// 00FB - Scroll the display right by 4 pixels (SUPER-CHIP)
+    ScrollRight,
+
+    /// 00FC - Scroll the display left by 4 pixels (SUPER-CHIP)
+    ScrollLeft,
+
+    /// 00FD - Exit the interpreter (SUPER-CHIP)
+    Exit,
+
+    /// 00FE - Switch to the 64x32 low resolution mode (SUPER-CHIP)
+    Lores,
+
+    /// 00FF - Switch to the 128x64 high resolution mode (SUPER-CHIP)
+    Hires,
+
+    /// 02A0 - Step the background to the next colour (CHIP-8X)
+    StepBackground,
+
+    /// 1nnn - Jump to location nnn
+    Jp(u16),
+
+    /// 2nnn - Call subroutine at nnn
+    Call(u16),
+
+    /// 3xnn - Skip next instruction if Vx = nn
+    Se(u8, u8),
+
+    /// 4xnn - Skip next instruction if Vx != nn
+    Sne(u8, u8),
+
+    /// 5xy0 - Skip next instruction if Vx = Vy
+    SeReg(u8, u8),
+
+    /// 5xy2 - Store registers Vx through Vy in memory starting at location I (XO-CHIP)
+    SaveRange(u8, u8),
+
+    /// 5xy3 - Read registers Vx through Vy from memory starting at location I (XO-CHIP)
+    LoadRange(u8, u8),
+
+    /// 6xnn - Set Vx = nn
+    Ld(u8, u8),
+
+    /// 7xnn - Set Vx = Vx + nn
+    Add(u8, u8),
+
+    /// 8xy0 - Set Vx = Vy
+    LdReg(u8, u8),
+
+    /// 8xy1 - Set Vx = Vx OR Vy
+    Or(u8, u8),
+
+    /// 8xy2 - Set Vx = Vx AND Vy
+    And(u8, u8),
+
+    /// 8xy3 - Set Vx = Vx XOR Vy
+    Xor(u8, u8),
+
+    /// 8xy4 - Set Vx = Vx + Vy, set VF = carry
+    AddReg(u8, u8),
+
+    /// 8xy5 - Set Vx = Vx - Vy, set VF = NOT borrow
+    Sub(u8, u8),
+
+    /// 8xy6 - Shift right, set VF = shifted bit
+    Shr(u8, u8),
+
+    /// 8xy7 - Set Vx = Vy - Vx, set VF = NOT borrow
+    Subn(u8, u8),
+
+    /// 8xyE - Shift left, set VF = shifted bit
+    Shl(u8, u8),
+
+    /// 9xy0 - Skip next instruction if Vx != Vy
+    SneReg(u8, u8),
+
+    /// Annn - Set I = nnn
+    LdI(u16),
+
+    /// Bnnn - Jump to location nnn + V0
+    JpV0(u16),
+
+    /// Bxyn - Set the foreground colour of an area to Vy (CHIP-8X). With n = 0 the area is
+    /// a range of 8x4 zones, the low nibbles of Vx and Vx+1 are the first column and row and
+    /// the high nibbles the last. Otherwise it's n rows of 8 pixels at (Vx, Vx+1).
+    Colour(u8, u8, u8),
+
+    /// Cxnn - Set Vx = random byte AND nn
+    Rnd(u8, u8),
+
+    /// Dxyn - Draw an n-byte sprite at (Vx, Vy), set VF = collision. On SUPER-CHIP `Dxy0`
+    /// draws a 16x16 sprite.
+    Drw(u8, u8, u8),
+
+    /// Ex9E - Skip next instruction if the key with the value of Vx is pressed
+    Skp(u8),
+
+    /// ExA1 - Skip next instruction if the key with the value of Vx is not pressed
+    Sknp(u8),
+
+    /// F000 nnnn - Set I = nnnn, a 16 bit address stored in the word after the opcode
+    /// (XO-CHIP)
+    LdILong(u16),
+
+    /// Fn01 - Select the bitplanes n that drawing, clearing and scrolling affect (XO-CHIP)
+    Plane(u8),
+
+    /// F002 - Load the 16 byte audio pattern starting at location I (XO-CHIP)
+    LdAudio,
+
+    /// ExF2 - Skip next instruction if the key with the value of Vx is pressed on the
+    /// second keypad (CHIP-8X)
+    SkpKeypad2(u8),
+
+    /// ExF5 - Skip next instruction if the key with the value of Vx is not pressed on the
+    /// second keypad (CHIP-8X)
+    SknpKeypad2(u8),
+
+    /// Fx07 - Set Vx = delay timer value
+    LdVxDt(u8),
+
+    /// Fx0A - Wait for a key press, store the value of the key in Vx
+    LdVxK(u8),
+
+    /// Fx15 - Set delay timer = Vx
+    LdDtVx(u8),
+
+    /// Fx18 - Set sound timer = Vx
+    LdStVx(u8),
+
+    /// Fx1E - Set I = I + Vx
+    AddI(u8),
+
+    /// Fx29 - Set I = location of sprite for digit Vx
+    LdF(u8),
+
+    /// Fx30 - Set I = location of the large sprite for digit Vx (SUPER-CHIP)
+    LdHf(u8),
+
+    /// Fx3A - Set the audio pattern playback rate = Vx (XO-CHIP)
+    Pitch(u8),
+
+    /// Fx33 - Store BCD representation of Vx in memory locations I, I+1, and I+2
+    LdB(u8),
+
+    /// Fx55 - Store registers V0 through Vx in memory starting at location I
+    LdIVx(u8),
+
+    /// Fx65 - Read registers V0 through Vx from memory starting at location I
+    LdVxI(u8),
+
+    /// Fx75 - Store registers V0 through Vx in the RPL user flags (SUPER-CHIP)
+    LdRVx(u8),
+
+    /// Fx85 - Read registers V0 through Vx from the RPL user flags (SUPER-CHIP)
+    LdVxR(u8),
+}
+
+/// Returned when two bytes don't form a known instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeError {
+    pub opcode: u16,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown opcode 0x{:04X}", self.opcode)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl Instruction {
+    /// Decodes a big-endian opcode into an instruction
+    pub fn decode(opcode: u16) -> Result<Instruction, DecodeError> {
+        // Split the opcode into its 4 nibbles and the commonly used operands
+        let nibbles = (
+            ((opcode & 0xF000) >> 12) as u8,
+            ((opcode & 0x0F00) >> 8) as u8,
+            ((opcode & 0x00F0) >> 4) as u8,
+            (opcode & 0x000F) as u8,
+        );
+        let nnn = opcode & 0x0FFF;
+        let nn = (opcode & 0x00FF) as u8;
+
+        let instruction = match nibbles {
+            (0x0, 0x0, 0xE, 0x0) => Instruction::Cls,
+            (0x0, 0x0, 0xE, 0xE) => Instruction::Ret,
+            (0x0, 0x0, 0xC, n) => Instruction::ScrollDown(n),
+            (0x0, 0x0, 0xD, n) => Instruction::ScrollUp(n),
+            (0x0, 0x0, 0xF, 0xB) => Instruction::ScrollRight,
+            (0x0, 0x0, 0xF, 0xC) => Instruction::ScrollLeft,
+            (0x0, 0x0, 0xF, 0xD) => Instruction::Exit,
+            (0x0, 0x0, 0xF, 0xE) => Instruction::Lores,
+            (0x0, 0x0, 0xF, 0xF) => Instruction::Hires,
+            (0x0, _, _, _) => Instruction::Sys(nnn),
+            (0x1, _, _, _) => Instruction::Jp(nnn),
+            (0x2, _, _, _) => Instruction::Call(nnn),
+            (0x3, x, _, _) => Instruction::Se(x, nn),
+            (0x4, x, _, _) => Instruction::Sne(x, nn),
+            (0x5, x, y, 0x0) => Instruction::SeReg(x, y),
+            (0x5, x, y, 0x2) => Instruction::SaveRange(x, y),
+            (0x5, x, y, 0x3) => Instruction::LoadRange(x, y),
+            (0x6, x, _, _) => Instruction::Ld(x, nn),
+            (0x7, x, _, _) => Instruction::Add(x, nn),
+            (0x8, x, y, 0x0) => Instruction::LdReg(x, y),
+            (0x8, x, y, 0x1) => Instruction::Or(x, y),
+            (0x8, x, y, 0x2) => Instruction::And(x, y),
+            (0x8, x, y, 0x3) => Instruction::Xor(x, y),
+            (0x8, x, y, 0x4) => Instruction::AddReg(x, y),
+            (0x8, x, y, 0x5) => Instruction::Sub(x, y),
+            (0x8, x, y, 0x6) => Instruction::Shr(x, y),
+            (0x8, x, y, 0x7) => Instruction::Subn(x, y),
+            (0x8, x, y, 0xE) => Instruction::Shl(x, y),
+            (0x9, x, y, 0x0) => Instruction::SneReg(x, y),
+            (0xA, _, _, _) => Instruction::LdI(nnn),
+            (0xB, _, _, _) => Instruction::JpV0(nnn),
+            (0xC, x, _, _) => Instruction::Rnd(x, nn),
+            (0xD, x, y, n) => Instruction::Drw(x, y, n),
+            (0xE, x, 0x9, 0xE) => Instruction::Skp(x),
+            (0xE, x, 0xA, 0x1) => Instruction::Sknp(x),
+            (0xF, n, 0x0, 0x1) => Instruction::Plane(n),
+            (0xF, 0x0, 0x0, 0x2) => Instruction::LdAudio,
+            (0xF, x, 0x0, 0x7) => Instruction::LdVxDt(x),
+            (0xF, x, 0x0, 0xA) => Instruction::LdVxK(x),
+            (0xF, x, 0x1, 0x5) => Instruction::LdDtVx(x),
+            (0xF, x, 0x1, 0x8) => Instruction::LdStVx(x),
+            (0xF, x, 0x1, 0xE) => Instruction::AddI(x),
+            (0xF, x, 0x2, 0x9) => Instruction::LdF(x),
+            (0xF, x, 0x3, 0x0) => Instruction::LdHf(x),
+            (0xF, x, 0x3, 0x3) => Instruction::LdB(x),
+            (0xF, x, 0x3, 0xA) => Instruction::Pitch(x),
+            (0xF, x, 0x5, 0x5) => Instruction::LdIVx(x),
+            (0xF, x, 0x6, 0x5) => Instruction::LdVxI(x),
+            (0xF, x, 0x7, 0x5) => Instruction::LdRVx(x),
+            (0xF, x, 0x8, 0x5) => Instruction::LdVxR(x),
+            _ => return Err(DecodeError { opcode }),
+        };
+
+        Ok(instruction)
+    }
+
+    /// Decodes an opcode together with the word that follows it in memory. Only
+    /// `F000 nnnn` needs the second word, every other opcode is decoded on its own.
+    pub fn decode_long(opcode: u16, next: u16) -> Result<Instruction, DecodeError> {
+        if opcode == 0xF000 {
+            return Ok(Instruction::LdILong(next));
+        }
+
+        Self::decode(opcode)
+    }
+
+    /// Decodes an opcode the way CHIP-8X does, where `Bnnn` sets colours instead of
+    /// jumping and a few machine code routines became instructions
+    pub fn decode_chip8x(opcode: u16) -> Result<Instruction, DecodeError> {
+        let nibbles = (
+            ((opcode & 0xF000) >> 12) as u8,
+            ((opcode & 0x0F00) >> 8) as u8,
+            ((opcode & 0x00F0) >> 4) as u8,
+            (opcode & 0x000F) as u8,
+        );
+
+        let instruction = match nibbles {
+            (0x0, 0x2, 0xA, 0x0) => Instruction::StepBackground,
+            (0xB, x, y, n) => Instruction::Colour(x, y, n),
+            (0xE, x, 0xF, 0x2) => Instruction::SkpKeypad2(x),
+            (0xE, x, 0xF, 0x5) => Instruction::SknpKeypad2(x),
+            _ => return Self::decode(opcode),
+        };
+
+        Ok(instruction)
+    }
+
+    /// The size of the instruction in memory in bytes
+    pub fn size(&self) -> u16 {
+        match self {
+            Instruction::LdILong(_) => 4,
+            _ => 2,
+        }
+    }
+
+    /// Encodes the instruction back into its big-endian opcode. `F000 nnnn` encodes to
+    /// its first word, use [`Instruction::to_bytes`] to get the address as well.
+    pub fn encode(&self) -> u16 {
+        // Helpers for the common operand layouts
+        let xnn = |prefix: u16, x: u8, nn: u8| prefix << 12 | (x as u16 & 0xF) << 8 | nn as u16;
+        let xyn = |prefix: u16, x: u8, y: u8, n: u8| {
+            prefix << 12 | (x as u16 & 0xF) << 8 | (y as u16 & 0xF) << 4 | (n as u16 & 0xF)
+        };
+        let nnn = |prefix: u16, nnn: u16| prefix << 12 | (nnn & 0x0FFF);
+
+        match *self {
+            Instruction::Sys(addr) => nnn(0x0, addr),
+            Instruction::Cls => 0x00E0,
+            Instruction::Ret => 0x00EE,
+            Instruction::ScrollDown(n) => 0x00C0 | (n as u16 & 0xF),
+            Instruction::ScrollUp(n) => 0x00D0 | (n as u16 & 0xF),
+            Instruction::ScrollRight => 0x00FB,
+            Instruction::ScrollLeft => 0x00FC,
+            Instruction::Exit => 0x00FD,
+            Instruction::Lores => 0x00FE,
+            Instruction::Hires => 0x00FF,
+            Instruction::StepBackground => 0x02A0,
+            Instruction::Jp(addr) => nnn(0x1, addr),
+            Instruction::Call(addr) => nnn(0x2, addr),
+            Instruction::Se(x, nn) => xnn(0x3, x, nn),
+            Instruction::Sne(x, nn) => xnn(0x4, x, nn),
+            Instruction::SeReg(x, y) => xyn(0x5, x, y, 0x0),
+            Instruction::SaveRange(x, y) => xyn(0x5, x, y, 0x2),
+            Instruction::LoadRange(x, y) => xyn(0x5, x, y, 0x3),
+            Instruction::Ld(x, nn) => xnn(0x6, x, nn),
+            Instruction::Add(x, nn) => xnn(0x7, x, nn),
+            Instruction::LdReg(x, y) => xyn(0x8, x, y, 0x0),
+            Instruction::Or(x, y) => xyn(0x8, x, y, 0x1),
+            Instruction::And(x, y) => xyn(0x8, x, y, 0x2),
+            Instruction::Xor(x, y) => xyn(0x8, x, y, 0x3),
+            Instruction::AddReg(x, y) => xyn(0x8, x, y, 0x4),
+            Instruction::Sub(x, y) => xyn(0x8, x, y, 0x5),
+            Instruction::Shr(x, y) => xyn(0x8, x, y, 0x6),
+            Instruction::Subn(x, y) => xyn(0x8, x, y, 0x7),
+            Instruction::Shl(x, y) => xyn(0x8, x, y, 0xE),
+            Instruction::SneReg(x, y) => xyn(0x9, x, y, 0x0),
+            Instruction::LdI(addr) => nnn(0xA, addr),
+            Instruction::JpV0(addr) => nnn(0xB, addr),
+            Instruction::Colour(x, y, n) => xyn(0xB, x, y, n),
+            Instruction::Rnd(x, nn) => xnn(0xC, x, nn),
+            Instruction::Drw(x, y, n) => xyn(0xD, x, y, n),
+            Instruction::Skp(x) => xnn(0xE, x, 0x9E),
+            Instruction::Sknp(x) => xnn(0xE, x, 0xA1),
+            Instruction::SkpKeypad2(x) => xnn(0xE, x, 0xF2),
+            Instruction::SknpKeypad2(x) => xnn(0xE, x, 0xF5),
+            Instruction::LdILong(_) => 0xF000,
+            Instruction::Plane(n) => xnn(0xF, n, 0x01),
+            Instruction::LdAudio => 0xF002,
+            Instruction::LdVxDt(x) => xnn(0xF, x, 0x07),
+            Instruction::LdVxK(x) => xnn(0xF, x, 0x0A),
+            Instruction::LdDtVx(x) => xnn(0xF, x, 0x15),
+            Instruction::LdStVx(x) => xnn(0xF, x, 0x18),
+            Instruction::AddI(x) => xnn(0xF, x, 0x1E),
+            Instruction::LdF(x) => xnn(0xF, x, 0x29),
+            Instruction::LdHf(x) => xnn(0xF, x, 0x30),
+            Instruction::LdB(x) => xnn(0xF, x, 0x33),
+            Instruction::Pitch(x) => xnn(0xF, x, 0x3A),
+            Instruction::LdIVx(x) => xnn(0xF, x, 0x55),
+            Instruction::LdVxI(x) => xnn(0xF, x, 0x65),
+            Instruction::LdRVx(x) => xnn(0xF, x, 0x75),
+            Instruction::LdVxR(x) => xnn(0xF, x, 0x85),
+        }
+    }
+
+    /// The instruction as it's stored in memory, 2 bytes or 4 for `F000 nnnn`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.encode().to_be_bytes().to_vec();
+
+        if let Instruction::LdILong(addr) = self {
+            bytes.extend_from_slice(&addr.to_be_bytes());
+        }
+
+        bytes
+    }
+
+    /// Whether the instruction was added by SUPER-CHIP and doesn't exist on plain CHIP-8
+    pub fn is_superchip(&self) -> bool {
+        matches!(
+            self,
+            Instruction::ScrollDown(_)
+                | Instruction::ScrollRight
+                | Instruction::ScrollLeft
+                | Instruction::Exit
+                | Instruction::Lores
+                | Instruction::Hires
+                | Instruction::LdHf(_)
+                | Instruction::LdRVx(_)
+                | Instruction::LdVxR(_)
+        )
+    }
+
+    /// Whether the instruction only exists on CHIP-8X
+    pub fn is_chip8x(&self) -> bool {
+        matches!(
+            self,
+            Instruction::StepBackground
+                | Instruction::Colour(..)
+                | Instruction::SkpKeypad2(_)
+                | Instruction::SknpKeypad2(_)
+        )
+    }
+
+    /// Whether the instruction was added by XO-CHIP and doesn't exist on SUPER-CHIP
+    pub fn is_xochip(&self) -> bool {
+        matches!(
+            self,
+            Instruction::ScrollUp(_)
+                | Instruction::SaveRange(..)
+                | Instruction::LoadRange(..)
+                | Instruction::LdILong(_)
+                | Instruction::Plane(_)
+                | Instruction::LdAudio
+                | Instruction::Pitch(_)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_encode_round_trip() {
+        for opcode in 0..=u16::MAX {
+            if let Ok(instruction) = Instruction::decode(opcode) {
+                assert_eq!(instruction.encode(), opcode, "{instruction:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn decode_chip8x_encode_round_trip() {
+        for opcode in 0..=u16::MAX {
+            if let Ok(instruction) = Instruction::decode_chip8x(opcode) {
+                assert_eq!(instruction.encode(), opcode, "{instruction:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn decode_known_opcodes() {
+        assert_eq!(Instruction::decode(0x00E0), Ok(Instruction::Cls));
+        assert_eq!(Instruction::decode(0x1ABC), Ok(Instruction::Jp(0xABC)));
+        assert_eq!(Instruction::decode(0x6A42), Ok(Instruction::Ld(0xA, 0x42)));
+        assert_eq!(
+            Instruction::decode(0xD125),
+            Ok(Instruction::Drw(0x1, 0x2, 0x5))
+        );
+        assert_eq!(Instruction::decode(0xF365), Ok(Instruction::LdVxI(0x3)));
+    }
+
+    #[test]
+    fn decode_superchip_opcodes() {
+        assert_eq!(Instruction::decode(0x00C4), Ok(Instruction::ScrollDown(4)));
+        assert_eq!(Instruction::decode(0x00FD), Ok(Instruction::Exit));
+        assert_eq!(Instruction::decode(0x00FF), Ok(Instruction::Hires));
+        assert_eq!(Instruction::decode(0xF230), Ok(Instruction::LdHf(0x2)));
+        assert_eq!(Instruction::decode(0xF785), Ok(Instruction::LdVxR(0x7)));
+        assert!(Instruction::Hires.is_superchip());
+        assert!(!Instruction::Cls.is_superchip());
+    }
+
+    #[test]
+    fn decode_chip8x_opcodes() {
+        assert_eq!(
+            Instruction::decode_chip8x(0x02A0),
+            Ok(Instruction::StepBackground)
+        );
+        assert_eq!(
+            Instruction::decode_chip8x(0xB123),
+            Ok(Instruction::Colour(0x1, 0x2, 0x3))
+        );
+        assert_eq!(
+            Instruction::decode_chip8x(0xE4F2),
+            Ok(Instruction::SkpKeypad2(0x4))
+        );
+        assert_eq!(
+            Instruction::decode_chip8x(0x1ABC),
+            Ok(Instruction::Jp(0xABC))
+        );
+
+        // Plain CHIP-8 still jumps
+        assert_eq!(Instruction::decode(0xB123), Ok(Instruction::JpV0(0x123)));
+    }
+
+    #[test]
+    fn decode_xochip_opcodes() {
+        assert_eq!(
+            Instruction::decode_long(0xF000, 0x1234),
+            Ok(Instruction::LdILong(0x1234))
+        );
+        assert_eq!(
+            Instruction::decode(0x5AB2),
+            Ok(Instruction::SaveRange(0xA, 0xB))
+        );
+        assert_eq!(Instruction::decode(0xF301), Ok(Instruction::Plane(3)));
+        assert_eq!(Instruction::decode(0xF002), Ok(Instruction::LdAudio));
+        assert_eq!(Instruction::decode(0x00D2), Ok(Instruction::ScrollUp(2)));
+
+        let long = Instruction::LdILong(0xBEEF);
+        assert_eq!(long.size(), 4);
+        assert_eq!(long.to_bytes(), [0xF0, 0x00, 0xBE, 0xEF]);
+        assert!(long.is_xochip());
+    }
+
+    #[test]
+    fn decode_unknown_opcodes() {
+        for opcode in [0x5121, 0x8AB8, 0x9001, 0xE0FF, 0xF0FF] {
+            assert_eq!(Instruction::decode(opcode), Err(DecodeError { opcode }));
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use super::display::Colours;
+use std::time::{Duration, Instant};
+
+/// Something that can show the contents of the frame buffer
+pub trait DisplaySink {
+    /// Shows the frame buffer, one byte per pixel in row-major order. Bit n of a pixel is
+    /// set when the pixel is on in bitplane n, so pixels are 0 or 1 unless XO-CHIP's second
+    /// plane is in use.
+    fn present(&mut self, buf: &[u8], width: usize, height: usize);
+
+    /// Sets the CHIP-8X colours the next frames are shown with. Only called on CHIP-8X,
+    /// right before `present`. Monochrome displays ignore it.
+    fn set_colours(&mut self, _colours: &Colours) {}
+}
+
+/// The 16-key hexadecimal keypad
+pub trait Keypad {
+    /// Whether the key with the hex value `key` is currently held down
+    fn is_key_pressed(&mut self, key: u8) -> bool;
+
+    /// Whether the key with the hex value `key` is held down on CHIP-8X's second keypad.
+    /// Keyboards without a second keypad never report a key.
+    fn is_second_key_pressed(&mut self, _key: u8) -> bool {
+        false
+    }
+
+    /// Called by `run_frame` before frame number `frame` starts. Keypads that record or
+    /// replay input use it to line key changes up with frames.
+    fn start_frame(&mut self, _frame: u64) {}
+}
+
+/// The buzzer driven by the sound timer
+pub trait AudioSink {
+    /// Starts or stops the tone, called once per frame
+    fn set_playing(&mut self, playing: bool);
+
+    /// Replaces the XO-CHIP audio pattern, 128 1-bit samples played back at
+    /// `4000 * 2^((pitch - 64) / 48)` samples per second. Buzzers that can only beep
+    /// ignore it.
+    fn set_pattern(&mut self, _pattern: &[u8; 16], _pitch: u8) {}
+}
+
+/// A monotonic clock used to pace frames
+pub trait Clock {
+    /// The time elapsed since some fixed point in the past
+    fn now(&self) -> Duration;
+
+    /// Blocks for `duration`
+    fn sleep(&mut self, duration: Duration);
+}
+
+/// A display that throws every frame away
+#[derive(Debug, Default)]
+pub struct NullDisplay;
+
+impl DisplaySink for NullDisplay {
+    fn present(&mut self, _buf: &[u8], _width: usize, _height: usize) {}
+}
+
+/// A keypad where no key is ever pressed
+#[derive(Debug, Default)]
+pub struct NullKeypad;
+
+impl Keypad for NullKeypad {
+    fn is_key_pressed(&mut self, _key: u8) -> bool {
+        false
+    }
+}
+
+/// A buzzer that never makes a sound
+#[derive(Debug, Default)]
+pub struct NullAudio;
+
+impl AudioSink for NullAudio {
+    fn set_playing(&mut self, _playing: bool) {}
+}
+
+/// The host's monotonic clock
+#[derive(Debug)]
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        SystemClock {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
@@ -0,0 +1,98 @@
+/// The behaviours that differ between CHIP-8 interpreters. Each field toggles one
+/// of the well known "quirks" so a ROM can be run with the conventions it was written for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// `8xy6`/`8xyE` shift Vy into Vx instead of shifting Vx in place
+    pub shift_uses_vy: bool,
+
+    /// `Fx55`/`Fx65` leave I pointing past the last register that was stored or loaded
+    pub memory_increments_i: bool,
+
+    /// `Bnnn` is treated as `BXnn` and jumps to nnn + Vx instead of nnn + V0
+    pub jump_uses_vx: bool,
+
+    /// `8xy1`/`8xy2`/`8xy3` reset VF to 0
+    pub logic_resets_vf: bool,
+
+    /// Sprites are clipped at the edges of the screen instead of wrapping around
+    pub clip_sprites: bool,
+
+    /// `Dxyn` waits for the next vertical blank before drawing
+    pub display_wait: bool,
+}
+
+impl Quirks {
+    /// The original COSMAC VIP interpreter
+    pub fn vip() -> Self {
+        Quirks {
+            shift_uses_vy: true,
+            memory_increments_i: true,
+            jump_uses_vx: false,
+            logic_resets_vf: true,
+            clip_sprites: true,
+            display_wait: true,
+        }
+    }
+
+    /// CHIP-48 on the HP-48 calculators
+    pub fn chip48() -> Self {
+        Quirks {
+            shift_uses_vy: false,
+            memory_increments_i: true,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            clip_sprites: true,
+            display_wait: false,
+        }
+    }
+
+    /// SUPER-CHIP 1.1
+    pub fn schip() -> Self {
+        Quirks {
+            shift_uses_vy: false,
+            memory_increments_i: false,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            clip_sprites: true,
+            display_wait: false,
+        }
+    }
+
+    /// XO-CHIP as implemented by Octo
+    pub fn xochip() -> Self {
+        Quirks {
+            shift_uses_vy: true,
+            memory_increments_i: true,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            clip_sprites: false,
+            display_wait: false,
+        }
+    }
+
+    /// Looks up a preset by name, e.g. `vip`, `chip48`, `schip` or `xochip`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "vip" | "chip8" | "chip-8" => Some(Self::vip()),
+            "chip48" | "chip-48" => Some(Self::chip48()),
+            "schip" | "schip1.1" | "superchip" => Some(Self::schip()),
+            "xochip" | "xo-chip" => Some(Self::xochip()),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Quirks {
+    /// The conventions this emulator has always used: shifts operate on Vx in place and
+    /// `Fx55`/`Fx65` increment I.
+    fn default() -> Self {
+        Quirks {
+            shift_uses_vy: false,
+            memory_increments_i: true,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            clip_sprites: true,
+            display_wait: false,
+        }
+    }
+}
@@ -0,0 +1,115 @@
+/// Where `Cxnn` gets its random numbers. Every source is deterministic, so two runs with
+/// the same source and the same input behave the same.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Random {
+    /// SplitMix64 started from `seed`, the default
+    Seeded { seed: u64, state: u64 },
+
+    /// Modeled on the COSMAC VIP interpreter's generator, which counts every instruction
+    /// it fetches in the 16 bit register R9. `Cxnn` mixes the low byte of the count into
+    /// the high byte, so the numbers depend on how many instructions ran since the last
+    /// one like on the real machine.
+    Vip { r9: u16 },
+
+    /// Returns `values` in order and starts over at the end, for tests
+    Sequence { values: Vec<u8>, next: usize },
+}
+
+impl Default for Random {
+    fn default() -> Self {
+        Random::seeded(0)
+    }
+}
+
+impl Random {
+    pub fn seeded(seed: u64) -> Self {
+        Random::Seeded { seed, state: seed }
+    }
+
+    /// The VIP generator with R9 starting at the low 16 bits of `seed`
+    pub fn vip(seed: u64) -> Self {
+        Random::Vip { r9: seed as u16 }
+    }
+
+    pub fn sequence(values: Vec<u8>) -> Self {
+        Random::Sequence { values, next: 0 }
+    }
+
+    /// Called for every instruction, only the VIP generator cares
+    pub fn tick(&mut self) {
+        if let Random::Vip { r9 } = self {
+            *r9 = r9.wrapping_add(1);
+        }
+    }
+
+    /// The next random byte
+    pub fn next_byte(&mut self) -> u8 {
+        match self {
+            Random::Seeded { state, .. } => {
+                *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+
+                let mut z = *state;
+                z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+                z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+
+                (z ^ (z >> 31)) as u8
+            }
+            Random::Vip { r9 } => {
+                let [high, low] = r9.to_be_bytes();
+                let high = high.wrapping_add(low).rotate_right(1) ^ low;
+
+                *r9 = u16::from_be_bytes([high, low.wrapping_add(1)]);
+
+                high
+            }
+            Random::Sequence { values, next } => {
+                if values.is_empty() {
+                    return 0;
+                }
+
+                let value = values[*next % values.len()];
+                *next = (*next + 1) % values.len();
+
+                value
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_numbers() {
+        let mut a = Random::seeded(1234);
+        let mut b = Random::seeded(1234);
+        let mut c = Random::seeded(1235);
+
+        let a: Vec<_> = (0..16).map(|_| a.next_byte()).collect();
+        let b: Vec<_> = (0..16).map(|_| b.next_byte()).collect();
+        let c: Vec<_> = (0..16).map(|_| c.next_byte()).collect();
+
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn vip_numbers_depend_on_timing() {
+        let mut fast = Random::vip(0);
+        let mut slow = Random::vip(0);
+        slow.tick();
+
+        assert_ne!(fast.next_byte(), slow.next_byte());
+    }
+
+    #[test]
+    fn sequence_repeats() {
+        let mut random = Random::sequence(vec![1, 2, 3]);
+
+        let numbers: Vec<_> = (0..5).map(|_| random.next_byte()).collect();
+
+        assert_eq!(numbers, [1, 2, 3, 1, 2]);
+        assert_eq!(Random::sequence(Vec::new()).next_byte(), 0);
+    }
+}
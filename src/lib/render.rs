@@ -0,0 +1,396 @@
+use super::display::Colours;
+use super::platform::DisplaySink;
+use std::io::{self, Write};
+
+/// How pixels are packed into terminal cells
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderMode {
+    /// One cell per pixel
+    Pixel,
+
+    /// `▀▄█` half blocks, one cell covers 1x2 pixels
+    HalfBlock,
+
+    /// Braille patterns, one cell covers 2x4 pixels
+    Braille,
+}
+
+impl RenderMode {
+    /// How many pixels one cell covers horizontally and vertically
+    pub fn cell_size(&self) -> (usize, usize) {
+        match self {
+            RenderMode::Pixel => (1, 1),
+            RenderMode::HalfBlock => (1, 2),
+            RenderMode::Braille => (2, 4),
+        }
+    }
+
+    /// Looks up a render mode by name, e.g. `pixel`, `half` or `braille`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "pixel" => Some(RenderMode::Pixel),
+            "half" | "halfblock" | "half-block" => Some(RenderMode::HalfBlock),
+            "braille" => Some(RenderMode::Braille),
+            _ => None,
+        }
+    }
+}
+
+/// The Braille dot bit for the pixel at (x, y) inside a 2x4 cell
+const BRAILLE_DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+/// The terminal's own colour, as in the ANSI codes 39 and 49
+const DEFAULT_COLOUR: u8 = 9;
+
+/// The ANSI colour a pixel is drawn with for each of the 4 XO-CHIP pixel colours. Colour 1
+/// is the terminal's own foreground so plain CHIP-8 looks the way the terminal is set up.
+const PIXEL_FOREGROUND: [u8; 4] = [0, DEFAULT_COLOUR, 6, 3];
+
+/// The ANSI colour behind a half block for each of the 4 XO-CHIP pixel colours
+const PIXEL_BACKGROUND: [u8; 4] = [DEFAULT_COLOUR, 7, 6, 3];
+
+/// The ANSI colours for CHIP-8X's black, red, blue, violet, green, yellow, aqua and white
+const CHIP8X_FOREGROUND: [u8; 8] = [0, 1, 4, 5, 2, 3, 6, 7];
+
+/// The ANSI colours for CHIP-8X's blue, black, green and red backgrounds
+const CHIP8X_BACKGROUND: [u8; 4] = [4, 0, 2, 1];
+
+/// One character on the terminal and the colours it's drawn with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub glyph: char,
+
+    /// The ANSI colour of the glyph, 0 to 7 or 9 for the terminal's own colour
+    pub fg: u8,
+
+    /// The ANSI colour behind the glyph, 0 to 7 or 9 for the terminal's own colour
+    pub bg: u8,
+}
+
+impl Cell {
+    /// An empty cell
+    const BLANK: Cell = Cell {
+        glyph: ' ',
+        fg: DEFAULT_COLOUR,
+        bg: DEFAULT_COLOUR,
+    };
+
+    /// A glyph drawn in pixel colour `colour` on the terminal's background
+    fn new(glyph: char, colour: u8) -> Self {
+        Cell {
+            glyph,
+            fg: PIXEL_FOREGROUND[colour as usize],
+            bg: DEFAULT_COLOUR,
+        }
+    }
+}
+
+/// Converts a frame buffer into rows of terminal cells. Each pixel is a colour from 0 to
+/// 3, with one bit per XO-CHIP bitplane. Returns the cells in row-major order and the
+/// number of columns.
+pub fn render_cells(
+    buf: &[u8],
+    width: usize,
+    height: usize,
+    mode: RenderMode,
+) -> (Vec<Cell>, usize) {
+    let (cell_width, cell_height) = mode.cell_size();
+    let columns = width.div_ceil(cell_width);
+    let rows = height.div_ceil(cell_height);
+
+    // Pixels outside the frame buffer count as off
+    let pixel = |x: usize, y: usize| {
+        if x < width && y < height {
+            buf[x + y * width] & 0x3
+        } else {
+            0
+        }
+    };
+
+    let mut cells = Vec::with_capacity(columns * rows);
+    for row in 0..rows {
+        for column in 0..columns {
+            let x = column * cell_width;
+            let y = row * cell_height;
+
+            let cell = match mode {
+                RenderMode::Pixel => match pixel(x, y) {
+                    0 => Cell::BLANK,
+                    colour => Cell::new('■', colour),
+                },
+                RenderMode::HalfBlock => match (pixel(x, y), pixel(x, y + 1)) {
+                    (0, 0) => Cell::BLANK,
+                    (top, bottom) if top == bottom => Cell::new('█', top),
+                    (0, bottom) => Cell::new('▄', bottom),
+                    (top, 0) => Cell::new('▀', top),
+                    (top, bottom) => Cell {
+                        glyph: '▀',
+                        fg: PIXEL_FOREGROUND[top as usize],
+                        bg: PIXEL_BACKGROUND[bottom as usize],
+                    },
+                },
+                RenderMode::Braille => {
+                    // A cell only has one colour, the highest one in it wins
+                    let mut dots = 0;
+                    let mut colour = 0;
+                    for (dy, row_dots) in BRAILLE_DOTS.iter().enumerate() {
+                        for (dx, dot) in row_dots.iter().enumerate() {
+                            let pixel = pixel(x + dx, y + dy);
+                            if pixel != 0 {
+                                dots |= dot;
+                                colour = colour.max(pixel);
+                            }
+                        }
+                    }
+
+                    match char::from_u32(0x2800 + dots) {
+                        Some(glyph) if dots != 0 => Cell::new(glyph, colour),
+                        _ => Cell::BLANK,
+                    }
+                }
+            };
+
+            cells.push(cell);
+        }
+    }
+
+    (cells, columns)
+}
+
+/// Paints cells made by [`render_cells`] with the CHIP-8X colours. A cell takes the
+/// foreground colour of its top left pixel.
+pub fn apply_colours(
+    cells: &mut [Cell],
+    columns: usize,
+    width: usize,
+    mode: RenderMode,
+    colours: &Colours,
+) {
+    let (cell_width, cell_height) = mode.cell_size();
+    let background = CHIP8X_BACKGROUND[colours.background as usize % 4];
+
+    for (i, cell) in cells.iter_mut().enumerate() {
+        let x = (i % columns) * cell_width;
+        let y = (i / columns) * cell_height;
+
+        cell.fg = CHIP8X_FOREGROUND[colours.foreground_at(x, y, width) as usize % 8];
+        cell.bg = background;
+    }
+}
+
+/// Draws frames to a terminal, only writing the cells that changed since the last frame
+pub struct TerminalRenderer<W: Write = io::Stdout> {
+    pub mode: RenderMode,
+
+    out: W,
+
+    /// The cells currently on screen. `show_commands` prints instructions instead of
+    /// frames, so nothing is on screen then.
+    #[cfg(not(feature = "show_commands"))]
+    previous: Vec<Cell>,
+    #[cfg(not(feature = "show_commands"))]
+    previous_columns: usize,
+
+    /// The CHIP-8X colours, if the program has any
+    colours: Option<Colours>,
+}
+
+impl TerminalRenderer {
+    /// A renderer that draws to standard output
+    pub fn new(mode: RenderMode) -> Self {
+        Self::with_writer(mode, io::stdout())
+    }
+}
+
+impl<W: Write> TerminalRenderer<W> {
+    pub fn with_writer(mode: RenderMode, out: W) -> Self {
+        TerminalRenderer {
+            mode,
+            out,
+            #[cfg(not(feature = "show_commands"))]
+            previous: Vec::new(),
+            #[cfg(not(feature = "show_commands"))]
+            previous_columns: 0,
+            colours: None,
+        }
+    }
+
+    /// The writer frames are drawn to
+    pub fn writer(&self) -> &W {
+        &self.out
+    }
+
+    /// Writes the escape codes to turn the previous frame into `cells`
+    #[cfg(not(feature = "show_commands"))]
+    fn write_diff(&mut self, cells: &[Cell], columns: usize) -> io::Result<()> {
+        let mut frame = String::new();
+
+        // The screen size changed, start over with a clear screen
+        if columns != self.previous_columns || cells.len() != self.previous.len() {
+            frame.push_str("\x1B[2J");
+            self.previous = vec![Cell::BLANK; cells.len()];
+            self.previous_columns = columns;
+        }
+
+        // Where the terminal's cursor is after the last write, to skip needless moves
+        let mut cursor = None;
+
+        // The colours the terminal currently draws with, which start out as the defaults
+        let mut pen = (Cell::BLANK.fg, Cell::BLANK.bg);
+
+        for (i, (&cell, previous)) in cells.iter().zip(self.previous.iter_mut()).enumerate() {
+            if cell == *previous {
+                continue;
+            }
+
+            let position = (i / columns, i % columns);
+            if cursor != Some(position) {
+                frame.push_str(&format!("\x1B[{};{}H", position.0 + 1, position.1 + 1));
+            }
+
+            // Blank cells only show their background, so any foreground will do
+            let fg = if cell.glyph == ' ' { pen.0 } else { cell.fg };
+            if (fg, cell.bg) != pen {
+                pen = (fg, cell.bg);
+                frame.push_str(&format!("\x1B[{};{}m", 30 + fg, 40 + cell.bg));
+            }
+
+            frame.push(cell.glyph);
+            *previous = cell;
+            cursor = Some((position.0, position.1 + 1));
+        }
+
+        // Leave the terminal with its default colours
+        if pen != (Cell::BLANK.fg, Cell::BLANK.bg) {
+            frame.push_str("\x1B[0m");
+        }
+
+        if !frame.is_empty() {
+            self.out.write_all(frame.as_bytes())?;
+            self.out.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<W: Write> DisplaySink for TerminalRenderer<W> {
+    #[cfg(feature = "show_commands")]
+    fn present(&mut self, _buf: &[u8], _width: usize, _height: usize) {}
+
+    #[cfg(not(feature = "show_commands"))]
+    fn present(&mut self, buf: &[u8], width: usize, height: usize) {
+        let (mut cells, columns) = render_cells(buf, width, height, self.mode);
+
+        if let Some(colours) = &self.colours {
+            apply_colours(&mut cells, columns, width, self.mode, colours);
+        }
+
+        // A frame that fails to draw is simply dropped, the next one tries again
+        let _ = self.write_diff(&cells, columns);
+    }
+
+    fn set_colours(&mut self, colours: &Colours) {
+        self.colours = Some(colours.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glyphs(cells: &[Cell]) -> String {
+        cells.iter().map(|cell| cell.glyph).collect()
+    }
+
+    #[test]
+    fn half_blocks() {
+        let buf = [1, 0, 1, 0, 0, 1, 1, 0];
+
+        let (cells, columns) = render_cells(&buf, 4, 2, RenderMode::HalfBlock);
+
+        assert_eq!(columns, 4);
+        assert_eq!(glyphs(&cells), "▀▄█ ");
+    }
+
+    #[test]
+    fn braille() {
+        // The left column of a 2x4 cell
+        let buf = [1, 0, 1, 0, 1, 0, 1, 0];
+
+        let (cells, columns) = render_cells(&buf, 2, 4, RenderMode::Braille);
+
+        assert_eq!(columns, 1);
+        assert_eq!(glyphs(&cells), "⡇");
+    }
+
+    #[test]
+    fn braille_fits_schip_screen_in_80_columns() {
+        let buf = [0; 128 * 64];
+
+        let (cells, columns) = render_cells(&buf, 128, 64, RenderMode::Braille);
+
+        assert_eq!(columns, 64);
+        assert_eq!(cells.len() / columns, 16);
+    }
+
+    #[test]
+    #[cfg(not(feature = "show_commands"))]
+    fn only_changed_cells_are_written() {
+        let mut renderer = TerminalRenderer::with_writer(RenderMode::Pixel, Vec::new());
+
+        let blank = [Cell::BLANK; 4];
+        renderer.write_diff(&blank, 2).unwrap();
+        let first_frame = renderer.writer().len();
+
+        let mut cells = blank;
+        cells[3] = Cell::new('■', 1);
+        renderer.write_diff(&cells, 2).unwrap();
+        let second_frame = String::from_utf8(renderer.writer()[first_frame..].to_vec()).unwrap();
+
+        assert_eq!(second_frame, "\x1B[2;2H■");
+    }
+
+    #[test]
+    fn half_blocks_with_two_colours() {
+        // Plane 1 on top, plane 2 below
+        let buf = [1, 2];
+
+        let (cells, _) = render_cells(&buf, 1, 2, RenderMode::HalfBlock);
+
+        assert_eq!(
+            cells,
+            [Cell {
+                glyph: '▀',
+                fg: DEFAULT_COLOUR,
+                bg: 6
+            }]
+        );
+    }
+
+    #[test]
+    #[cfg(not(feature = "show_commands"))]
+    fn colours_are_reset_after_a_frame() {
+        let mut renderer = TerminalRenderer::with_writer(RenderMode::Pixel, Vec::new());
+
+        renderer.write_diff(&[Cell::new('■', 3)], 1).unwrap();
+        let frame = String::from_utf8(renderer.writer().clone()).unwrap();
+
+        assert_eq!(frame, "\x1B[2J\x1B[1;1H\x1B[33;49m■\x1B[0m");
+    }
+
+    #[test]
+    fn chip8x_colours() {
+        let buf = [1; 16 * 2];
+        let mut colours = Colours::new(16, 2);
+        colours.foreground[1] = 4;
+        colours.background = 0;
+
+        let (mut cells, columns) = render_cells(&buf, 16, 2, RenderMode::HalfBlock);
+        apply_colours(&mut cells, columns, 16, RenderMode::HalfBlock, &colours);
+
+        // Red on the left block, green on the right, both on blue
+        assert_eq!((cells[0].fg, cells[0].bg), (1, 4));
+        assert_eq!((cells[8].fg, cells[8].bg), (2, 4));
+    }
+}
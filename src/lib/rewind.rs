@@ -0,0 +1,237 @@
+use super::cpu::CPU;
+use std::collections::VecDeque;
+
+/// How many seconds of gameplay the frontend keeps by default
+pub const DEFAULT_REWIND_SECONDS: usize = 60;
+
+/// A ring buffer of the last frames' save states for stepping backwards in time.
+///
+/// Only the newest state is stored in full. Every older frame is stored as the difference
+/// to the frame after it, which is small since most of memory and the frame buffer stays
+/// the same from one frame to the next.
+#[derive(Debug, Clone, Default)]
+pub struct Rewind {
+    /// The state of the last captured frame
+    latest: Option<Vec<u8>>,
+
+    /// Turns the state of a frame into the one before it, oldest first
+    deltas: VecDeque<Vec<u8>>,
+
+    /// How many frames before the latest one are kept
+    capacity: usize,
+}
+
+impl Rewind {
+    /// A buffer that can step back `frames` frames
+    pub fn new(frames: usize) -> Self {
+        Rewind {
+            latest: None,
+            deltas: VecDeque::new(),
+            capacity: frames,
+        }
+    }
+
+    /// A buffer that can step back `seconds` seconds of 60 Hz frames
+    pub fn with_seconds(seconds: usize) -> Self {
+        Self::new(seconds * 60)
+    }
+
+    /// How many frames can currently be stepped back
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    /// Forgets every captured frame, e.g. after loading a save state
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+    }
+
+    /// Captures the state of `cpu`, called once after every frame. The oldest frame is
+    /// dropped once the buffer is full.
+    pub fn capture(&mut self, cpu: &CPU) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let state = cpu.save_state();
+
+        if let Some(previous) = self.latest.take() {
+            self.deltas.push_back(encode_delta(&state, &previous));
+
+            if self.deltas.len() > self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+
+        self.latest = Some(state);
+    }
+
+    /// Puts `cpu` back to the frame before the latest captured one, which then becomes the
+    /// latest. Returns false without changing anything when there's nothing to go back to.
+    pub fn step_back(&mut self, cpu: &mut CPU) -> bool {
+        let (Some(latest), Some(delta)) = (&self.latest, self.deltas.pop_back()) else {
+            return false;
+        };
+
+        let previous = apply_delta(latest, &delta);
+
+        // Every captured state was made by `save_state`, so it always loads
+        cpu.load_state(&previous)
+            .expect("rewind states are valid save states");
+        self.latest = Some(previous);
+
+        true
+    }
+}
+
+/// Encodes what turns `from` into `to`: the length of `to`, then runs of unchanged bytes
+/// and changed bytes XOR-ed with `from`, each run prefixed with its length as a varint
+fn encode_delta(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    write_varint(&mut delta, to.len());
+
+    let xor = |i: usize| to[i] ^ from.get(i).copied().unwrap_or(0);
+
+    let mut i = 0;
+    while i < to.len() {
+        let unchanged = (i..to.len()).take_while(|&j| xor(j) == 0).count();
+        i += unchanged;
+
+        let changed = (i..to.len()).take_while(|&j| xor(j) != 0).count();
+        write_varint(&mut delta, unchanged);
+        write_varint(&mut delta, changed);
+        delta.extend((i..i + changed).map(xor));
+        i += changed;
+    }
+
+    delta
+}
+
+/// Applies a delta made by `encode_delta` to `from`
+fn apply_delta(from: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut delta = delta.iter().copied();
+    let len = read_varint(&mut delta);
+
+    let mut to = from.to_vec();
+    to.resize(len, 0);
+
+    let mut i = 0;
+    while i < len {
+        i += read_varint(&mut delta);
+
+        let changed = read_varint(&mut delta);
+        for (byte, xor) in to[i..i + changed].iter_mut().zip(delta.by_ref()) {
+            *byte ^= xor;
+        }
+        i += changed;
+    }
+
+    to
+}
+
+/// Writes `value` 7 bits at a time, low bits first, with the top bit set on all but the
+/// last byte
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(bytes: &mut impl Iterator<Item = u8>) -> usize {
+    let mut value = 0;
+
+    for (shift, byte) in bytes.enumerate() {
+        value |= (byte as usize & 0x7F) << (shift * 7);
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::variant::Platform;
+
+    #[test]
+    fn deltas_round_trip() {
+        let from = [1, 2, 3, 4, 5, 6, 7, 8];
+        let to = [1, 2, 9, 4, 5, 0, 0, 8, 10, 11];
+
+        assert_eq!(apply_delta(&from, &encode_delta(&from, &to)), to);
+        assert_eq!(apply_delta(&to, &encode_delta(&to, &from)), from);
+    }
+
+    #[test]
+    fn unchanged_states_are_small() {
+        let state = CPU::new().save_state();
+
+        assert!(encode_delta(&state, &state).len() < 8);
+    }
+
+    #[test]
+    fn step_back_restores_earlier_frames() {
+        // Counts frames in V0: ADD V0, 1; JP 0x200
+        let mut cpu = CPU::new_with_memory(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        let mut rewind = Rewind::new(60);
+
+        for _ in 0..5 {
+            cpu.run_cycles(2).unwrap();
+            rewind.capture(&cpu);
+        }
+        assert_eq!(cpu.registers[0], 5);
+
+        assert!(rewind.step_back(&mut cpu));
+        assert_eq!(cpu.registers[0], 4);
+        assert!(rewind.step_back(&mut cpu));
+        assert_eq!(cpu.registers[0], 3);
+
+        // Running again continues from the restored frame
+        cpu.run_cycles(2).unwrap();
+        rewind.capture(&cpu);
+        assert!(rewind.step_back(&mut cpu));
+        assert_eq!(cpu.registers[0], 3);
+    }
+
+    #[test]
+    fn oldest_frames_are_dropped() {
+        let mut cpu = CPU::new_with_memory(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        let mut rewind = Rewind::new(2);
+
+        for _ in 0..5 {
+            cpu.run_cycles(2).unwrap();
+            rewind.capture(&cpu);
+        }
+
+        assert_eq!(rewind.len(), 2);
+        assert!(rewind.step_back(&mut cpu));
+        assert!(rewind.step_back(&mut cpu));
+        assert_eq!(cpu.registers[0], 3);
+        assert!(!rewind.step_back(&mut cpu));
+        assert_eq!(cpu.registers[0], 3);
+    }
+
+    #[test]
+    fn step_back_across_a_resolution_change() {
+        let mut cpu = CPU::with_platform(Platform::SuperChip, &[0x00, 0xFF]).unwrap();
+        let mut rewind = Rewind::new(60);
+
+        rewind.capture(&cpu);
+        cpu.run_cycles(1).unwrap();
+        rewind.capture(&cpu);
+        assert!(cpu.is_hires());
+
+        assert!(rewind.step_back(&mut cpu));
+        assert!(!cpu.is_hires());
+        assert_eq!(cpu.pc, 0x200);
+    }
+}
@@ -0,0 +1,358 @@
+use super::opcode::Instruction;
+use super::platform::{Clock, SystemClock};
+use std::time::Duration;
+
+/// Instructions per frame that roughly match the speed of the COSMAC VIP
+pub const VIP_INSTRUCTIONS_PER_FRAME: u32 = 11;
+
+/// Instructions per frame commonly used for SUPER-CHIP games
+pub const SCHIP_INSTRUCTIONS_PER_FRAME: u32 = 30;
+
+/// Instructions per frame used by Octo for XO-CHIP games
+pub const XOCHIP_INSTRUCTIONS_PER_FRAME: u32 = 1000;
+
+/// The length of one 60 Hz frame in microseconds
+pub const FRAME_MICROS: u32 = 1_000_000 / 60;
+
+/// The COSMAC VIP's 1802 runs 3668 machine cycles of 8 clock pulses every 60 Hz frame, 14
+/// for each of the 262 lines its video chip sends to the TV
+pub const VIP_CYCLES_PER_FRAME: u32 = 3668;
+
+/// The machine cycles of every frame the VIP doesn't spend on the interpreter. The video
+/// chip takes 8 of the 14 cycles of each of the 128 lines it shows to fetch pixels by DMA,
+/// and the interrupt routine that starts it and counts down the timers takes about 46.
+pub const VIP_DISPLAY_CYCLES: u32 = 128 * 8 + 46;
+
+/// The machine cycles the VIP interpreter's main loop takes to fetch an instruction and
+/// jump to the code for it
+const VIP_FETCH_CYCLES: u32 = 40;
+
+/// How much work fits into one 60 Hz frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameBudget {
+    /// Every instruction counts as one, the frame ends after this many instructions
+    Instructions(u32),
+
+    /// Every instruction takes as many machine cycles as it did on the COSMAC VIP, the
+    /// frame ends when the interpreter's share of the frame is used up
+    VipTiming,
+}
+
+impl FrameBudget {
+    /// The size of a frame in the units returned by `cost`
+    pub fn size(&self) -> u32 {
+        match self {
+            FrameBudget::Instructions(n) => *n,
+            FrameBudget::VipTiming => VIP_CYCLES_PER_FRAME - VIP_DISPLAY_CYCLES,
+        }
+    }
+
+    /// How much of the frame an instruction uses up. `registers` are the ones it ran
+    /// with and `skipped` tells whether it skipped the next instruction.
+    pub fn cost(&self, instruction: &Instruction, registers: &[u8; 16], skipped: bool) -> u32 {
+        match self {
+            FrameBudget::Instructions(_) => 1,
+            FrameBudget::VipTiming => {
+                VIP_FETCH_CYCLES + vip_cycles(instruction, registers, skipped)
+            }
+        }
+    }
+}
+
+impl Default for FrameBudget {
+    fn default() -> Self {
+        FrameBudget::Instructions(VIP_INSTRUCTIONS_PER_FRAME)
+    }
+}
+
+/// The machine cycles the COSMAC VIP interpreter's code for an instruction takes, not
+/// counting the fetch. Skips take 4 more cycles when they skip, and the instructions that
+/// loop take longer the more they have to do.
+pub fn vip_cycles(instruction: &Instruction, registers: &[u8; 16], skipped: bool) -> u32 {
+    let skip = |cycles: u32| if skipped { cycles + 4 } else { cycles };
+
+    match instruction {
+        // Clearing the screen writes each of its 256 bytes
+        Instruction::Cls => 24 + 3054,
+        Instruction::Ret => 10,
+        Instruction::Jp(_) => 12,
+        Instruction::Call(_) => 26,
+        // Crossing into the next page takes an extra long branch
+        Instruction::JpV0(addr) => match (addr & 0xFF) + registers[0] as u16 > 0xFF {
+            true => 24,
+            false => 22,
+        },
+        Instruction::Se(..) | Instruction::Sne(..) => skip(10),
+        Instruction::SeReg(..) | Instruction::SneReg(..) => skip(14),
+        Instruction::Ld(..) => 6,
+        Instruction::Add(..) => 10,
+        Instruction::LdReg(..) => 12,
+        // The other 8xyN build the ALU instruction in memory, run it and store the carry
+        Instruction::Or(..)
+        | Instruction::And(..)
+        | Instruction::Xor(..)
+        | Instruction::AddReg(..)
+        | Instruction::Sub(..)
+        | Instruction::Shr(..)
+        | Instruction::Subn(..)
+        | Instruction::Shl(..) => 44,
+        Instruction::LdI(_) => 12,
+        Instruction::Rnd(..) => 36,
+        Instruction::Drw(x, _, n) => vip_draw_cycles(registers[*x as usize], *n),
+        Instruction::Skp(_) | Instruction::Sknp(_) => skip(14),
+        Instruction::LdVxDt(_) | Instruction::LdDtVx(_) | Instruction::LdStVx(_) => 10,
+        // Each check of the keypad while waiting for a key
+        Instruction::LdVxK(_) => 18,
+        Instruction::AddI(_) => 12,
+        Instruction::LdF(_) => 16,
+        // Every digit is found by subtracting its power of 10 until it goes below zero
+        Instruction::LdB(x) => {
+            let value = registers[*x as usize] as u32;
+            80 + 16 * (value / 100 + value / 10 % 10 + value % 10)
+        }
+        Instruction::LdIVx(x) | Instruction::LdVxI(x) => 14 + 14 * (*x as u32 + 1),
+
+        // SUPER-CHIP instructions never ran on the VIP, charge them like their closest
+        // VIP counterparts
+        Instruction::ScrollDown(_)
+        | Instruction::ScrollRight
+        | Instruction::ScrollLeft
+        | Instruction::Lores
+        | Instruction::Hires => 24 + 3054,
+        Instruction::Sys(_) | Instruction::Exit => 0,
+        Instruction::LdHf(_) => 16,
+        Instruction::LdRVx(x) | Instruction::LdVxR(x) => 14 + 14 * (*x as u32 + 1),
+
+        // The same goes for XO-CHIP
+        Instruction::ScrollUp(_) => 24 + 3054,
+        Instruction::SaveRange(x, y) | Instruction::LoadRange(x, y) => {
+            14 + 14 * (x.abs_diff(*y) as u32 + 1)
+        }
+        Instruction::LdAudio => 14 + 14 * 16,
+        Instruction::LdILong(_) => 24,
+        Instruction::Plane(_) | Instruction::Pitch(_) => 10,
+
+        // CHIP-8X ran on the VIP, but with the VP-590 doing the colour work
+        Instruction::StepBackground => 24,
+        Instruction::Colour(..) => 44,
+        Instruction::SkpKeypad2(_) | Instruction::SknpKeypad2(_) => skip(14),
+    }
+}
+
+/// The machine cycles the VIP takes to draw an `n` row sprite at column `x`. Every row is
+/// shifted right one bit at a time to where it starts in its byte of the screen, and a row
+/// that doesn't start at the beginning of a byte is XOR-ed into two bytes instead of one.
+/// SUPER-CHIP's 16x16 sprites count as 16 rows of two bytes.
+pub fn vip_draw_cycles(x: u8, n: u8) -> u32 {
+    let shift = (x % 8) as u32;
+    let (rows, bytes) = match n {
+        0 => (16, 2),
+        n => (n as u32, 1),
+    };
+    let bytes_written = if shift == 0 { bytes } else { bytes + 1 };
+
+    68 + rows * (46 + 20 * shift * bytes + 26 * bytes_written)
+}
+
+/// How fast emulated frames are run compared to real time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Speed {
+    /// 60 frames per second
+    Normal,
+
+    /// 120 frames per second
+    Double,
+
+    /// 30 frames per second
+    Half,
+
+    /// As fast as the host can go
+    Unthrottled,
+
+    /// Paused, frames only run when requested with `Scheduler::advance_frame`
+    FrameAdvance,
+}
+
+impl Speed {
+    /// How long a frame should take in real time, `None` if frames aren't paced
+    pub fn frame_duration(&self) -> Option<Duration> {
+        let frame = Duration::from_micros(FRAME_MICROS as u64);
+
+        match self {
+            Speed::Normal => Some(frame),
+            Speed::Double => Some(frame / 2),
+            Speed::Half => Some(frame * 2),
+            Speed::Unthrottled | Speed::FrameAdvance => None,
+        }
+    }
+
+    /// Looks up a speed by name, e.g. `1x`, `2x`, `0.5x`, `unthrottled` or `frame-advance`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "1x" | "normal" => Some(Speed::Normal),
+            "2x" | "double" => Some(Speed::Double),
+            "0.5x" | "half" => Some(Speed::Half),
+            "unthrottled" | "max" => Some(Speed::Unthrottled),
+            "frame-advance" | "pause" => Some(Speed::FrameAdvance),
+            _ => None,
+        }
+    }
+}
+
+/// Paces emulated frames against a monotonic clock
+pub struct Scheduler {
+    speed: Speed,
+
+    clock: Box<dyn Clock>,
+
+    /// When the next frame is due according to `clock`
+    next_frame: Duration,
+
+    /// Frames requested in frame-advance mode that haven't run yet
+    pending_frames: u32,
+}
+
+impl Scheduler {
+    /// A scheduler that paces frames with the host's clock
+    pub fn new(speed: Speed) -> Self {
+        Self::with_clock(speed, Box::new(SystemClock::default()))
+    }
+
+    /// A scheduler that paces frames with a custom clock
+    pub fn with_clock(speed: Speed, clock: Box<dyn Clock>) -> Self {
+        Scheduler {
+            speed,
+            next_frame: clock.now(),
+            clock,
+            pending_frames: 0,
+        }
+    }
+
+    pub fn speed(&self) -> Speed {
+        self.speed
+    }
+
+    /// Changes the speed, pacing restarts from the current time
+    pub fn set_speed(&mut self, speed: Speed) {
+        self.speed = speed;
+        self.next_frame = self.clock.now();
+        self.pending_frames = 0;
+    }
+
+    /// Lets one more frame run while in frame-advance mode
+    pub fn advance_frame(&mut self) {
+        self.pending_frames += 1;
+    }
+
+    /// Blocks until the next frame is due. Returns `false` if no frame should run yet,
+    /// which only happens while paused in frame-advance mode.
+    pub fn wait_for_frame(&mut self) -> bool {
+        let Some(frame) = self.speed.frame_duration() else {
+            if self.speed == Speed::FrameAdvance {
+                if self.pending_frames == 0 {
+                    // Don't spin while paused
+                    self.clock.sleep(Duration::from_micros(FRAME_MICROS as u64));
+                    return false;
+                }
+                self.pending_frames -= 1;
+            }
+
+            return true;
+        };
+
+        let now = self.clock.now();
+        if self.next_frame > now {
+            self.clock.sleep(self.next_frame - now);
+        } else if now - self.next_frame > frame * 4 {
+            // We fell far behind (e.g. the host was suspended), don't try to catch up
+            self.next_frame = now;
+        }
+        self.next_frame += frame;
+
+        true
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new(Speed::Normal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::Cell, rc::Rc};
+
+    /// A clock that only moves when something sleeps on it
+    struct FakeClock(Rc<Cell<Duration>>);
+
+    impl Clock for FakeClock {
+        fn now(&self) -> Duration {
+            self.0.get()
+        }
+
+        fn sleep(&mut self, duration: Duration) {
+            self.0.set(self.0.get() + duration);
+        }
+    }
+
+    #[test]
+    fn double_speed_halves_frame_time() {
+        let time = Rc::new(Cell::new(Duration::ZERO));
+        let mut scheduler = Scheduler::with_clock(Speed::Double, Box::new(FakeClock(time.clone())));
+
+        for _ in 0..121 {
+            scheduler.wait_for_frame();
+        }
+
+        assert_eq!(
+            time.get(),
+            Duration::from_micros(FRAME_MICROS as u64 / 2) * 120
+        );
+    }
+
+    #[test]
+    fn frame_advance_runs_requested_frames() {
+        let mut scheduler = Scheduler::new(Speed::FrameAdvance);
+
+        scheduler.advance_frame();
+
+        assert!(scheduler.wait_for_frame());
+        assert!(!scheduler.wait_for_frame());
+    }
+
+    #[test]
+    fn vip_timing_budget() {
+        let budget = FrameBudget::VipTiming;
+        let registers = [0; 16];
+
+        assert_eq!(budget.size(), 2598);
+        assert_eq!(budget.cost(&Instruction::Ld(0, 1), &registers, false), 46);
+        assert_eq!(
+            FrameBudget::Instructions(30).cost(&Instruction::Cls, &registers, false),
+            1
+        );
+
+        // Skips that skip and 8xyN that go through the ALU take longer
+        let se = Instruction::Se(0, 0);
+        assert!(budget.cost(&se, &registers, true) > budget.cost(&se, &registers, false));
+        assert!(
+            budget.cost(&Instruction::Xor(0, 1), &registers, false)
+                > budget.cost(&Instruction::LdReg(0, 1), &registers, false)
+        );
+
+        // Clearing the screen takes longer than a frame, a small sprite doesn't
+        assert!(budget.cost(&Instruction::Cls, &registers, false) > budget.size());
+        assert!(budget.cost(&Instruction::Drw(0, 0, 1), &registers, false) < budget.size() / 4);
+    }
+
+    #[test]
+    fn vip_sprites_take_longer_when_not_byte_aligned() {
+        assert_eq!(vip_draw_cycles(0, 1), 68 + 46 + 26);
+        assert!(vip_draw_cycles(1, 5) > vip_draw_cycles(0, 5));
+        assert!(vip_draw_cycles(7, 5) > vip_draw_cycles(1, 5));
+        assert_eq!(vip_draw_cycles(8, 5), vip_draw_cycles(0, 5));
+        assert!(vip_draw_cycles(0, 10) > vip_draw_cycles(0, 5));
+    }
+}
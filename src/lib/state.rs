@@ -0,0 +1,566 @@
+use super::cpu::CPU;
+use super::display::Colours;
+use super::random::Random;
+use super::timers::Timers;
+use super::variant::Platform;
+use std::path::{Path, PathBuf};
+use std::{fmt, io};
+
+/// The bytes every save state starts with
+pub const STATE_MAGIC: [u8; 4] = *b"SSC8";
+
+/// The version of the save state format written by [`CPU::save_state`]
+pub const STATE_VERSION: u16 = 2;
+
+/// The oldest save state format version that can still be loaded. Version 1 states don't
+/// have the random number generator, it's left as it is when they're loaded.
+pub const MIN_STATE_VERSION: u16 = 1;
+
+/// Errors that can occur while reading a save state
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    /// The data doesn't start with `STATE_MAGIC`
+    NotAState,
+
+    /// The data was written in a format version this build can't read
+    UnsupportedVersion(u16),
+
+    /// The data ends in the middle of a field
+    Truncated,
+
+    /// A field holds a value no machine could be in, e.g. an unknown platform
+    Invalid(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::NotAState => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "unsupported format version {version}")
+            }
+            StateError::Truncated => write!(f, "the data is truncated"),
+            StateError::Invalid(field) => write!(f, "invalid {field}"),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+/// The part of a save state that describes it, readable without restoring the state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StateHeader {
+    pub version: u16,
+
+    /// The [`rom_hash`] of the ROM that was running when the state was saved
+    pub rom_hash: u64,
+}
+
+impl StateHeader {
+    /// Reads the header at the start of a save state
+    pub fn read(data: &[u8]) -> Result<Self, StateError> {
+        StateReader::new(data).header()
+    }
+}
+
+/// Hashes a ROM with 64 bit FNV-1a so save states can tell which ROM they belong to
+pub fn rom_hash(rom: &[u8]) -> u64 {
+    rom.iter().fold(0xCBF2_9CE4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01B3)
+    })
+}
+
+/// The numbered quick-save slots of a ROM, kept next to the ROM file as `<rom>.state<n>`
+#[derive(Debug, Clone)]
+pub struct SaveSlots {
+    rom_path: PathBuf,
+}
+
+impl SaveSlots {
+    pub fn new(rom_path: impl AsRef<Path>) -> Self {
+        SaveSlots {
+            rom_path: rom_path.as_ref().to_path_buf(),
+        }
+    }
+
+    /// The file slot `slot` is stored in
+    pub fn path(&self, slot: u8) -> PathBuf {
+        self.rom_path.with_extension(format!("state{slot}"))
+    }
+
+    /// Saves the state of `cpu` to slot `slot`
+    pub fn save(&self, cpu: &CPU, slot: u8) -> io::Result<()> {
+        std::fs::write(self.path(slot), cpu.save_state())
+    }
+
+    /// Restores `cpu` from slot `slot`. Returns whether the state was saved while running
+    /// the same ROM, a state from another ROM still loads but is unlikely to work.
+    pub fn load(&self, cpu: &mut CPU, slot: u8) -> io::Result<bool> {
+        let data = std::fs::read(self.path(slot))?;
+        let same_rom = StateHeader::read(&data).map_err(invalid_data)?.rom_hash == cpu.rom_hash;
+
+        cpu.load_state(&data).map_err(invalid_data)?;
+
+        Ok(same_rom)
+    }
+}
+
+fn invalid_data(err: StateError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+impl CPU {
+    /// Serializes the whole machine: memory, registers, stack, timers, the frame buffer and
+    /// the platform state. Settings such as the quirks and the frame budget aren't part of
+    /// the state.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::default();
+
+        state.bytes(&STATE_MAGIC);
+        state.u16(STATE_VERSION);
+        state.u64(self.rom_hash);
+
+        state.platform(self.platform);
+        state.bytes_with_len(&self.mem);
+        state.u16(self.pc);
+        state.u16(self.i_reg);
+        for &entry in &self.stack {
+            state.u16(entry);
+        }
+        state.u8(self.sp);
+        state.bytes(&self.registers);
+        state.u8(self.timers.delay);
+        state.u8(self.timers.sound);
+
+        state.u16(self.width as u16);
+        state.u16(self.height as u16);
+        state.bytes_with_len(&self.buf);
+        state.u8(self.planes);
+        match &self.colours {
+            Some(colours) => {
+                state.u8(1);
+                state.u8(colours.background);
+                state.bytes_with_len(&colours.foreground);
+            }
+            None => state.u8(0),
+        }
+
+        state.bytes(&self.rpl);
+        state.u8(self.exited as u8);
+        state.bytes(&self.audio_pattern);
+        state.u8(self.pitch);
+        state.u8(self.pressed_key.unwrap_or(0xFF));
+
+        state.u32(self.frame_cycles);
+        state.u64(self.cycles);
+        state.u64(self.frames);
+
+        state.random(&self.random);
+
+        state.0
+    }
+
+    /// Restores a state made by [`CPU::save_state`]. Nothing changes if the state can't be
+    /// read. The ROM hash isn't checked, use [`StateHeader::read`] to compare it first, the
+    /// CPU takes over the hash of the state since its memory now holds that ROM.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut state = StateReader::new(data);
+        let header = state.header()?;
+
+        let platform = state.platform()?;
+        let mem = state.bytes_with_len()?.to_vec();
+        if mem.len() != platform.memory_size() {
+            return Err(StateError::Invalid("memory size"));
+        }
+
+        let pc = state.u16()?;
+        let i_reg = state.u16()?;
+        let mut stack = [0; 16];
+        for entry in stack.iter_mut() {
+            *entry = state.u16()?;
+        }
+        let sp = state.u8()?;
+        if sp as usize > platform.stack_depth() {
+            return Err(StateError::Invalid("stack pointer"));
+        }
+        let registers = state.array()?;
+        let timers = Timers {
+            delay: state.u8()?,
+            sound: state.u8()?,
+        };
+
+        let width = state.u16()? as usize;
+        let height = state.u16()? as usize;
+        if (width, height) != platform.lores_size()
+            && Some((width, height)) != platform.hires_size()
+        {
+            return Err(StateError::Invalid("screen size"));
+        }
+        let buf = state.bytes_with_len()?.to_vec();
+        if buf.len() != width * height {
+            return Err(StateError::Invalid("frame buffer size"));
+        }
+        let planes = state.u8()?;
+        let colours = match state.u8()? {
+            0 => None,
+            _ => Some(Colours {
+                background: state.u8()?,
+                foreground: state.bytes_with_len()?.to_vec(),
+            }),
+        };
+        // Only the CHIP-8X has colours, and then every 8x1 pixel block needs one
+        if colours.is_some() != platform.has_chip8x()
+            || colours
+                .as_ref()
+                .is_some_and(|colours| colours.foreground.len() != width.div_ceil(8) * height)
+        {
+            return Err(StateError::Invalid("colours"));
+        }
+
+        let rpl = state.array()?;
+        let exited = state.u8()? != 0;
+        let audio_pattern = state.array()?;
+        let pitch = state.u8()?;
+        let pressed_key = match state.u8()? {
+            0xFF => None,
+            key => Some(key),
+        };
+
+        let frame_cycles = state.u32()?;
+        let cycles = state.u64()?;
+        let frames = state.u64()?;
+
+        // Version 1 didn't have the random number generator
+        let random = if header.version >= 2 {
+            Some(state.random()?)
+        } else {
+            None
+        };
+
+        self.rom_hash = header.rom_hash;
+        self.platform = platform;
+        self.mem = mem;
+        self.pc = pc;
+        self.i_reg = i_reg;
+        self.stack = stack;
+        self.sp = sp;
+        self.registers = registers;
+        self.timers = timers;
+        self.width = width;
+        self.height = height;
+        self.buf = buf;
+        self.planes = planes;
+        self.colours = colours;
+        self.rpl = rpl;
+        self.exited = exited;
+        self.audio_pattern = audio_pattern;
+        self.pitch = pitch;
+        self.pressed_key = pressed_key;
+        self.frame_cycles = frame_cycles;
+        self.cycles = cycles;
+        self.frames = frames;
+        if let Some(random) = random {
+            self.random = random;
+        }
+
+        // The display and audio have to catch up with the restored machine
+        self.dirty = true;
+        self.audio.set_pattern(&self.audio_pattern, self.pitch);
+
+        Ok(())
+    }
+}
+
+/// Appends big-endian fields to a save state
+#[derive(Default)]
+pub(crate) struct StateWriter(pub(crate) Vec<u8>);
+
+impl StateWriter {
+    pub(crate) fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    pub(crate) fn u16(&mut self, value: u16) {
+        self.0.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub(crate) fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub(crate) fn u64(&mut self, value: u64) {
+        self.0.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub(crate) fn bytes(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes);
+    }
+
+    /// Writes the length before the bytes so they can be read back without knowing it
+    pub(crate) fn bytes_with_len(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.bytes(bytes);
+    }
+
+    pub(crate) fn platform(&mut self, platform: Platform) {
+        self.u8(match platform {
+            Platform::Chip8 => 0,
+            Platform::CosmacVip => 1,
+            Platform::HiresChip8 => 2,
+            Platform::Chip8X => 3,
+            Platform::SuperChip => 4,
+            Platform::XoChip => 5,
+        });
+    }
+
+    /// Writes the kind of generator followed by its state
+    pub(crate) fn random(&mut self, random: &Random) {
+        match random {
+            Random::Seeded { seed, state } => {
+                self.u8(0);
+                self.u64(*seed);
+                self.u64(*state);
+            }
+            Random::Vip { r9 } => {
+                self.u8(1);
+                self.u16(*r9);
+            }
+            Random::Sequence { values, next } => {
+                self.u8(2);
+                self.bytes_with_len(values);
+                self.u32(*next as u32);
+            }
+        }
+    }
+}
+
+/// Reads the fields written by `StateWriter` back in the same order
+pub(crate) struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        StateReader { data }
+    }
+
+    /// Checks the magic bytes and version and returns the header
+    pub(crate) fn header(&mut self) -> Result<StateHeader, StateError> {
+        if self.take(STATE_MAGIC.len()).ok() != Some(&STATE_MAGIC[..]) {
+            return Err(StateError::NotAState);
+        }
+
+        let version = self.u16()?;
+        if !(MIN_STATE_VERSION..=STATE_VERSION).contains(&version) {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+
+        Ok(StateHeader {
+            version,
+            rom_hash: self.u64()?,
+        })
+    }
+
+    pub(crate) fn take(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() < len {
+            return Err(StateError::Truncated);
+        }
+
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+
+        Ok(taken)
+    }
+
+    pub(crate) fn array<const N: usize>(&mut self) -> Result<[u8; N], StateError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+
+        Ok(array)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_be_bytes(self.array()?))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_be_bytes(self.array()?))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_be_bytes(self.array()?))
+    }
+
+    pub(crate) fn bytes_with_len(&mut self) -> Result<&'a [u8], StateError> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    pub(crate) fn platform(&mut self) -> Result<Platform, StateError> {
+        let platform = match self.u8()? {
+            0 => Platform::Chip8,
+            1 => Platform::CosmacVip,
+            2 => Platform::HiresChip8,
+            3 => Platform::Chip8X,
+            4 => Platform::SuperChip,
+            5 => Platform::XoChip,
+            _ => return Err(StateError::Invalid("platform")),
+        };
+
+        Ok(platform)
+    }
+
+    pub(crate) fn random(&mut self) -> Result<Random, StateError> {
+        let random = match self.u8()? {
+            0 => Random::Seeded {
+                seed: self.u64()?,
+                state: self.u64()?,
+            },
+            1 => Random::Vip { r9: self.u16()? },
+            2 => Random::Sequence {
+                values: self.bytes_with_len()?.to_vec(),
+                next: self.u32()? as usize,
+            },
+            _ => return Err(StateError::Invalid("random number generator")),
+        };
+
+        Ok(random)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let mut cpu = CPU::new_with_memory(&[0x60, 0x2A, 0x12, 0x00]).unwrap();
+        cpu.run_cycles(1).unwrap();
+        cpu.buf[5] = 1;
+        cpu.timers.delay = 30;
+        cpu.pressed_key = Some(0x7);
+
+        let state = cpu.save_state();
+
+        let mut restored = CPU::new();
+        restored.load_state(&state).unwrap();
+
+        assert_eq!(restored.save_state(), state);
+        assert_eq!(restored.registers[0], 0x2A);
+        assert_eq!(restored.pc, 0x202);
+        assert_eq!(restored.buf[5], 1);
+        assert_eq!(restored.rom_hash, cpu.rom_hash);
+    }
+
+    #[test]
+    fn load_restores_the_platform() {
+        let cpu = CPU::with_platform(Platform::XoChip, &[]).unwrap();
+
+        let mut restored = CPU::new();
+        restored.load_state(&cpu.save_state()).unwrap();
+
+        assert_eq!(restored.platform, Platform::XoChip);
+        assert_eq!(restored.mem.len(), 0x10000);
+    }
+
+    #[test]
+    fn random_numbers_continue_after_load() {
+        let mut cpu = CPU::new();
+        cpu.random = Random::seeded(42);
+        cpu.random.next_byte();
+        let state = cpu.save_state();
+        let expected = cpu.random.next_byte();
+
+        let mut restored = CPU::new();
+        restored.load_state(&state).unwrap();
+
+        assert_eq!(restored.random.next_byte(), expected);
+    }
+
+    #[test]
+    fn version_1_states_still_load() {
+        let mut cpu = CPU::new();
+        cpu.registers[3] = 3;
+        let mut state = cpu.save_state();
+
+        // Version 1 ended before the seeded generator's kind, seed and state
+        state.truncate(state.len() - 17);
+        state[4..6].copy_from_slice(&1u16.to_be_bytes());
+
+        let mut restored = CPU::new();
+        restored.random = Random::seeded(7);
+        restored.load_state(&state).unwrap();
+
+        assert_eq!(restored.registers[3], 3);
+        assert_eq!(restored.random, Random::seeded(7));
+    }
+
+    #[test]
+    fn header_has_rom_hash() {
+        let cpu = CPU::new_with_memory(&[0x12, 0x00]).unwrap();
+
+        let header = StateHeader::read(&cpu.save_state()).unwrap();
+
+        assert_eq!(header.version, STATE_VERSION);
+        assert_eq!(header.rom_hash, rom_hash(&[0x12, 0x00]));
+        assert_ne!(header.rom_hash, rom_hash(&[0x12, 0x02]));
+    }
+
+    #[test]
+    fn bad_states_are_rejected_without_changes() {
+        let mut cpu = CPU::new();
+        cpu.registers[0] = 1;
+        let state = CPU::new().save_state();
+
+        assert_eq!(cpu.load_state(b"nope"), Err(StateError::NotAState));
+        assert_eq!(
+            cpu.load_state(&state[..state.len() - 1]),
+            Err(StateError::Truncated)
+        );
+
+        let mut future = state.clone();
+        future[4..6].copy_from_slice(&(STATE_VERSION + 1).to_be_bytes());
+        assert_eq!(
+            cpu.load_state(&future),
+            Err(StateError::UnsupportedVersion(STATE_VERSION + 1))
+        );
+
+        assert_eq!(cpu.registers[0], 1);
+    }
+
+    #[test]
+    fn states_no_machine_could_be_in_are_rejected() {
+        let load = |cpu: &CPU| CPU::new().load_state(&cpu.save_state());
+
+        let mut cpu = CPU::new();
+        (cpu.width, cpu.height, cpu.buf) = (0, 0, Vec::new());
+        assert_eq!(load(&cpu), Err(StateError::Invalid("screen size")));
+
+        let mut cpu = CPU::with_platform(Platform::Chip8X, &[]).unwrap();
+        cpu.colours.as_mut().unwrap().foreground.clear();
+        assert_eq!(load(&cpu), Err(StateError::Invalid("colours")));
+
+        let mut cpu = CPU::with_platform(Platform::Chip8X, &[]).unwrap();
+        cpu.colours = None;
+        assert_eq!(load(&cpu), Err(StateError::Invalid("colours")));
+
+        let mut cpu = CPU::new();
+        cpu.colours = CPU::with_platform(Platform::Chip8X, &[]).unwrap().colours;
+        assert_eq!(load(&cpu), Err(StateError::Invalid("colours")));
+
+        let mut cpu = CPU::with_platform(Platform::CosmacVip, &[]).unwrap();
+        cpu.sp = 13;
+        assert_eq!(load(&cpu), Err(StateError::Invalid("stack pointer")));
+    }
+
+    #[test]
+    fn save_slots_sit_next_to_the_rom() {
+        let slots = SaveSlots::new("roms/pong.ch8");
+
+        assert_eq!(slots.path(3), Path::new("roms/pong.state3"));
+    }
+}
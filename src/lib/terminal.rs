@@ -0,0 +1,406 @@
+use super::cpu::CPU;
+use super::error::Chip8Error;
+use super::platform::Keypad;
+use super::rewind::Rewind;
+use super::scheduler::{Scheduler, Speed};
+use super::state::SaveSlots;
+use std::{
+    cell::RefCell,
+    io::{self, Write},
+    rc::Rc,
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+/// How long a key counts as held after its first byte arrives. Terminals only repeat a
+/// held key after a delay, so this has to bridge the gap until the first repeat.
+pub const INITIAL_RELEASE_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// How long a key counts as held after a repeated byte arrives
+pub const REPEAT_RELEASE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// The terminal settings from before raw mode was entered, used to restore the terminal
+/// from the panic hook
+static ORIGINAL_TERMIOS: OnceLock<libc::termios> = OnceLock::new();
+
+/// Maps the standard layout of the left side of a QWERTY keyboard to the hex keypad
+///
+/// ```text
+/// 1 2 3 4      1 2 3 C
+/// Q W E R  ->  4 5 6 D
+/// A S D F      7 8 9 E
+/// Z X C V      A 0 B F
+/// ```
+pub fn key_for_char(c: u8) -> Option<u8> {
+    let key = match c.to_ascii_lowercase() {
+        b'1' => 0x1,
+        b'2' => 0x2,
+        b'3' => 0x3,
+        b'4' => 0xC,
+        b'q' => 0x4,
+        b'w' => 0x5,
+        b'e' => 0x6,
+        b'r' => 0xD,
+        b'a' => 0x7,
+        b's' => 0x8,
+        b'd' => 0x9,
+        b'f' => 0xE,
+        b'z' => 0xA,
+        b'x' => 0x0,
+        b'c' => 0xB,
+        b'v' => 0xF,
+        _ => return None,
+    };
+
+    Some(key)
+}
+
+/// Keys that control the emulator rather than the program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// Escape or Ctrl+C
+    Quit,
+
+    /// Change how fast frames run
+    SetSpeed(Speed),
+
+    /// Run a single frame while paused
+    AdvanceFrame,
+
+    /// F1 to F4 save to quick-save slots 1 to 4
+    SaveState(u8),
+
+    /// F5 to F8 load quick-save slots 1 to 4
+    LoadState(u8),
+
+    /// Backspace steps back one frame, holding it keeps going back
+    Rewind,
+}
+
+/// Maps the keys outside the keypad layout to emulator commands
+fn command_for_char(c: u8) -> Option<Command> {
+    let command = match c {
+        0x03 => Command::Quit,
+        b'`' => Command::SetSpeed(Speed::Normal),
+        b'=' | b'+' => Command::SetSpeed(Speed::Double),
+        b'-' => Command::SetSpeed(Speed::Half),
+        b'\t' => Command::SetSpeed(Speed::Unthrottled),
+        b'p' | b'P' => Command::SetSpeed(Speed::FrameAdvance),
+        b'n' | b'N' => Command::AdvanceFrame,
+        0x7F | 0x08 => Command::Rewind,
+        _ => return None,
+    };
+
+    Some(command)
+}
+
+/// Maps the escape sequences of function keys to emulator commands. `sequence` is
+/// everything after the escape byte.
+fn command_for_sequence(sequence: &[u8]) -> Option<Command> {
+    let command = match sequence {
+        b"OP" | b"[11~" => Command::SaveState(1),
+        b"OQ" | b"[12~" => Command::SaveState(2),
+        b"OR" | b"[13~" => Command::SaveState(3),
+        b"OS" | b"[14~" => Command::SaveState(4),
+        b"[15~" => Command::LoadState(1),
+        b"[17~" => Command::LoadState(2),
+        b"[18~" => Command::LoadState(3),
+        b"[19~" => Command::LoadState(4),
+        _ => return None,
+    };
+
+    Some(command)
+}
+
+/// Tracks which keys are held. Terminals only report key presses, never releases, so a
+/// key is released once no bytes for it have arrived for a while.
+#[derive(Debug, Clone)]
+pub struct KeyState {
+    /// When each key's latest byte arrived
+    last_seen: [Option<Instant>; 16],
+
+    /// Whether each key has been repeated since it went down
+    repeating: [bool; 16],
+
+    pub initial_timeout: Duration,
+    pub repeat_timeout: Duration,
+}
+
+impl Default for KeyState {
+    fn default() -> Self {
+        KeyState {
+            last_seen: [None; 16],
+            repeating: [false; 16],
+            initial_timeout: INITIAL_RELEASE_TIMEOUT,
+            repeat_timeout: REPEAT_RELEASE_TIMEOUT,
+        }
+    }
+}
+
+impl KeyState {
+    /// Records a byte for `key` arriving at `now`
+    pub fn press(&mut self, key: u8, now: Instant) {
+        let key = key as usize & 0xF;
+
+        self.repeating[key] = self.is_held(key as u8, now);
+        self.last_seen[key] = Some(now);
+    }
+
+    /// Whether `key` still counts as held at `now`
+    pub fn is_held(&self, key: u8, now: Instant) -> bool {
+        let key = key as usize & 0xF;
+
+        let timeout = if self.repeating[key] {
+            self.repeat_timeout
+        } else {
+            self.initial_timeout
+        };
+
+        match self.last_seen[key] {
+            Some(last_seen) => now.duration_since(last_seen) < timeout,
+            None => false,
+        }
+    }
+}
+
+/// The keypad handed to the CPU, it shares its key state with the `TerminalFrontend`
+#[derive(Debug, Clone)]
+pub struct TerminalKeypad {
+    state: Rc<RefCell<KeyState>>,
+}
+
+impl Keypad for TerminalKeypad {
+    fn is_key_pressed(&mut self, key: u8) -> bool {
+        self.state.borrow().is_held(key, Instant::now())
+    }
+}
+
+/// Restores the terminal settings saved when raw mode was entered
+fn restore_terminal() {
+    if let Some(termios) = ORIGINAL_TERMIOS.get() {
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, termios);
+        }
+    }
+
+    // Show the cursor and leave the alternate screen
+    print!("\x1B[?25h\x1B[?1049l");
+    let _ = io::stdout().flush();
+}
+
+/// Puts the terminal in raw mode for as long as it's alive, reads key presses without
+/// blocking and restores the terminal when it's dropped or the program panics.
+pub struct TerminalFrontend {
+    state: Rc<RefCell<KeyState>>,
+}
+
+impl TerminalFrontend {
+    pub fn new() -> Result<Self, Chip8Error> {
+        unsafe {
+            let mut termios: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+            let original = *ORIGINAL_TERMIOS.get_or_init(|| termios);
+
+            // No line buffering, no echo and no signals so Ctrl+C reaches us and the
+            // terminal can be restored. Reads return immediately even without input.
+            let mut raw = original;
+            raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+            raw.c_cc[libc::VMIN] = 0;
+            raw.c_cc[libc::VTIME] = 0;
+
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) != 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+        }
+
+        // Restore the terminal before the panic message is printed
+        let hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            restore_terminal();
+            hook(info);
+        }));
+
+        // Switch to the alternate screen, clear it once and hide the cursor
+        print!("\x1B[?1049h\x1B[2J\x1B[?25l");
+        io::stdout().flush()?;
+
+        Ok(TerminalFrontend {
+            state: Rc::new(RefCell::new(KeyState::default())),
+        })
+    }
+
+    /// A keypad for the CPU that reads the keys seen by this frontend
+    pub fn keypad(&self) -> TerminalKeypad {
+        TerminalKeypad {
+            state: Rc::clone(&self.state),
+        }
+    }
+
+    /// Reads all pending input, updates the held keys and returns the emulator commands
+    pub fn poll(&mut self) -> Result<Vec<Command>, Chip8Error> {
+        let mut commands = Vec::new();
+        let mut buf = [0u8; 64];
+        let now = Instant::now();
+
+        loop {
+            let read = unsafe {
+                libc::read(
+                    libc::STDIN_FILENO,
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                )
+            };
+            if read < 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+            if read == 0 {
+                break;
+            }
+
+            let mut bytes = buf[..read as usize].iter().copied().peekable();
+            while let Some(byte) = bytes.next() {
+                if byte == 0x1B {
+                    // A lone escape is the escape key, anything else is an escape
+                    // sequence. Function keys are commands, the rest such as arrow keys
+                    // are skipped.
+                    if bytes.peek().is_none() {
+                        commands.push(Command::Quit);
+                    }
+                    let mut sequence = Vec::new();
+                    for byte in bytes.by_ref() {
+                        sequence.push(byte);
+                        // `ESC O` starts a sequence that ends with the letter after it
+                        if (byte.is_ascii_alphabetic() && sequence != b"O") || byte == b'~' {
+                            break;
+                        }
+                    }
+                    commands.extend(command_for_sequence(&sequence));
+                } else if let Some(key) = key_for_char(byte) {
+                    self.state.borrow_mut().press(key, now);
+                } else if let Some(command) = command_for_char(byte) {
+                    commands.push(command);
+                }
+            }
+        }
+
+        Ok(commands)
+    }
+}
+
+impl Drop for TerminalFrontend {
+    fn drop(&mut self) {
+        restore_terminal();
+    }
+}
+
+/// Shows a status message in the terminal's title bar, out of the way of the screen
+fn show_status(message: &str) {
+    print!("\x1B]2;{message}\x07");
+    let _ = io::stdout().flush();
+}
+
+/// Runs the CHIP-8 in the terminal until escape or Ctrl+C is pressed or the program exits.
+/// The CPU's keypad is left alone, it's usually `frontend.keypad()` but can also be e.g. a
+/// movie being replayed. Quick-saves are written to and read from `slots`, every frame is
+/// captured in `rewind`. A movie only holds the key presses since power on, so while
+/// `recording` one, loading a state and rewinding are refused.
+pub fn run(
+    cpu: &mut CPU,
+    frontend: &mut TerminalFrontend,
+    scheduler: &mut Scheduler,
+    slots: &SaveSlots,
+    rewind: &mut Rewind,
+    recording: bool,
+) -> Result<(), Chip8Error> {
+    // The rewind key is held the same way as the keypad's keys, it's tracked as key 0
+    let mut rewind_key = KeyState::default();
+
+    while !cpu.exited {
+        for command in frontend.poll()? {
+            match command {
+                Command::Quit => return Ok(()),
+                Command::SetSpeed(speed) => scheduler.set_speed(speed),
+                Command::AdvanceFrame => scheduler.advance_frame(),
+                Command::SaveState(slot) => match slots.save(cpu, slot) {
+                    Ok(()) => show_status(&format!("Saved slot {slot}")),
+                    Err(err) => show_status(&format!("Could not save slot {slot}: {err}")),
+                },
+                Command::LoadState(slot) if recording => {
+                    show_status(&format!("Can't load slot {slot} while recording a movie"))
+                }
+                Command::LoadState(slot) => match slots.load(cpu, slot) {
+                    Ok(same_rom) => {
+                        // The frames before the load belong to another timeline
+                        rewind.clear();
+
+                        if same_rom {
+                            show_status(&format!("Loaded slot {slot}"));
+                        } else {
+                            show_status(&format!(
+                                "Loaded slot {slot}, it was saved with a different ROM"
+                            ));
+                        }
+                    }
+                    Err(err) => show_status(&format!("Could not load slot {slot}: {err}")),
+                },
+                Command::Rewind if recording => show_status("Can't rewind while recording a movie"),
+                Command::Rewind => rewind_key.press(0, Instant::now()),
+            }
+        }
+
+        if !scheduler.wait_for_frame() {
+            continue;
+        }
+
+        if rewind_key.is_held(0, Instant::now()) {
+            rewind.step_back(cpu);
+        } else {
+            cpu.run_frame()?;
+            rewind.capture(cpu);
+        }
+        cpu.update();
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keypad_layout() {
+        assert_eq!(key_for_char(b'1'), Some(0x1));
+        assert_eq!(key_for_char(b'4'), Some(0xC));
+        assert_eq!(key_for_char(b'X'), Some(0x0));
+        assert_eq!(key_for_char(b'v'), Some(0xF));
+        assert_eq!(key_for_char(b'5'), None);
+    }
+
+    #[test]
+    fn function_keys_use_save_slots() {
+        assert_eq!(command_for_sequence(b"OP"), Some(Command::SaveState(1)));
+        assert_eq!(command_for_sequence(b"[14~"), Some(Command::SaveState(4)));
+        assert_eq!(command_for_sequence(b"[15~"), Some(Command::LoadState(1)));
+        assert_eq!(command_for_sequence(b"[19~"), Some(Command::LoadState(4)));
+        assert_eq!(command_for_sequence(b"[A"), None);
+    }
+
+    #[test]
+    fn keys_release_after_timeout() {
+        let mut state = KeyState::default();
+        let start = Instant::now();
+
+        state.press(0x5, start);
+        assert!(state.is_held(0x5, start + INITIAL_RELEASE_TIMEOUT / 2));
+        assert!(!state.is_held(0x5, start + INITIAL_RELEASE_TIMEOUT));
+
+        // Once the key repeats the shorter timeout applies
+        let repeat = start + Duration::from_millis(50);
+        state.press(0x5, repeat);
+        assert!(!state.is_held(0x5, repeat + REPEAT_RELEASE_TIMEOUT));
+        assert!(!state.is_held(0x6, repeat));
+    }
+}
@@ -0,0 +1,23 @@
+/// The delay and sound timers. Both count down by one on every 60 Hz tick until they
+/// reach 0. Ticks come from the emulated frame clock, never from the host's wall clock.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timers {
+    /// The delay timer, read and written by `Fx07` and `Fx15`
+    pub delay: u8,
+
+    /// The sound timer, a tone plays while it's non-zero
+    pub sound: u8,
+}
+
+impl Timers {
+    /// Decrements both timers, called once per emulated 60 Hz frame
+    pub fn tick(&mut self) {
+        self.delay = self.delay.saturating_sub(1);
+        self.sound = self.sound.saturating_sub(1);
+    }
+
+    /// Whether the buzzer should currently be sounding
+    pub fn is_sound_playing(&self) -> bool {
+        self.sound != 0
+    }
+}
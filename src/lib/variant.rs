@@ -0,0 +1,192 @@
+use super::cpu::{BIG_FONT_ADDR, FONT_ADDR};
+use super::opcode::{DecodeError, Instruction};
+use super::quirks::Quirks;
+
+/// Where the COSMAC VIP keeps the font, right below the interpreter's reserved area
+pub const VIP_FONT_ADDR: u16 = 0xE50;
+
+/// Where the COSMAC VIP interpreter keeps the call stack
+pub const VIP_STACK_ADDR: u16 = 0xEA0;
+
+/// The first instruction of every hires CHIP-8 ROM, a jump to the interpreter patch
+pub const HIRES_HEADER: [u8; 2] = [0x12, 0x60];
+
+/// The machine a ROM was written for. Unlike [`Quirks`](super::quirks::Quirks), which only
+/// tweak how ambiguous instructions behave, the platform decides which instructions exist
+/// and how big the screen is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Platform {
+    /// The original CHIP-8 with a 64x32 screen
+    #[default]
+    Chip8,
+
+    /// The COSMAC VIP running the original 1977 interpreter. The program shares the 4K of
+    /// memory with the interpreter, which reserves 0x000-0x1FF and keeps the font and the
+    /// 12 level call stack at the top of memory.
+    CosmacVip,
+
+    /// The two page "hires" CHIP-8 with a 64x64 screen. Its ROMs start with a `1260` jump
+    /// into a patch for the interpreter, the program itself starts at 0x2C0.
+    HiresChip8,
+
+    /// CHIP-8X for the VP-590 colour board, with colour zones, a changeable background
+    /// and a second keypad. Programs load at 0x300.
+    Chip8X,
+
+    /// SUPER-CHIP 1.1, which adds a 128x64 high resolution mode, scrolling, 16x16
+    /// sprites, a large font and the RPL user flags
+    SuperChip,
+
+    /// XO-CHIP as implemented by Octo. Builds on SUPER-CHIP with 64K of memory, a second
+    /// bitplane for 4 colours and a programmable audio pattern.
+    XoChip,
+}
+
+impl Platform {
+    /// Picks the platform for a ROM that didn't say which one it's for. Only the hires
+    /// CHIP-8 header can be recognized, everything else is assumed to be plain CHIP-8.
+    pub fn detect(rom: &[u8]) -> Platform {
+        if rom.starts_with(&HIRES_HEADER) {
+            Platform::HiresChip8
+        } else {
+            Platform::Chip8
+        }
+    }
+
+    /// Where programs are loaded and start running
+    pub fn load_addr(&self) -> u16 {
+        match self {
+            Platform::Chip8X => 0x300,
+            _ => 0x200,
+        }
+    }
+
+    /// The size of the screen every program starts with, SUPER-CHIP's low resolution mode
+    pub fn lores_size(&self) -> (usize, usize) {
+        match self {
+            Platform::HiresChip8 => (64, 64),
+            _ => (64, 32),
+        }
+    }
+
+    /// The size of the screen after `00FF`, if the platform has a high resolution mode
+    pub fn hires_size(&self) -> Option<(usize, usize)> {
+        match self {
+            Platform::SuperChip | Platform::XoChip => Some((128, 64)),
+            _ => None,
+        }
+    }
+
+    /// The size of the address space in bytes
+    pub fn memory_size(&self) -> usize {
+        match self {
+            Platform::XoChip => 0x10000,
+            _ => 0x1000,
+        }
+    }
+
+    /// The number of bitplanes, each one adds a bit to every pixel
+    pub fn planes(&self) -> u8 {
+        match self {
+            Platform::XoChip => 2,
+            _ => 1,
+        }
+    }
+
+    /// The first address past the space programs can be loaded into
+    pub fn program_end(&self) -> usize {
+        match self {
+            Platform::CosmacVip => VIP_FONT_ADDR as usize,
+            _ => self.memory_size(),
+        }
+    }
+
+    /// Where the 4x5 font used by `Fx29` is stored
+    pub fn font_addr(&self) -> u16 {
+        match self {
+            Platform::CosmacVip => VIP_FONT_ADDR,
+            _ => FONT_ADDR,
+        }
+    }
+
+    /// Where the 8x10 font used by `Fx30` is stored, if the platform has one
+    pub fn big_font_addr(&self) -> Option<u16> {
+        self.has_superchip().then_some(BIG_FONT_ADDR)
+    }
+
+    /// Where the call stack lives in memory, if the interpreter keeps it there
+    pub fn stack_addr(&self) -> Option<u16> {
+        match self {
+            Platform::CosmacVip => Some(VIP_STACK_ADDR),
+            _ => None,
+        }
+    }
+
+    /// How many subroutine calls can be nested
+    pub fn stack_depth(&self) -> usize {
+        match self {
+            Platform::CosmacVip => 12,
+            _ => 16,
+        }
+    }
+
+    /// The interpreter conventions ROMs for the platform usually expect
+    pub fn quirks(&self) -> Quirks {
+        match self {
+            Platform::Chip8 | Platform::HiresChip8 | Platform::Chip8X => Quirks::default(),
+            Platform::CosmacVip => Quirks::vip(),
+            Platform::SuperChip => Quirks::schip(),
+            Platform::XoChip => Quirks::xochip(),
+        }
+    }
+
+    /// Decodes an opcode the way the platform's interpreter does. `next` is the word after
+    /// the opcode, which only XO-CHIP's `F000 nnnn` uses.
+    pub fn decode(&self, opcode: u16, next: u16) -> Result<Instruction, DecodeError> {
+        match self {
+            // The hires patch adds its own clear screen routine for the 64x64 screen
+            Platform::HiresChip8 if opcode == 0x0230 => Ok(Instruction::Cls),
+            Platform::Chip8X => Instruction::decode_chip8x(opcode),
+            Platform::XoChip => Instruction::decode_long(opcode, next),
+            _ => Instruction::decode(opcode),
+        }
+    }
+
+    /// Whether `instruction` exists on the platform. SUPER-CHIP opcodes are machine code
+    /// routine calls on plain CHIP-8, and XO-CHIP and CHIP-8X opcodes don't exist anywhere
+    /// else.
+    pub fn supports(&self, instruction: &Instruction) -> bool {
+        (!instruction.is_superchip() || self.has_superchip())
+            && (!instruction.is_xochip() || self.has_xochip())
+            && (!instruction.is_chip8x() || self.has_chip8x())
+    }
+
+    /// Whether the SUPER-CHIP instructions are available
+    pub fn has_superchip(&self) -> bool {
+        matches!(self, Platform::SuperChip | Platform::XoChip)
+    }
+
+    /// Whether the XO-CHIP instructions are available
+    pub fn has_xochip(&self) -> bool {
+        matches!(self, Platform::XoChip)
+    }
+
+    /// Whether the CHIP-8X instructions replace `Bnnn` and some machine code routines
+    pub fn has_chip8x(&self) -> bool {
+        matches!(self, Platform::Chip8X)
+    }
+
+    /// Looks up a platform by name, e.g. `chip8`, `vip`, `hires`, `chip8x`, `schip` or
+    /// `xochip`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "chip8" | "chip-8" => Some(Platform::Chip8),
+            "vip" | "cosmac-vip" => Some(Platform::CosmacVip),
+            "hires" | "chip8-hires" | "hires-chip8" => Some(Platform::HiresChip8),
+            "chip8x" | "chip-8x" => Some(Platform::Chip8X),
+            "schip" | "schip1.1" | "superchip" => Some(Platform::SuperChip),
+            "xochip" | "xo-chip" => Some(Platform::XoChip),
+            _ => None,
+        }
+    }
+}
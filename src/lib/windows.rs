@@ -0,0 +1,78 @@
+use super::platform::{AudioSink, Keypad};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+/// Reads the keypad from the keyboard using `GetAsyncKeyState`. The hex keys map to the
+/// keys with the same label, 0-9 and A-F.
+#[derive(Debug, Default)]
+pub struct WindowsKeypad;
+
+impl Keypad for WindowsKeypad {
+    fn is_key_pressed(&mut self, key: u8) -> bool {
+        let key_code = match key {
+            0x0 => 0x30,
+            0x1 => 0x31,
+            0x2 => 0x32,
+            0x3 => 0x33,
+            0x4 => 0x34,
+            0x5 => 0x35,
+            0x6 => 0x36,
+            0x7 => 0x37,
+            0x8 => 0x38,
+            0x9 => 0x39,
+            0xA => 0x41,
+            0xB => 0x42,
+            0xC => 0x43,
+            0xD => 0x44,
+            0xE => 0x45,
+            0xF => 0x46,
+            _ => 0x30,
+        };
+
+        unsafe {
+            let is_key_pressed = winapi::um::winuser::GetAsyncKeyState(key_code);
+
+            // Checking if the MSB of `is_key_pressed`, which indicates if the key was pressed during the
+            // function call
+            ((is_key_pressed >> 15) & 1) == 1
+        }
+    }
+}
+
+/// Plays the tone with `Beep`. `Beep` blocks until the tone is over, so it runs on its
+/// own thread for as long as the sound timer is active.
+#[derive(Debug)]
+pub struct WindowsAudio {
+    playing: Arc<AtomicBool>,
+}
+
+impl Default for WindowsAudio {
+    fn default() -> Self {
+        let playing = Arc::new(AtomicBool::new(false));
+
+        let thread_playing = Arc::clone(&playing);
+        thread::spawn(move || loop {
+            if thread_playing.load(Ordering::Relaxed) {
+                unsafe {
+                    winapi::um::utilapiset::Beep(750, 50);
+                }
+            } else {
+                thread::sleep(Duration::from_millis(5));
+            }
+        });
+
+        WindowsAudio { playing }
+    }
+}
+
+impl AudioSink for WindowsAudio {
+    fn set_playing(&mut self, playing: bool) {
+        self.playing.store(playing, Ordering::Relaxed);
+    }
+}
@@ -1,21 +1,575 @@
-mod lib;
-
-use std::{io::prelude::*, path::Path};
 use std::env;
+use std::{cell::RefCell, rc::Rc};
+use std::{io::prelude::*, path::Path};
 
-fn main() {
+use sschip8::asm::assemble_file;
+use sschip8::cpu::CPU;
+use sschip8::debugger::Debugger;
+use sschip8::disasm::{disassemble, Syntax};
+use sschip8::error::Chip8Error;
+use sschip8::headless::{
+    parse_input_script, registers_json, screen_png, screen_text, HeadlessRun, Stop,
+    DEFAULT_HEADLESS_FRAMES,
+};
+use sschip8::movie::{KeyEvent, Movie, MoviePlayer, MovieRecorder};
+use sschip8::octo::compile_file;
+use sschip8::platform::{Keypad, NullKeypad};
+use sschip8::quirks::Quirks;
+use sschip8::random::Random;
+use sschip8::render::{RenderMode, TerminalRenderer};
+use sschip8::rewind::DEFAULT_REWIND_SECONDS;
+use sschip8::scheduler::{
+    FrameBudget, Scheduler, Speed, SCHIP_INSTRUCTIONS_PER_FRAME, VIP_INSTRUCTIONS_PER_FRAME,
+    XOCHIP_INSTRUCTIONS_PER_FRAME,
+};
+use sschip8::variant::Platform;
+
+const USAGE: &str = "Usage: sschip8 [run] <PATH TO .ch8 OR .8o FILE> [OPTIONS]
+       sschip8 disasm <PATH TO .ch8 FILE> [DISASSEMBLER OPTIONS]
+       sschip8 asm <PATH TO SOURCE OR .8o FILE> [ASSEMBLER OPTIONS]
+
+Options:
+  --platform <name>                   the machine the ROM was written for: chip8, vip,
+                                      hires, chip8x, schip or xochip
+  --quirks <vip|chip48|schip|xochip>  interpreter conventions the ROM expects
+  --ipf <n>                           instructions per frame
+  --vip-timing                        use the COSMAC VIP's instruction timings
+  --speed <1x|2x|0.5x|unthrottled>    how fast to run compared to real time
+  --render <pixel|half|braille>       how pixels are drawn in the terminal
+  --seed <n>                          seed for the random numbers of Cxnn
+  --vip-random                        use the COSMAC VIP's random number generator
+  --record <movie>                    record the input to a movie file
+  --play <movie>                      replay the input recorded in a movie file
+  --rewind <seconds>                  how far back Backspace can rewind, 0 turns it off
+
+Headless options:
+  --headless                          run without a display or keyboard and exit
+  --frames <n>                        how many frames to run, 3600 by default, or as
+                                      many as the movie given to --play recorded
+  --until-pc <addr>                   stop when the program counter reaches addr
+  --input <script>                    hold keys from a script, see the README
+  --dump-screen <path>                write the screen as a PNG, or as text unless the
+                                      path ends in .png, - for stdout
+  --dump-registers <path>             write the registers as JSON, - for stdout
+  --debug                             pause in the debugger instead, type help there
+                                      for its commands, --input holds keys
+
+Headless runs exit with 0 when they finish, 1 on errors and 2 if --until-pc was never
+reached
+
+Disassembler options:
+  --syntax <octo|cowgod>              the assembly language to write, octo by default
+  --platform <name>                   the machine the ROM was written for
+  --addresses                         comment every line with its address and bytes
+
+Assembler options:
+  -o <path>                           where to write the ROM, the source file with a .ch8
+                                      extension by default
+  --platform <name>                   the machine to assemble for, xochip by default";
+
+/// Whether `path` is Octo source rather than a ROM
+fn is_octo(path: &str) -> bool {
+    Path::new(path).extension().is_some_and(|ext| ext == "8o")
+}
+
+/// Reads the whole ROM file into memory
+fn read_rom(path: &str) -> Result<Vec<u8>, Chip8Error> {
     let mut bytes: Vec<u8> = Vec::new();
-    let args: Vec<_> = env::args().collect();
 
     let mut file = std::fs::OpenOptions::new()
         .read(true)
-        .open(Path::new(
-            &args[1],
+        .open(Path::new(path))?;
+    file.read_to_end(&mut bytes)?;
+
+    Ok(bytes)
+}
+
+/// Where the SUPER-CHIP RPL flags of a ROM are kept between runs
+fn rpl_path(rom_path: &str) -> std::path::PathBuf {
+    Path::new(rom_path).with_extension("rpl")
+}
+
+/// Loads the RPL flags saved by an earlier run of the ROM, if there are any
+fn load_rpl_flags(cpu: &mut CPU, rom_path: &str) {
+    if let Ok(flags) = std::fs::read(rpl_path(rom_path)) {
+        let len = flags.len().min(cpu.rpl.len());
+        cpu.rpl[..len].copy_from_slice(&flags[..len]);
+    }
+}
+
+/// Saves the RPL flags next to the ROM so the next run can read them
+fn save_rpl_flags(cpu: &CPU, rom_path: &str) {
+    if let Err(err) = std::fs::write(rpl_path(rom_path), cpu.rpl) {
+        eprintln!("Could not save RPL flags: {err}");
+    }
+}
+
+/// Saves the RPL flags of SUPER-CHIP ROMs, which every way of running a ROM ends with, and
+/// exits with `status`
+fn exit_saving_flags(cpu: &CPU, rom_path: &str, status: i32) -> ! {
+    if cpu.platform.has_superchip() {
+        save_rpl_flags(cpu, rom_path);
+    }
+    std::process::exit(status);
+}
+
+/// Reads an input movie recorded with `--record`
+fn read_movie(path: &str) -> Movie {
+    std::fs::read(path)
+        .map_err(|err| err.to_string())
+        .and_then(|data| Movie::from_bytes(&data).map_err(|err| err.to_string()))
+        .unwrap_or_else(|err| fail(&format!("Could not load movie {path}: {err}")))
+}
+
+/// Hands the host's keypad to the CPU. While recording it goes through a recorder and the
+/// recorded key changes are returned, while playing the movie's keypad is kept instead.
+fn connect_keypad(
+    cpu: &mut CPU,
+    keypad: impl Keypad + 'static,
+    playing: bool,
+    recording: bool,
+) -> Option<Rc<RefCell<Vec<KeyEvent>>>> {
+    if playing {
+        return None;
+    }
+
+    if recording {
+        let recorder = MovieRecorder::new(keypad);
+        let events = recorder.events();
+        cpu.keypad = Box::new(recorder);
+
+        Some(events)
+    } else {
+        cpu.keypad = Box::new(keypad);
+
+        None
+    }
+}
+
+/// Parses a decimal or `0x` prefixed hex address
+fn parse_addr(addr: &str) -> Option<u16> {
+    match addr.strip_prefix("0x").or_else(|| addr.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => addr.parse().ok(),
+    }
+}
+
+/// Writes a headless dump to `path`, or to stdout if `path` is `-`
+fn write_dump(path: &str, data: &[u8]) {
+    let result = if path == "-" {
+        std::io::stdout().write_all(data)
+    } else {
+        std::fs::write(path, data)
+    };
+
+    if let Err(err) = result {
+        fail(&format!("Could not write {path}: {err}"));
+    }
+}
+
+/// Holds keys the way the script passed to `--input <script>` says
+fn load_input_script(args: &[String], cpu: &mut CPU) {
+    if let Some(keypad) = input_script(args) {
+        cpu.keypad = Box::new(keypad);
+    }
+}
+
+/// Reads the keys `--input <script>` holds
+fn input_script(args: &[String]) -> Option<MoviePlayer> {
+    let script_path = flag_value(args, "--input")?;
+    let script = std::fs::read_to_string(script_path)
+        .unwrap_or_else(|err| fail(&format!("Could not load {script_path}: {err}")));
+    let events = parse_input_script(&script)
+        .unwrap_or_else(|err| fail(&format!("Invalid input script {script_path}: {err}")));
+
+    Some(MoviePlayer::new(events))
+}
+
+/// Runs the ROM in the debugger for `--debug`, reading commands from stdin. `back` can go
+/// back as far as `rewind_seconds` of frames. Returns the exit status.
+fn run_debugger(args: &[String], cpu: &mut CPU, rewind_seconds: usize) -> i32 {
+    load_input_script(args, cpu);
+
+    let mut debugger = Debugger::new();
+    debugger.rewind = Some(sschip8::rewind::Rewind::with_seconds(rewind_seconds));
+
+    let stdin = std::io::stdin();
+    if let Err(err) = debugger.repl(cpu, stdin.lock(), std::io::stdout()) {
+        eprintln!("Error: {err}");
+        return 1;
+    }
+    0
+}
+
+/// Runs the ROM without a display for `--headless`, writes the requested dumps and returns
+/// the exit status. A movie being played runs for as many frames as it recorded and has to end on the same
+/// frame, and a movie being recorded takes its input from `--input`.
+fn run_headless(
+    args: &[String],
+    cpu: &mut CPU,
+    playing: Option<&Movie>,
+    recording: Option<&str>,
+) -> i32 {
+    let frames = match (playing, flag_value(args, "--frames")) {
+        (Some(_), Some(_)) => {
+            fail("--play runs as many frames as the movie recorded, leave out --frames")
+        }
+        (Some(movie), None) => movie.frames,
+        (None, Some(n)) => n
+            .parse()
+            .unwrap_or_else(|_| fail(&format!("Invalid frame count '{n}'"))),
+        (None, None) => DEFAULT_HEADLESS_FRAMES,
+    };
+    let until_pc = flag_value(args, "--until-pc")
+        .map(|addr| parse_addr(addr).unwrap_or_else(|| fail(&format!("Invalid address '{addr}'"))));
+
+    if playing.is_some() && flag_value(args, "--input").is_some() {
+        fail("--play and --input can't be used together");
+    }
+
+    // Recording starts from power on, like it does with a display
+    let mut movie = recording.map(|_| Movie::new(cpu));
+    let events = match input_script(args) {
+        Some(script) => connect_keypad(cpu, script, playing.is_some(), movie.is_some()),
+        None => connect_keypad(cpu, NullKeypad, playing.is_some(), movie.is_some()),
+    };
+
+    let result = (HeadlessRun { frames, until_pc }).run(cpu);
+
+    if let (Some(movie), Some(movie_path), Some(events)) = (&mut movie, recording, events) {
+        movie.finish(cpu, events.take());
+        if let Err(err) = std::fs::write(movie_path, movie.to_bytes()) {
+            fail(&format!("Could not save movie {movie_path}: {err}"));
+        }
+    }
+
+    let stop = match result {
+        Ok(stop) => stop,
+        Err(err) => {
+            eprintln!("Error: {err}");
+            eprintln!("  PC: 0x{:03X}", cpu.pc);
+            return 1;
+        }
+    };
+
+    if let Some(screen_path) = flag_value(args, "--dump-screen") {
+        if screen_path.ends_with(".png") {
+            write_dump(screen_path, &screen_png(cpu));
+        } else {
+            write_dump(screen_path, screen_text(cpu).as_bytes());
+        }
+    }
+    if let Some(registers_path) = flag_value(args, "--dump-registers") {
+        write_dump(
+            registers_path,
+            (registers_json(cpu, stop) + "\n").as_bytes(),
+        );
+    }
+
+    if until_pc.is_some() && stop != Stop::ReachedPc {
+        return 2;
+    }
+    if let Some(movie) = playing.filter(|_| stop != Stop::ReachedPc) {
+        if !movie.matches(cpu) {
+            if cpu.frames == movie.frames {
+                eprintln!("The screen differs from the one the movie ended on");
+            } else {
+                eprintln!(
+                    "The run ended on frame {} but the movie on frame {}",
+                    cpu.frames, movie.frames
+                );
+            }
+            return 3;
+        }
+    }
+    0
+}
+
+/// Disassembles the ROM for `sschip8 disasm` and prints the listing
+fn run_disasm(args: &[String]) -> ! {
+    let Some(path) = args.get(2) else {
+        fail(USAGE);
+    };
+    let rom = read_rom(path).unwrap_or_else(|err| fail(&format!("Could not load {path}: {err}")));
+
+    let syntax = match flag_value(args, "--syntax") {
+        Some(name) => Syntax::from_name(name).unwrap_or_else(|| {
+            fail(&format!(
+                "Unknown syntax '{name}', expected one of: octo, cowgod"
+            ))
+        }),
+        None => Syntax::default(),
+    };
+    let platform = match flag_value(args, "--platform") {
+        Some(name) => parse_platform(name),
+        None => Platform::detect(&rom),
+    };
+
+    let listing =
+        disassemble(&rom, platform).render(syntax, args.iter().any(|arg| arg == "--addresses"));
+    write_dump("-", listing.as_bytes());
+    std::process::exit(0);
+}
+
+/// Assembles a source file for `sschip8 asm` and writes the ROM
+fn run_asm(args: &[String]) -> ! {
+    let Some(path) = args.get(2) else {
+        fail(USAGE);
+    };
+    let platform = flag_value(args, "--platform").map_or(Platform::XoChip, parse_platform);
+
+    let rom = if is_octo(path) {
+        compile_file(Path::new(path)).map(|program| program.rom)
+    } else {
+        assemble_file(Path::new(path), platform)
+    }
+    .unwrap_or_else(|err| fail(&err.to_string()));
+
+    let out = match flag_value(args, "-o") {
+        Some(out) => out.into(),
+        None => Path::new(path).with_extension("ch8"),
+    };
+    if let Err(err) = std::fs::write(&out, &rom) {
+        fail(&format!("Could not write {}: {err}", out.display()));
+    }
+    std::process::exit(0);
+}
+
+/// Looks up the platform passed to `--platform`
+fn parse_platform(name: &str) -> Platform {
+    Platform::from_name(name).unwrap_or_else(|| {
+        fail(&format!(
+            "Unknown platform '{name}', expected one of: chip8, vip, hires, chip8x, schip, xochip"
         ))
-        .unwrap();
-    file.read_to_end(&mut bytes).unwrap();
+    })
+}
+
+/// Returns the value following `--name` on the command line, if the flag was passed
+fn flag_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    let pos = args.iter().position(|arg| arg == name)?;
+
+    Some(args.get(pos + 1).map(String::as_str).unwrap_or_default())
+}
+
+/// Prints `message` and exits with a failure status
+fn fail(message: &str) -> ! {
+    eprintln!("{message}");
+    std::process::exit(1);
+}
+
+fn main() {
+    let mut args: Vec<_> = env::args().collect();
+    if args.get(1).is_some_and(|arg| arg == "run") {
+        args.remove(1);
+    }
+
+    let Some(path) = args.get(1) else {
+        fail(USAGE);
+    };
+
+    match path.as_str() {
+        "disasm" => run_disasm(&args),
+        "asm" => run_asm(&args),
+        _ => {}
+    }
+
+    // Octo source is compiled first, for the platform its instructions need
+    let (rom, compiled_platform) = if is_octo(path) {
+        let program = compile_file(Path::new(path)).unwrap_or_else(|err| fail(&err.to_string()));
+        (program.rom, Some(program.platform))
+    } else {
+        let rom =
+            read_rom(path).unwrap_or_else(|err| fail(&format!("Could not load {path}: {err}")));
+        (rom, None)
+    };
+
+    // `--play <movie>` replays recorded input and runs the ROM the way it was recorded
+    let playing = flag_value(&args, "--play").map(read_movie);
+    let recording = flag_value(&args, "--record");
+    if playing.is_some() && recording.is_some() {
+        fail("--play and --record can't be used together");
+    }
+
+    // `--platform <platform>` selects the machine, which decides the available instructions
+    // and how much memory there is for the ROM
+    let platform = match (&playing, flag_value(&args, "--platform")) {
+        (Some(movie), _) => movie.platform,
+        (None, Some(name)) => parse_platform(name),
+        (None, None) => compiled_platform.unwrap_or_else(|| Platform::detect(&rom)),
+    };
+
+    let mut cpu = CPU::with_platform(platform, &rom)
+        .unwrap_or_else(|err| fail(&format!("Could not load {path}: {err}")));
+
+    // ROMs expect their platform's conventions unless told otherwise
+    cpu.quirks = platform.quirks();
+
+    // `--quirks <preset>` selects the interpreter conventions the ROM expects
+    if let Some(name) = flag_value(&args, "--quirks") {
+        match Quirks::from_name(name) {
+            Some(quirks) => cpu.quirks = quirks,
+            None => fail(&format!(
+                "Unknown quirks preset '{name}', expected one of: vip, chip48, schip, xochip"
+            )),
+        }
+    }
+
+    // Later platforms ran on faster hardware, so the preset also picks the default speed
+    let instructions_per_frame = if cpu.quirks == Quirks::xochip() {
+        XOCHIP_INSTRUCTIONS_PER_FRAME
+    } else if cpu.quirks == Quirks::schip() || cpu.quirks == Quirks::chip48() {
+        SCHIP_INSTRUCTIONS_PER_FRAME
+    } else {
+        VIP_INSTRUCTIONS_PER_FRAME
+    };
+    cpu.frame_budget = FrameBudget::Instructions(instructions_per_frame);
+
+    // The VIP runs every instruction at its real speed
+    if platform == Platform::CosmacVip {
+        cpu.frame_budget = FrameBudget::VipTiming;
+    }
+
+    // `--ipf <n>` overrides the number of instructions per frame
+    if let Some(n) = flag_value(&args, "--ipf") {
+        match n.parse() {
+            Ok(n) if n > 0 => cpu.frame_budget = FrameBudget::Instructions(n),
+            _ => fail(&format!("Invalid instructions per frame '{n}'")),
+        }
+    }
+
+    // `--vip-timing` charges every instruction its COSMAC VIP machine cycles instead
+    if args.iter().any(|arg| arg == "--vip-timing") {
+        cpu.frame_budget = FrameBudget::VipTiming;
+    }
+
+    // `--speed <speed>` runs faster or slower than real time
+    let speed = match flag_value(&args, "--speed") {
+        Some(name) => Speed::from_name(name).unwrap_or_else(|| {
+            fail(&format!(
+                "Unknown speed '{name}', expected one of: 1x, 2x, 0.5x, unthrottled"
+            ))
+        }),
+        None => Speed::Normal,
+    };
+
+    // `--render <mode>` picks how pixels are packed into terminal cells
+    let render_mode = match flag_value(&args, "--render") {
+        Some(name) => RenderMode::from_name(name).unwrap_or_else(|| {
+            fail(&format!(
+                "Unknown render mode '{name}', expected one of: pixel, half, braille"
+            ))
+        }),
+        None => RenderMode::HalfBlock,
+    };
+
+    // `--seed <n>` makes the random numbers the same on every run
+    let seed = match flag_value(&args, "--seed") {
+        Some(seed) => seed
+            .parse()
+            .unwrap_or_else(|_| fail(&format!("Invalid seed '{seed}'"))),
+        None => rand::random(),
+    };
+
+    // `--vip-random` picks the VIP's generator, which the VIP platform uses anyway
+    cpu.random = if platform == Platform::CosmacVip || args.iter().any(|arg| arg == "--vip-random")
+    {
+        Random::vip(seed)
+    } else {
+        Random::seeded(seed)
+    };
+
+    // `--rewind <seconds>` sets how much gameplay is kept for rewinding
+    let rewind_seconds = match flag_value(&args, "--rewind") {
+        Some(seconds) => seconds
+            .parse()
+            .unwrap_or_else(|_| fail(&format!("Invalid rewind length '{seconds}'"))),
+        None => DEFAULT_REWIND_SECONDS,
+    };
+
+    load_rpl_flags(&mut cpu, path);
+
+    if let Some(movie) = &playing {
+        if movie.rom_hash != cpu.rom_hash {
+            eprintln!("Warning: the movie was recorded with a different ROM");
+        }
+        movie.apply(&mut cpu);
+    }
+
+    if args.iter().any(|arg| arg == "--headless") {
+        let status = run_headless(&args, &mut cpu, playing.as_ref(), recording);
+        exit_saving_flags(&cpu, path, status);
+    }
+    if args.iter().any(|arg| arg == "--debug") {
+        let status = run_debugger(&args, &mut cpu, rewind_seconds);
+        exit_saving_flags(&cpu, path, status);
+    }
+
+    // `--record <movie>` starts recording from power on, after everything is set up
+    let mut movie = recording.map(|_| Movie::new(&cpu));
+    let events;
+
+    cpu.display = Box::new(TerminalRenderer::new(render_mode));
+    let mut scheduler = Scheduler::new(speed);
+
+    #[cfg(unix)]
+    let result = match sschip8::terminal::TerminalFrontend::new() {
+        Ok(mut frontend) => {
+            events = connect_keypad(
+                &mut cpu,
+                frontend.keypad(),
+                playing.is_some(),
+                movie.is_some(),
+            );
+
+            sschip8::terminal::run(
+                &mut cpu,
+                &mut frontend,
+                &mut scheduler,
+                &sschip8::state::SaveSlots::new(path),
+                &mut sschip8::rewind::Rewind::with_seconds(rewind_seconds),
+                movie.is_some(),
+            )
+        }
+        Err(err) => {
+            events = None;
+            Err(err)
+        }
+    };
+
+    #[cfg(not(unix))]
+    let result = {
+        #[cfg(all(windows, feature = "windows"))]
+        {
+            events = connect_keypad(
+                &mut cpu,
+                sschip8::windows::WindowsKeypad,
+                playing.is_some(),
+                movie.is_some(),
+            );
+            cpu.audio = Box::new(sschip8::windows::WindowsAudio::default());
+        }
+        #[cfg(not(all(windows, feature = "windows")))]
+        {
+            events = connect_keypad(&mut cpu, NullKeypad, playing.is_some(), movie.is_some());
+        }
+
+        cpu.run_with(&mut scheduler)
+    };
 
-    let mut cpu = lib::cpu::CPU::new_with_memory(&bytes);
+    if let (Some(movie), Some(movie_path), Some(events)) = (&mut movie, recording, events) {
+        movie.finish(&cpu, events.take());
+        if let Err(err) = std::fs::write(movie_path, movie.to_bytes()) {
+            eprintln!("Could not save movie {movie_path}: {err}");
+        }
+    }
 
-    cpu.run();
-}
\ No newline at end of file
+    if let Err(err) = result {
+        eprintln!("Error: {err}");
+        eprintln!("  PC: 0x{:03X}", cpu.pc);
+        if let Ok(opcode) = cpu.fetch() {
+            eprintln!("  opcode: 0x{opcode:04X}");
+        }
+        exit_saving_flags(&cpu, path, 1);
+    }
+    exit_saving_flags(&cpu, path, 0);
+}
@@ -0,0 +1,312 @@
+//! Runs whole test ROMs and compares the final screen with checked-in golden images.
+//!
+//! ROMs are read from `tests/fixtures/roms`. Only `sschip8-smoke.ch8` is checked in, the
+//! community test ROMs have to be downloaded there first and are checked by an ignored
+//! test, run it with `-- --ignored`. Golden images live in `tests/fixtures/golden` as
+//! `<rom>.<target>.txt` in the text format of `--dump-screen`. A missing ROM or golden image
+//! fails the test. `UPDATE_GOLDEN=1` writes the missing golden images and prints them, the
+//! test still fails until they've been checked by hand and the run is repeated.
+//!
+//! `sschip8-quirks.8o` checks every quirk on its own, so each target's quirks are compared
+//! one by one with what its platform is documented to do.
+
+use sschip8::cpu::CPU;
+use sschip8::headless::{parse_input_script, screen_text, HeadlessRun};
+use sschip8::movie::MoviePlayer;
+use sschip8::octo::compile_file;
+use sschip8::quirks::Quirks;
+use sschip8::random::Random;
+use sschip8::scheduler::{
+    FrameBudget, SCHIP_INSTRUCTIONS_PER_FRAME, VIP_INSTRUCTIONS_PER_FRAME,
+    XOCHIP_INSTRUCTIONS_PER_FRAME,
+};
+use sschip8::variant::Platform;
+use std::path::{Path, PathBuf};
+
+/// A platform and quirks preset the ROMs are run on
+struct Target {
+    name: &'static str,
+    platform: Platform,
+    quirks: Quirks,
+    frame_budget: FrameBudget,
+
+    /// The number the Timendus test suite's menus use for the platform
+    menu_choice: u8,
+
+    /// Which of `QUIRKS` the platform has, taken from the platform's documentation rather
+    /// than from `quirks`
+    expected_quirks: [bool; 6],
+}
+
+/// A test ROM and how to run it
+struct Case {
+    rom: &'static str,
+    frames: u64,
+
+    /// Whether to pick the target in the ROM's menu by writing its number to 0x1FF
+    select_platform: bool,
+
+    /// The input script in `tests/fixtures/inputs` to hold keys with
+    input: Option<&'static str>,
+
+    /// The targets to run on, every target if empty
+    targets: &'static [&'static str],
+}
+
+fn targets() -> Vec<Target> {
+    vec![
+        Target {
+            name: "chip8",
+            platform: Platform::Chip8,
+            quirks: Quirks::default(),
+            frame_budget: FrameBudget::Instructions(VIP_INSTRUCTIONS_PER_FRAME),
+            menu_choice: 1,
+            // The conventions this emulator has always used for plain CHIP-8
+            expected_quirks: [false, true, false, true, false, false],
+        },
+        Target {
+            name: "vip",
+            platform: Platform::CosmacVip,
+            quirks: Quirks::vip(),
+            frame_budget: FrameBudget::VipTiming,
+            menu_choice: 1,
+            // What Timendus' quirks test expects of the VIP
+            expected_quirks: [true, true, true, true, true, false],
+        },
+        Target {
+            name: "schip",
+            platform: Platform::SuperChip,
+            quirks: Quirks::schip(),
+            frame_budget: FrameBudget::Instructions(SCHIP_INSTRUCTIONS_PER_FRAME),
+            menu_choice: 2,
+            expected_quirks: [false, false, false, true, false, true],
+        },
+        Target {
+            name: "xochip",
+            platform: Platform::XoChip,
+            quirks: Quirks::xochip(),
+            frame_budget: FrameBudget::Instructions(XOCHIP_INSTRUCTIONS_PER_FRAME),
+            menu_choice: 3,
+            expected_quirks: [false, true, false, false, true, false],
+        },
+    ]
+}
+
+/// The quirks `sschip8-quirks.8o` checks, in the order it stores the results
+const QUIRKS: [&str; 6] = [
+    "logic_resets_vf",
+    "memory_increments_i",
+    "display_wait",
+    "clip_sprites",
+    "shift_uses_vy",
+    "jump_uses_vx",
+];
+
+/// Where `sschip8-quirks.8o` stores the results, 1 for a quirk that's off and 2 for one
+/// that's on
+const QUIRK_RESULTS: usize = 0x202;
+
+/// The ROMs that are checked in
+const CASES: &[Case] = &[Case {
+    rom: "sschip8-smoke.ch8",
+    frames: 30,
+    select_platform: false,
+    input: None,
+    targets: &[],
+}];
+
+/// Timendus' CHIP-8 test suite and `BC_test.ch8`, which aren't checked in
+const COMMUNITY_CASES: &[Case] = &[
+    Case {
+        rom: "3-corax+.ch8",
+        frames: 120,
+        select_platform: false,
+        input: None,
+        targets: &[],
+    },
+    Case {
+        rom: "4-flags.ch8",
+        frames: 240,
+        select_platform: false,
+        input: None,
+        targets: &[],
+    },
+    Case {
+        rom: "5-quirks.ch8",
+        frames: 600,
+        select_platform: true,
+        input: None,
+        targets: &[],
+    },
+    Case {
+        rom: "6-keypad.ch8",
+        frames: 180,
+        select_platform: true,
+        input: Some("6-keypad.input"),
+        targets: &["chip8"],
+    },
+    Case {
+        rom: "BC_test.ch8",
+        frames: 120,
+        select_platform: false,
+        input: None,
+        targets: &["chip8"],
+    },
+];
+
+fn fixture(path: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(path)
+}
+
+/// Runs `case` on `target` and returns the final screen as text
+fn run(case: &Case, target: &Target, rom: &[u8]) -> Result<String, String> {
+    run_cpu(case, target, rom).map(|cpu| screen_text(&cpu))
+}
+
+/// Runs `case` on `target` and returns the machine it ended in
+fn run_cpu(case: &Case, target: &Target, rom: &[u8]) -> Result<CPU, String> {
+    let mut cpu = CPU::with_platform(target.platform, rom).map_err(|err| err.to_string())?;
+    cpu.quirks = target.quirks;
+    cpu.frame_budget = target.frame_budget;
+    cpu.random = Random::seeded(0);
+
+    if case.select_platform {
+        cpu.mem[0x1FF] = target.menu_choice;
+    }
+
+    if let Some(input) = case.input {
+        let script = std::fs::read_to_string(fixture(&format!("inputs/{input}")))
+            .map_err(|err| format!("{input}: {err}"))?;
+        let events = parse_input_script(&script).map_err(|err| format!("{input}: {err}"))?;
+        cpu.keypad = Box::new(MoviePlayer::new(events));
+    }
+
+    let run = HeadlessRun {
+        frames: case.frames,
+        until_pc: None,
+    };
+    run.run(&mut cpu).map_err(|err| err.to_string())?;
+
+    Ok(cpu)
+}
+
+/// Describes the first row that differs between two screens
+fn first_difference(expected: &str, actual: &str) -> String {
+    let mut rows = expected.lines().zip(actual.lines()).enumerate();
+
+    match rows.find(|(_, (expected, actual))| expected != actual) {
+        Some((row, (expected, actual))) => {
+            format!("row {row} differs\n  expected {expected}\n  actual   {actual}")
+        }
+        None => "the screen size differs".to_string(),
+    }
+}
+
+/// Runs every case on its targets and compares the screens with the golden images,
+/// returning the names of the ones that failed
+fn check_golden_images(cases: &[Case]) -> Vec<String> {
+    let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+    let targets = targets();
+    let mut failures = Vec::new();
+
+    for case in cases {
+        let Ok(rom) = std::fs::read(fixture(&format!("roms/{}", case.rom))) else {
+            println!("{:<20} FAIL: not in tests/fixtures/roms", case.rom);
+            failures.push(case.rom.to_string());
+            continue;
+        };
+        let stem = case.rom.trim_end_matches(".ch8");
+
+        for target in &targets {
+            if !case.targets.is_empty() && !case.targets.contains(&target.name) {
+                continue;
+            }
+
+            let name = format!("{stem}.{}", target.name);
+            let golden_path = fixture(&format!("golden/{name}.txt"));
+
+            let result = run(case, target, &rom).and_then(|actual| {
+                let Ok(expected) = std::fs::read_to_string(&golden_path) else {
+                    if !update {
+                        return Err("no golden image, write it with UPDATE_GOLDEN=1".to_string());
+                    }
+                    // A golden image is only as good as the review it got, so writing one
+                    // never makes the test pass
+                    std::fs::write(&golden_path, &actual).map_err(|err| err.to_string())?;
+                    return Err(format!("wrote a new golden image, check it:\n{actual}"));
+                };
+
+                if expected == actual {
+                    Ok(())
+                } else {
+                    Err(first_difference(&expected, &actual))
+                }
+            });
+
+            match result {
+                Ok(()) => println!("{:<20} {:<7} pass", case.rom, target.name),
+                Err(err) => {
+                    println!("{:<20} {:<7} FAIL: {err}", case.rom, target.name);
+                    failures.push(name);
+                }
+            }
+        }
+    }
+
+    failures
+}
+
+#[test]
+fn test_roms_match_golden_images() {
+    let failures = check_golden_images(CASES);
+
+    assert!(failures.is_empty(), "failed: {}", failures.join(", "));
+}
+
+#[test]
+#[ignore = "needs the community test ROMs in tests/fixtures/roms"]
+fn community_roms_match_golden_images() {
+    let failures = check_golden_images(COMMUNITY_CASES);
+
+    assert!(failures.is_empty(), "failed: {}", failures.join(", "));
+}
+
+#[test]
+fn quirks_match_platforms() {
+    let program = compile_file(&fixture("roms/sschip8-quirks.8o")).unwrap();
+    let case = Case {
+        rom: "sschip8-quirks.8o",
+        frames: 120,
+        select_platform: false,
+        input: None,
+        targets: &[],
+    };
+    let mut failures = Vec::new();
+
+    for target in targets() {
+        let cpu = run_cpu(&case, &target, &program.rom).unwrap();
+
+        for (i, (quirk, expected)) in QUIRKS.iter().zip(target.expected_quirks).enumerate() {
+            let actual = match cpu.mem[QUIRK_RESULTS + i] {
+                1 => "off",
+                2 => "on",
+                _ => "not checked",
+            };
+            let expected = if expected { "on" } else { "off" };
+
+            if actual == expected {
+                println!("{:<7} {quirk:<20} pass", target.name);
+            } else {
+                println!(
+                    "{:<7} {quirk:<20} FAIL: expected {expected}, got {actual}",
+                    target.name
+                );
+                failures.push(format!("{}.{quirk}", target.name));
+            }
+        }
+    }
+
+    assert!(failures.is_empty(), "failed: {}", failures.join(", "));
+}
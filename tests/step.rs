@@ -0,0 +1,179 @@
+use sschip8::cpu::{StepOutcome, CPU};
+use sschip8::error::Chip8Error;
+use sschip8::opcode::Instruction;
+use sschip8::platform::Keypad;
+use sschip8::scheduler::{FrameBudget, VIP_INSTRUCTIONS_PER_FRAME};
+use sschip8::variant::Platform;
+
+/// Draws the font sprite for 0 in the top left corner and then loops forever
+const DRAW_ZERO: [u8; 10] = [
+    0x60, 0x00, // 6000 - V0 = 0
+    0xF0, 0x29, // F029 - I = font sprite for V0
+    0xD0, 0x05, // D005 - draw 5 rows at (V0, V0)
+    0x71, 0x01, // 7101 - V1 += 1
+    0x12, 0x06, // 1206 - jump to the previous instruction
+];
+
+#[test]
+fn step_executes_one_instruction() {
+    let mut cpu = CPU::new_with_memory(&DRAW_ZERO).unwrap();
+
+    let outcome = cpu.step().unwrap();
+
+    assert_eq!(outcome, StepOutcome::Executed(Instruction::Ld(0, 0)));
+    assert_eq!(cpu.pc, 0x202);
+}
+
+#[test]
+fn step_draws_sprite() {
+    let mut cpu = CPU::new_with_memory(&DRAW_ZERO).unwrap();
+
+    cpu.run_cycles(3).unwrap();
+
+    // The top row of the 0 sprite is 0xF0
+    assert_eq!(cpu.buf[0..8], [1, 1, 1, 1, 0, 0, 0, 0]);
+    assert_eq!(cpu.buf[64..72], [1, 0, 0, 1, 0, 0, 0, 0]);
+}
+
+#[test]
+fn run_frame_executes_a_frame_of_instructions() {
+    let mut cpu = CPU::new_with_memory(&DRAW_ZERO).unwrap();
+
+    cpu.run_frame().unwrap();
+    cpu.run_frame().unwrap();
+
+    // 3 setup instructions, then the add/jump loop for the rest of the two frames
+    let loop_instructions = 2 * VIP_INSTRUCTIONS_PER_FRAME - 3;
+    assert_eq!(cpu.registers[1] as u32, loop_instructions.div_ceil(2));
+}
+
+#[test]
+fn step_reports_invalid_opcode() {
+    let mut cpu = CPU::new_with_memory(&[0xFF, 0xFF]).unwrap();
+
+    assert!(matches!(
+        cpu.step(),
+        Err(Chip8Error::InvalidOpcode {
+            pc: 0x200,
+            opcode: 0xFFFF
+        })
+    ));
+}
+
+#[test]
+fn step_reports_stack_underflow() {
+    // 00EE - return without a call
+    let mut cpu = CPU::new_with_memory(&[0x00, 0xEE]).unwrap();
+
+    assert!(matches!(cpu.step(), Err(Chip8Error::StackUnderflow)));
+    assert_eq!(cpu.pc, 0x200);
+}
+
+#[test]
+fn step_reports_stack_overflow() {
+    // 2200 - a subroutine that calls itself forever
+    let mut cpu = CPU::new_with_memory(&[0x22, 0x00]).unwrap();
+
+    assert!(matches!(cpu.run_cycles(17), Err(Chip8Error::StackOverflow)));
+}
+
+#[test]
+fn step_reports_memory_out_of_bounds() {
+    // AFFF - I = 0xFFF, D015 - draw 5 rows from I
+    let mut cpu = CPU::new_with_memory(&[0xAF, 0xFF, 0xD0, 0x15]).unwrap();
+
+    assert!(matches!(
+        cpu.run_cycles(2),
+        Err(Chip8Error::MemoryOutOfBounds { addr: 0xFFF })
+    ));
+}
+
+#[test]
+fn new_with_memory_rejects_large_roms() {
+    let rom = vec![0; 4096];
+
+    assert!(matches!(
+        CPU::new_with_memory(&rom),
+        Err(Chip8Error::RomTooLarge { len: 4096 })
+    ));
+}
+
+#[test]
+fn delay_timer_counts_down_once_per_frame() {
+    let rom = [
+        0x60, 0x0A, // 600A - V0 = 10
+        0xF0, 0x15, // F015 - delay timer = V0
+        0x12, 0x04, // 1204 - loop forever
+    ];
+    let mut cpu = CPU::new_with_memory(&rom).unwrap();
+
+    for _ in 0..4 {
+        cpu.run_frame().unwrap();
+    }
+
+    assert_eq!(cpu.timers.delay, 6);
+    assert_eq!(cpu.frames, 4);
+    assert_eq!(cpu.cycles, 4 * VIP_INSTRUCTIONS_PER_FRAME as u64);
+}
+
+/// A keypad that holds down key 7 for the first `held` queries
+struct ScriptedKeypad {
+    held: u32,
+}
+
+impl Keypad for ScriptedKeypad {
+    fn is_key_pressed(&mut self, key: u8) -> bool {
+        if key != 7 || self.held == 0 {
+            return false;
+        }
+        self.held -= 1;
+        true
+    }
+}
+
+#[test]
+fn wait_for_key_stores_key_after_release() {
+    // F30A - wait for a key and store it in V3
+    let mut cpu = CPU::new_with_memory(&[0xF3, 0x0A]).unwrap();
+    cpu.keypad = Box::new(ScriptedKeypad { held: 2 });
+
+    // The first step sees the key go down, the second one sees it still held
+    cpu.run_cycles(2).unwrap();
+    assert_eq!(cpu.pc, 0x200);
+
+    cpu.step().unwrap();
+    assert_eq!(cpu.pc, 0x202);
+    assert_eq!(cpu.registers[3], 7);
+}
+
+#[test]
+fn vip_platform_draws_one_sprite_per_frame() {
+    let mut cpu = CPU::with_platform(Platform::CosmacVip, &DRAW_ZERO).unwrap();
+    cpu.quirks = Platform::CosmacVip.quirks();
+    cpu.frame_budget = FrameBudget::VipTiming;
+
+    cpu.run_frame().unwrap();
+
+    // The frame ends right after the sprite is drawn, waiting for the vertical blank
+    assert_eq!(cpu.pc, 0x206);
+    assert_eq!(cpu.buf[0..8], [1, 1, 1, 1, 0, 0, 0, 0]);
+}
+
+#[test]
+fn vip_timing_leaves_room_for_several_sprites_per_frame() {
+    // D015 - draw 5 rows, 1200 - loop forever
+    let rom = [0xD0, 0x15, 0x12, 0x00];
+    let draws_in_a_frame = |display_wait| {
+        let mut cpu = CPU::with_platform(Platform::CosmacVip, &rom).unwrap();
+        cpu.quirks = Platform::CosmacVip.quirks();
+        cpu.quirks.display_wait = display_wait;
+        cpu.frame_budget = FrameBudget::VipTiming;
+
+        cpu.run_frame().unwrap();
+        cpu.cycles.div_ceil(2)
+    };
+
+    // Only waiting for the vertical blank keeps the VIP to one sprite per frame
+    assert_eq!(draws_in_a_frame(true), 1);
+    assert!(draws_in_a_frame(false) > 1);
+}